@@ -2,7 +2,6 @@ use log::LevelFilter;
 use mdf::{PagePointer, PageProvider, PageType, DB};
 use mtf::mdf::MTFPageProvider;
 use mtf::MTFParser;
-use std::collections::HashMap;
 
 fn main() {
     env_logger::init();
@@ -23,24 +22,28 @@ fn main() {
     let stream = db_stream.unwrap();
     let page_provider = MTFPageProvider::from_stream(stream);
 
-    let db = DB::new(page_provider);
+    let db = DB::new(page_provider).unwrap();
 
     log::set_max_level(old_level);
 
-    let mut p_min_info = HashMap::new();
+    let mut histogram: Vec<_> = db.page_type_histogram().into_iter().collect();
+    histogram.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (ty, count) in histogram {
+        println!("{:?}: {}", ty, count);
+    }
 
     for tbl in db.tables() {
         if !tbl.partition_pointer.is_empty() {
             let first_page = tbl.partition_pointer[0];
             if let Some(first_page) = tbl.page_provider.get(first_page) {
                 println!("########## {}\n{:?}", tbl.name, first_page.header);
-                p_min_info.insert(first_page.header.p_min_len, tbl.name);
             } else {
                 println!("######### {}\nNOTHING!!!", tbl.name);
             }
         }
     }
 
+    let owners = db.page_owner_names();
     for j in db.page_provider.file_ids() {
         for i in 0..db.page_provider.num_pages(j) {
             if let Some(page) = db.page_provider.get(PagePointer {
@@ -50,7 +53,7 @@ fn main() {
                 if matches!(page.header.ty, PageType::Data | PageType::Index) {
                     println!(
                         "######### {:?}\n{:?}",
-                        p_min_info.get(&page.header.p_min_len),
+                        owners.get(&(page.header.object_id, page.header.index_id)),
                         page.header
                     );
                 }
@@ -1,11 +1,11 @@
-use failure::Error;
 use log::LevelFilter;
-use mdf::{Row, SqlValue, DB};
+use mdf::{Row, DB};
 use mtf::{mdf::MTFPageProvider, MTFParser};
 use std::collections::HashMap;
+use std::error::Error;
 use std::path::Path;
 
-fn main() -> Result<(), Error> {
+fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
     let old_level = log::max_level();
@@ -24,7 +24,7 @@ fn main() -> Result<(), Error> {
     let stream = db_stream.unwrap();
     let page_provider = MTFPageProvider::from_stream(stream);
 
-    let db = DB::new(page_provider);
+    let db = DB::new(page_provider)?;
 
     log::set_max_level(old_level);
 
@@ -44,21 +44,17 @@ fn main() -> Result<(), Error> {
 
                 for row in tbl.scan_db() {
                     let Row { mut values, .. } = row;
-                    let _id = values[0].take().unwrap().unwrap_unique_identifier();
-                    let site_id = values[1].take().unwrap().unwrap_unique_identifier();
-                    let web_id = values[4].take().unwrap().unwrap_unique_identifier();
-
-                    // something is extremely broken, for some reason there is a (fixed?) 0x01 byte
-                    // between the id and the site_id, so we need to grab our most significant byte
-                    // (because little endian) from the least significant byte of the next value
-                    let actual_id = (site_id >> 8) | (web_id << (8 * 15));
+                    // the fixed length columns are read at their offsets from `sysrscols`,
+                    // reading them in column order put the id one byte off
+                    let actual_id = values[0].take().unwrap().unwrap_unique_identifier();
 
                     // Something is broken, the first var length column is zero long
                     let dir_name = values[3].take().unwrap().unwrap_nvar_char_in_row();
-                    let leaf_name = match values[18].take() {
-                        Some(v) => v.unwrap_nvar_char_in_row(),
-                        None => "empty_leaf_name".to_owned(),
-                    };
+                    let leaf_name = values[18]
+                        .as_ref()
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("empty_leaf_name")
+                        .to_owned();
 
                     println!("{}, {}, {}", actual_id, dir_name, leaf_name);
                     if all_docs_index.contains_key(&actual_id) {
@@ -97,11 +93,11 @@ fn main() -> Result<(), Error> {
                 let _site_id = values[1].take().unwrap().unwrap_unique_identifier();
                 let parent_id = values[3].take().unwrap().unwrap_unique_identifier();
                 let _size = values[4].take().unwrap().unwrap_int();
-                let ptr = values[6].take();
+                let content = values[6].take();
                 let doc_info = all_docs_index.get(&parent_id);
 
-                if let Some(SqlValue::Image(Some(ptr))) = ptr {
-                    let d = ptr.read(&db.page_provider);
+                if let Some(content) = content {
+                    let d = content.read_lob(&db.page_provider);
                     let file_name = if let Some((dir_name, leaf_name)) = doc_info {
                         format!("{}/{}", dir_name, leaf_name)
                     } else {
@@ -0,0 +1,308 @@
+use crate::{value_for_export, PageProvider, Schema, SqlType, SqlValue, Table};
+use arrow::array::{
+    ArrayRef, BinaryBuilder, BooleanBuilder, Date32Builder, Decimal128Builder,
+    FixedSizeBinaryBuilder, Float32Builder, Float64Builder, Int16Builder, Int32Builder,
+    Int64Builder, StringBuilder, Time64MicrosecondBuilder, TimestampMicrosecondBuilder,
+    UInt8Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema as ArrowSchema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use log::warn;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+// days from 0001-01-01 to 1970-01-01
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+// `datetimeoffset` values are stored as UTC, so they are exported as UTC timestamps
+const UTC: &str = "+00:00";
+
+impl SqlType {
+    // The arrow type used for values of this type by `Table::to_arrow`.
+    // Timestamps and times have microsecond resolution, so the 7th fractional digit of
+    // `datetime2(7)` and `time(7)` values is truncated. `sql_variant` values can have
+    // any base type and are exported as their string representation
+    pub fn arrow_data_type(&self) -> DataType {
+        match self {
+            // `tinyint` is unsigned (0 to 255)
+            Self::TinyInt => DataType::UInt8,
+            Self::SmallInt => DataType::Int16,
+            Self::Int => DataType::Int32,
+            Self::BigInt => DataType::Int64,
+            Self::Bit => DataType::Boolean,
+            Self::Real => DataType::Float32,
+            Self::Float => DataType::Float64,
+            Self::Decimal { precision, scale } => DataType::Decimal128(*precision, *scale as i8),
+            Self::Money => DataType::Decimal128(19, 4),
+            Self::SmallMoney => DataType::Decimal128(10, 4),
+            Self::Char(..)
+            | Self::NChar(_)
+            | Self::VarChar(..)
+            | Self::NVarChar(_)
+            | Self::SysName
+            | Self::NText
+            | Self::SqlVariant => DataType::Utf8,
+            Self::Binary(_) | Self::VarBinary(_) | Self::Image => DataType::Binary,
+            Self::UniqueIdentifier => DataType::FixedSizeBinary(16),
+            Self::Date => DataType::Date32,
+            Self::Time(_) => DataType::Time64(TimeUnit::Microsecond),
+            Self::DateTime | Self::SmallDateTime | Self::DateTime2(_) => {
+                DataType::Timestamp(TimeUnit::Microsecond, None)
+            }
+            Self::DateTimeOffset(_) => DataType::Timestamp(TimeUnit::Microsecond, Some(UTC.into())),
+        }
+    }
+}
+
+impl Schema {
+    // Values that can't be read (like LOBs with a broken page chain) are exported as null,
+    // so every field is nullable
+    pub fn to_arrow_schema(&self) -> ArrowSchema {
+        ArrowSchema::new(
+            self.columns
+                .iter()
+                .map(|col| Field::new(&col.name, col.data_type.arrow_data_type(), true))
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+impl<'a, T: PageProvider> Table<'a, T> {
+    // Reads all rows into a single record batch, see `SqlType::arrow_data_type` for the
+    // column types. Out of row values are read from their LOB / row-overflow pages
+    pub fn to_arrow(&self) -> Result<RecordBatch, ArrowError> {
+        let columns = &self.schema.columns;
+        let mut builders = columns
+            .iter()
+            .map(|col| ColumnBuilder::new(&col.data_type))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.for_each_row(|values| {
+            for ((builder, col), value) in builders.iter_mut().zip(columns).zip(values) {
+                let value = value.as_ref();
+                if !builder.append(&col.data_type, value, self.page_provider) {
+                    warn!(
+                        "could not export {:?} of column {} of {}, using null",
+                        value, col.name, self.name
+                    );
+                }
+            }
+        });
+
+        RecordBatch::try_new(
+            Arc::new(self.schema.to_arrow_schema()),
+            builders.iter_mut().map(ColumnBuilder::finish).collect(),
+        )
+    }
+}
+
+enum ColumnBuilder {
+    UInt8(UInt8Builder),
+    Int16(Int16Builder),
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    Boolean(BooleanBuilder),
+    Float32(Float32Builder),
+    Float64(Float64Builder),
+    Decimal128(Decimal128Builder, u8),
+    Utf8(StringBuilder),
+    Binary(BinaryBuilder),
+    FixedSizeBinary(FixedSizeBinaryBuilder),
+    Date32(Date32Builder),
+    Time64(Time64MicrosecondBuilder),
+    Timestamp(TimestampMicrosecondBuilder),
+}
+
+impl ColumnBuilder {
+    // The builder for the `arrow_data_type` of `data_type`
+    fn new(data_type: &SqlType) -> Result<Self, ArrowError> {
+        let decimal = |precision, scale| -> Result<Self, ArrowError> {
+            Ok(Self::Decimal128(
+                Decimal128Builder::new().with_precision_and_scale(precision, scale as i8)?,
+                scale,
+            ))
+        };
+        Ok(match data_type {
+            SqlType::TinyInt => Self::UInt8(UInt8Builder::new()),
+            SqlType::SmallInt => Self::Int16(Int16Builder::new()),
+            SqlType::Int => Self::Int32(Int32Builder::new()),
+            SqlType::BigInt => Self::Int64(Int64Builder::new()),
+            SqlType::Bit => Self::Boolean(BooleanBuilder::new()),
+            SqlType::Real => Self::Float32(Float32Builder::new()),
+            SqlType::Float => Self::Float64(Float64Builder::new()),
+            SqlType::Decimal { precision, scale } => decimal(*precision, *scale)?,
+            SqlType::Money => decimal(19, 4)?,
+            SqlType::SmallMoney => decimal(10, 4)?,
+            SqlType::Char(..)
+            | SqlType::NChar(_)
+            | SqlType::VarChar(..)
+            | SqlType::NVarChar(_)
+            | SqlType::SysName
+            | SqlType::NText
+            | SqlType::SqlVariant => Self::Utf8(StringBuilder::new()),
+            SqlType::Binary(_) | SqlType::VarBinary(_) | SqlType::Image => {
+                Self::Binary(BinaryBuilder::new())
+            }
+            SqlType::UniqueIdentifier => Self::FixedSizeBinary(FixedSizeBinaryBuilder::new(16)),
+            SqlType::Date => Self::Date32(Date32Builder::new()),
+            SqlType::Time(_) => Self::Time64(Time64MicrosecondBuilder::new()),
+            SqlType::DateTime | SqlType::SmallDateTime | SqlType::DateTime2(_) => {
+                Self::Timestamp(TimestampMicrosecondBuilder::new())
+            }
+            SqlType::DateTimeOffset(_) => {
+                Self::Timestamp(TimestampMicrosecondBuilder::new().with_timezone(UTC))
+            }
+        })
+    }
+
+    // Appends `value`, or null if it is null or can't be converted.
+    // Returns false if a non null value had to be replaced by null
+    fn append<T: PageProvider>(
+        &mut self,
+        data_type: &SqlType,
+        value: Option<&SqlValue>,
+        page_provider: &T,
+    ) -> bool {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                self.append_null();
+                return true;
+            }
+        };
+
+        // appends the converted value, evaluating to whether it was not null
+        macro_rules! append {
+            ($builder:expr, $value:expr) => {{
+                let value = $value;
+                let converted = value.is_some();
+                $builder.append_option(value);
+                converted
+            }};
+        }
+
+        match self {
+            Self::UInt8(b) => append!(
+                b,
+                match value {
+                    // `tinyint` values are parsed as an i8
+                    SqlValue::TinyInt(v) => Some(*v as u8),
+                    _ => value.as_i64().and_then(|v| u8::try_from(v).ok()),
+                }
+            ),
+            Self::Int16(b) => append!(b, value.as_i16()),
+            Self::Int32(b) => append!(b, value.as_i32()),
+            Self::Int64(b) => append!(b, value.as_i64()),
+            Self::Boolean(b) => append!(b, value.as_bool()),
+            Self::Float32(b) => append!(b, value.as_f64().map(|v| v as f32)),
+            Self::Float64(b) => append!(b, value.as_f64()),
+            Self::Decimal128(b, scale) => append!(
+                b,
+                value
+                    .as_decimal()
+                    .and_then(|(v, s)| rescale_decimal(v, s, *scale))
+            ),
+            Self::Utf8(b) => append!(
+                b,
+                match data_type {
+                    SqlType::SqlVariant => Some(value_for_export(value)),
+                    // out of row `varchar` values are not decoded while parsing
+                    SqlType::VarChar(_, code_page) if value.as_str().is_none() => value
+                        .read_lob(page_provider)
+                        .map(|data| code_page.decode(&data.to_vec())),
+                    _ => value.read_string(page_provider),
+                }
+            ),
+            Self::Binary(b) => append!(b, value.read_bytes(page_provider)),
+            Self::FixedSizeBinary(b) => match value.as_uuid() {
+                Some(uuid) => b.append_value(uuid.as_bytes()).is_ok(),
+                None => {
+                    b.append_null();
+                    false
+                }
+            },
+            Self::Date32(b) => append!(
+                b,
+                value
+                    .as_date()
+                    .map(|date| date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE)
+            ),
+            Self::Time64(b) => append!(
+                b,
+                value.as_time().map(|time| {
+                    time.num_seconds_from_midnight() as i64 * 1_000_000
+                        + time.nanosecond() as i64 / 1_000
+                })
+            ),
+            Self::Timestamp(b) => append!(
+                b,
+                value
+                    .as_datetime_offset()
+                    .map(|v| v.naive_utc())
+                    .or_else(|| value.as_datetime())
+                    .and_then(unix_micros)
+            ),
+        }
+    }
+
+    fn append_null(&mut self) {
+        match self {
+            Self::UInt8(b) => b.append_null(),
+            Self::Int16(b) => b.append_null(),
+            Self::Int32(b) => b.append_null(),
+            Self::Int64(b) => b.append_null(),
+            Self::Boolean(b) => b.append_null(),
+            Self::Float32(b) => b.append_null(),
+            Self::Float64(b) => b.append_null(),
+            Self::Decimal128(b, _) => b.append_null(),
+            Self::Utf8(b) => b.append_null(),
+            Self::Binary(b) => b.append_null(),
+            Self::FixedSizeBinary(b) => b.append_null(),
+            Self::Date32(b) => b.append_null(),
+            Self::Time64(b) => b.append_null(),
+            Self::Timestamp(b) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::UInt8(b) => Arc::new(b.finish()),
+            Self::Int16(b) => Arc::new(b.finish()),
+            Self::Int32(b) => Arc::new(b.finish()),
+            Self::Int64(b) => Arc::new(b.finish()),
+            Self::Boolean(b) => Arc::new(b.finish()),
+            Self::Float32(b) => Arc::new(b.finish()),
+            Self::Float64(b) => Arc::new(b.finish()),
+            Self::Decimal128(b, _) => Arc::new(b.finish()),
+            Self::Utf8(b) => Arc::new(b.finish()),
+            Self::Binary(b) => Arc::new(b.finish()),
+            Self::FixedSizeBinary(b) => Arc::new(b.finish()),
+            Self::Date32(b) => Arc::new(b.finish()),
+            Self::Time64(b) => Arc::new(b.finish()),
+            Self::Timestamp(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+// `value` with `from` fractional digits as an unscaled value with `to` fractional digits,
+// `None` if digits would be lost or it overflows
+fn rescale_decimal(value: i128, from: u8, to: u8) -> Option<i128> {
+    if from <= to {
+        value.checked_mul(10i128.checked_pow((to - from) as u32)?)
+    } else {
+        let factor = 10i128.checked_pow((from - to) as u32)?;
+        if value % factor == 0 {
+            Some(value / factor)
+        } else {
+            None
+        }
+    }
+}
+
+fn unix_micros(value: NaiveDateTime) -> Option<i64> {
+    let epoch =
+        NaiveDate::from_num_days_from_ce_opt(UNIX_EPOCH_DAYS_FROM_CE)?.and_hms_opt(0, 0, 0)?;
+    (value - epoch).num_microseconds()
+}
@@ -0,0 +1,109 @@
+use crate::providers::parse_page;
+use crate::{PagePointer, PageProvider, RawPage, PAGE_SIZE};
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+
+// Like `PageProvider`, but for storage where fetching a page is an async operation
+// (for example a `.mdf` file in object storage). Pages are returned as owned data,
+// use `BlockingProvider` to parse them with the sync API
+pub trait AsyncPageProvider {
+    fn file_ids(&self) -> Vec<u16>;
+
+    fn num_pages(&self, file_id: u16) -> u32;
+
+    // The raw data of the page, `None` if it does not exist or could not be fetched
+    fn get(&self, ptr: PagePointer) -> impl Future<Output = Option<Vec<u8>>>;
+
+    // see `PageProvider::page_size`, `BlockingProvider` passes this on
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    // Fetches multiple pages at once, providers that can batch (or parallelize) their requests
+    // should override this
+    fn get_many(&self, ptrs: &[PagePointer]) -> impl Future<Output = Vec<Option<Vec<u8>>>> {
+        async move {
+            let mut pages = Vec::with_capacity(ptrs.len());
+            for ptr in ptrs {
+                pages.push(self.get(*ptr).await);
+            }
+            pages
+        }
+    }
+}
+
+// Drives an `AsyncPageProvider` from the sync `PageProvider` API by blocking the current
+// thread on every fetch, so `DB` and `Table` can be used with it unchanged.
+// Pages are fetched again on every access, wrap it in a `CachingPageProvider` to keep
+// recently used pages in memory
+pub struct BlockingProvider<A> {
+    inner: A,
+}
+
+impl<A: AsyncPageProvider> BlockingProvider<A> {
+    pub fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn parse(&self, ptr: PagePointer, data: Vec<u8>) -> Option<RawPage<Self>> {
+        parse_page(ptr, Arc::<[u8]>::from(data), self)
+    }
+}
+
+impl<A: AsyncPageProvider> PageProvider for BlockingProvider<A> {
+    fn file_ids(&self) -> Vec<u16> {
+        self.inner.file_ids()
+    }
+
+    fn num_pages(&self, file_id: u16) -> u32 {
+        self.inner.num_pages(file_id)
+    }
+
+    fn page_size(&self) -> usize {
+        self.inner.page_size()
+    }
+
+    fn get(&self, ptr: PagePointer) -> Option<RawPage<Self>> {
+        let data = block_on(self.inner.get(ptr))?;
+        self.parse(ptr, data)
+    }
+
+    // all pages are requested using a single `get_many`
+    fn get_many(&self, ptrs: &[PagePointer]) -> Vec<Option<RawPage<Self>>> {
+        block_on(self.inner.get_many(ptrs))
+            .into_iter()
+            .zip(ptrs)
+            .map(|(data, ptr)| self.parse(*ptr, data?))
+            .collect()
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Minimal executor that polls `future` on the current thread, parking it while the
+// future is pending. Async providers that need a runtime (like tokio) have to enter
+// it themselves, for example by wrapping their futures with `Handle::block_on`
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
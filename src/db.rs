@@ -1,87 +1,448 @@
-use crate::pages::BootPage;
-use crate::raw_page::{PagePointer, PageProvider};
+use crate::pages::{BootPage, IamPage, PfsPage, PfsStatus, ServerVersion};
+use crate::raw_page::{PageHeader, PagePointer, PageProvider, PageType};
+use crate::util::parse_utf16_string;
 use crate::{
-    AllocUnitType, SchType, Schema, SysAllocUnit, SysColPar, SysRowSet, SysRsCol, SysScalarType,
-    SysSchObj, SysSingleObjRef, Table, SYS_COL_PARS_IDMAJOR, SYS_ROW_SET_AUID,
-    SYS_SCALAR_TYPES_IDMAJOR, SYS_SCH_OBJS_IDMAJOR, SYS_SINGLE_OBJECT_REFS_IDMAJOR,
+    AllocUnitType, ExportFormat, FilePageProvider, IdxStatStatus, IsColStatus, LobDataBlocks,
+    LobEntry, LobPointer, MdfError, Record, SchType, Schema, SysAllocUnit, SysColPar, SysIdxStat,
+    SysIsCol, SysMultiObjRef, SysObjValue, SysRowSet, SysRsCol, SysScalarType, SysSchObj,
+    SysSingleObjRef, Table, SYS_ALLOC_UNITS_AUID, SYS_COL_PARS_IDMAJOR, SYS_IDX_STATS_IDMAJOR,
+    SYS_IS_COLS_IDMAJOR, SYS_MULTI_OBJECT_REFS_IDMAJOR, SYS_OBJ_VALUES_IDMAJOR, SYS_ROW_SET_AUID,
+    SYS_RS_COLS_IDMAJOR, SYS_SCALAR_TYPES_IDMAJOR, SYS_SCH_OBJS_IDMAJOR,
+    SYS_SINGLE_OBJECT_REFS_IDMAJOR,
 };
-use log::trace;
+use log::{error, info, trace};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 
 #[derive(Debug)]
 pub struct DB<T> {
     pub page_provider: T,
-    boot_page: BootPage,
+    boot_page: Result<BootPage, MdfError>,
     pub system_tables: SystemTables,
 }
 
+impl DB<FilePageProvider<File>> {
+    // Opens a detached database consisting of only a `.mdf` file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, MdfError> {
+        Self::new(FilePageProvider::open(path)?)
+    }
+
+    // Opens a database split across multiple files, given as (file_id, path) pairs
+    pub fn open_files<P: AsRef<Path>>(
+        paths: impl IntoIterator<Item = (u16, P)>,
+    ) -> Result<Self, MdfError> {
+        Self::new(FilePageProvider::open_files(paths)?)
+    }
+}
+
 impl<T: PageProvider> DB<T> {
-    pub fn new(page_provider: T) -> Self {
-        // The location of the boot page is always the same
-        let boot_page = BootPage::parse(
-            page_provider
-                .get(PagePointer {
-                    file_id: 1,
-                    page_id: 9,
-                })
-                .unwrap(),
-        );
+    pub fn new(page_provider: T) -> Result<Self, MdfError> {
+        let boot_page = Self::read_boot_page(&page_provider)?;
+        let system_tables = SystemTables::parse(
+            &page_provider,
+            boot_page.first_sys_indices,
+            Some(boot_page.server_version()),
+        )?;
 
-        let system_tables = SystemTables::parse(&page_provider, &boot_page);
+        Ok(Self {
+            page_provider,
+            boot_page: Ok(boot_page),
+            system_tables,
+        })
+    }
+
+    // Like `new`, but if the boot page can't be read, the first page of `sysallocunits`
+    // (which is only referenced by the boot page) is searched for by scanning the whole
+    // database. Why the boot page could not be read is available using `boot_page`
+    pub fn new_degraded(page_provider: T) -> Result<Self, MdfError> {
+        let boot_page = Self::read_boot_page(&page_provider);
+        let first_sys_indices = match &boot_page {
+            Ok(boot_page) => boot_page.first_sys_indices,
+            Err(e) => {
+                error!(
+                    "could not read the boot page, scanning for sysallocunits: {}",
+                    e
+                );
+                Self::find_sys_alloc_units(&page_provider)?
+            }
+        };
+        let server_version = boot_page.as_ref().ok().map(BootPage::server_version);
+        let system_tables = SystemTables::parse(&page_provider, first_sys_indices, server_version)?;
 
-        Self {
+        Ok(Self {
             page_provider,
             boot_page,
             system_tables,
+        })
+    }
+
+    fn read_boot_page(page_provider: &T) -> Result<BootPage, MdfError> {
+        // The location of the boot page is always the same
+        let ptr = PagePointer {
+            file_id: 1,
+            page_id: 9,
+        };
+        let page = page_provider
+            .get(ptr)
+            .ok_or_else(|| MdfError::Corrupt(format!("could not read the boot page {:?}", ptr)))?;
+        BootPage::parse(page)
+    }
+
+    // The first page of the `sysallocunits` page chain
+    fn find_sys_alloc_units(page_provider: &T) -> Result<PagePointer, MdfError> {
+        let (object_id, index_id) = SysAllocUnit::owner_ids_of(SYS_ALLOC_UNITS_AUID);
+        page_provider
+            .extents()
+            .into_iter()
+            .flat_map(|extent| page_provider.get_extent(extent))
+            .flatten()
+            .find(|page| {
+                page.header.ty == PageType::Data
+                    && page.header.belongs_to(object_id, index_id)
+                    && page.header.prev_page_ptr().is_none()
+            })
+            .map(|page| page.header.ptr)
+            .ok_or_else(|| MdfError::Corrupt("could not find sysallocunits".to_string()))
+    }
+
+    // The boot page, or why it could not be read if this `DB` was created using `new_degraded`
+    pub fn boot_page(&self) -> Result<&BootPage, &MdfError> {
+        self.boot_page.as_ref()
+    }
+
+    // `None` if the boot page could not be read
+    pub fn server_version(&self) -> Option<ServerVersion> {
+        Some(self.boot_page().ok()?.server_version())
+    }
+
+    // The current timestamp of the database, to correlate with the timestamps of the
+    // row versions (see `VersioningTag`), `None` without a boot page
+    pub fn max_db_timestamp(&self) -> Option<u64> {
+        Some(self.boot_page().ok()?.max_db_timestamp)
+    }
+
+    // The PFS status of the page, `None` if its PFS page can't be read
+    pub fn pfs_status(&self, ptr: PagePointer) -> Option<PfsStatus> {
+        let pfs_page = self.page_provider.get(ptr.pfs_page())?;
+        PfsPage::parse(&pfs_page)
+            .map_err(|e| error!("could not parse PFS page for {:?}: {}", ptr, e))
+            .ok()?
+            .status(ptr)
+    }
+
+    // Lets scanners skip unallocated pages without having to parse them
+    pub fn is_page_allocated(&self, ptr: PagePointer) -> Option<bool> {
+        self.pfs_status(ptr).map(|status| status.is_allocated())
+    }
+
+    // All the pages of the in-row data of the partition, as tracked by its IAM chain
+    pub fn partition_pages(&self, partition: &SysRowSet) -> Vec<PagePointer> {
+        self.system_tables
+            .allocation_unit_for_partition(partition)
+            .and_then(|au| au.pg_firstiam)
+            .map(|first_iam| {
+                IamPage::chain(&self.page_provider, first_iam)
+                    .flat_map(|iam| iam.pages().collect::<Vec<_>>())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn foreign_keys(&self) -> Vec<ForeignKey> {
+        self.system_tables.foreign_keys()
+    }
+
+    // `SysSchObj::id` -> name of every object (tables, views, procedures, ...)
+    pub fn object_names(&self) -> HashMap<i32, String> {
+        self.system_tables
+            .sch_objs
+            .iter()
+            .map(|obj| (obj.id, obj.name.clone()))
+            .collect()
+    }
+
+    // (object_id, index_id) as found in page headers -> name of the object the page belongs
+    // to, including the pages of its indexes and LOB data. The ids in the page header are
+    // derived from the allocation unit, so they can't be looked up in `object_names`
+    pub fn page_owner_names(&self) -> HashMap<(u32, u16), String> {
+        let object_names = self.object_names();
+        self.system_tables
+            .alloc_units
+            .iter()
+            .filter_map(|au| {
+                let row_set = self
+                    .system_tables
+                    .row_sets
+                    .iter()
+                    .find(|row_set| row_set.row_set_id == au.owner_id)?;
+                let name = object_names.get(&row_set.id_major)?;
+                Some((au.page_owner_ids(), name.clone()))
+            })
+            .collect()
+    }
+
+    // Number of pages of each type across all files, read extent by extent.
+    // Pages the provider does not return (missing or never written) are not counted
+    pub fn page_type_histogram(&self) -> HashMap<PageType, u64> {
+        let mut histogram = HashMap::new();
+        for extent in self.page_provider.extents() {
+            for page in self.page_provider.get_extent(extent).into_iter().flatten() {
+                *histogram.entry(page.header.ty).or_insert(0) += 1;
+            }
+        }
+        histogram
+    }
+
+    // The T-SQL source of a view, procedure, function, trigger, default or check constraint
+    pub fn object_definition(&self, id: i32) -> Option<String> {
+        self.obj_value_text(id, SysObjValue::DEFINITION_CLASS, 0)
+    }
+
+    // The `image_val` of all `sysobjvalues` rows of the object with the given class and
+    // sub object id, concatenated in the order of their `val_num` and decoded as UTF-16
+    pub fn obj_value_text(&self, id: i32, val_class: i8, sub_obj_id: i32) -> Option<String> {
+        let mut text = vec![];
+        for value in self
+            .system_tables
+            .obj_values_for(id)
+            .filter(|value| value.val_class == val_class && value.sub_obj_id == sub_obj_id)
+        {
+            text.extend(value.read_image_val(&self.page_provider)?);
         }
+        if text.is_empty() {
+            None
+        } else {
+            Some(parse_utf16_string(&text))
+        }
+    }
+
+    // Finds a LOB by scanning all LOB pages for its root. Useful if the pointer to the LOB
+    // is damaged, but the LOB itself is intact
+    pub fn read_lob_by_id(&self, blob_id: u64) -> Option<LobDataBlocks> {
+        let root = self
+            .page_provider
+            .extents()
+            .into_iter()
+            .flat_map(|extent| self.page_provider.get_extent(extent))
+            .flatten()
+            .filter(|page| matches!(page.header.ty, PageType::TextMix | PageType::TextTree))
+            .flat_map(|page| page.local_records_with_pointers().collect::<Vec<_>>())
+            .find_map(|(ptr, record)| {
+                LobEntry::parse(record)
+                    .filter(|entry| entry.is_root() && entry.blob_id() == blob_id)
+                    .map(|_| ptr)
+            })?;
+        LobPointer::from_root(root).read(&self.page_provider)
     }
+
+    // Tables whose schema can't be read are logged and treated as missing,
+    // use `table_for` to get the error
     pub fn table(&self, name: &str) -> Option<Table<T>> {
-        let tbl = self.system_tables.tables().find(|tbl| tbl.name == name);
+        self.system_tables
+            .tables()
+            .find(|tbl| tbl.name == name)
+            .and_then(|tbl| self.logged_table_for(tbl))
+    }
 
-        tbl.map(|tbl| Table {
-            name: tbl.name.clone(),
-            page_provider: &self.page_provider,
-            schema: Schema::from_col_par(self.system_tables.columns_for_table(tbl).map(|col| {
+    pub fn table_by_id(&self, id: i32) -> Option<Table<T>> {
+        self.system_tables
+            .tables()
+            .find(|tbl| tbl.id == id)
+            .and_then(|tbl| self.logged_table_for(tbl))
+    }
+
+    // The table a page belongs to. The `object_id` in the page header is derived from the
+    // allocation unit, so it can't be used with `table_by_id` directly
+    pub fn table_for_page(&self, header: &PageHeader) -> Option<Table<T>> {
+        self.tables().find(|table| table.owns_page(header))
+    }
+
+    // Tables whose schema can't be read are logged and skipped
+    pub fn tables(&self) -> impl Iterator<Item = Table<T>> {
+        self.system_tables
+            .tables()
+            .filter_map(move |tbl| self.logged_table_for(tbl))
+    }
+
+    // Writes every table into its own file in `out_dir`, named after the table.
+    // Tables that fail to export are logged and skipped without leaving a file behind, the
+    // returned list contains the name and number of rows of each table that was written successfully
+    pub fn dump_all_tables<P: AsRef<Path>>(
+        &self,
+        out_dir: P,
+        format: ExportFormat,
+        include_system_tables: bool,
+    ) -> std::io::Result<Vec<(String, usize)>> {
+        let out_dir = out_dir.as_ref();
+        std::fs::create_dir_all(out_dir)?;
+
+        let mut used_names = HashSet::new();
+        let mut summary = vec![];
+        for tbl in self.system_tables.tables() {
+            if tbl.ty == SchType::SystemTable && !include_system_tables {
+                continue;
+            }
+
+            let file_name = unique_file_name(&mut used_names, &tbl.name, tbl.id);
+            let path = out_dir.join(format!("{}.{}", file_name, format.extension()));
+
+            let table = match self.table_for(tbl) {
+                Ok(table) => table,
+                Err(e) => {
+                    error!("could not dump table {}: {}", tbl.name, e);
+                    continue;
+                }
+            };
+            // written to a temporary file first, so a failed export does not leave a
+            // truncated file that looks complete
+            let tmp_path = out_dir.join(format!("{}.{}.tmp", file_name, format.extension()));
+            let result = File::create(&tmp_path)
+                .and_then(|file| table.export(BufWriter::new(file), format))
+                .and_then(|rows| std::fs::rename(&tmp_path, &path).map(|()| rows));
+            match result {
+                Ok(rows) => {
+                    info!("dumped {} rows of {} to {}", rows, tbl.name, path.display());
+                    summary.push((tbl.name.clone(), rows));
+                }
+                Err(e) => {
+                    error!("could not dump table {}: {}", tbl.name, e);
+                    let _ = std::fs::remove_file(&tmp_path);
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    fn logged_table_for(&self, tbl: &SysSchObj) -> Option<Table<T>> {
+        self.table_for(tbl)
+            .map_err(|e| error!("could not read the schema of {}: {}", tbl.name, e))
+            .ok()
+    }
+
+    // The table described by `tbl`, which has to be one of `system_tables.tables()`.
+    // Fails if its columns use types or storage features that can't be parsed
+    pub fn table_for(&self, tbl: &SysSchObj) -> Result<Table<T>, MdfError> {
+        let alloc_units: Vec<_> = self
+            .system_tables
+            .partitions_for_table(tbl)
+            .filter_map(|part| self.system_tables.allocation_unit_for_partition(part))
+            .collect();
+        let index_id = self
+            .system_tables
+            .partitions_for_table(tbl)
+            .map(|part| part.id_minor)
+            .next()
+            .unwrap_or(0);
+
+        let mut schema =
+            Schema::from_col_par(self.system_tables.columns_for_table(tbl).filter_map(|col| {
                 trace!("col = {:?}", col);
-                (col, self.system_tables.type_for_column(col))
-            })),
-            partition_pointer: self
+                match self.system_tables.type_for_column(col) {
+                    Some(ty) => Some((col, ty)),
+                    None => {
+                        error!(
+                            "unknown type {} of column {:?} of {}",
+                            col.xtype, col.name, tbl.name
+                        );
+                        None
+                    }
+                }
+            }))?;
+        for column in &mut schema.columns {
+            column.type_alias = self
                 .system_tables
-                .partitions_for_table(tbl)
-                .map(|part| {
-                    self.system_tables
-                        .allocation_unit_for_partition(part)
-                        .pg_first
+                .columns_for_table(tbl)
+                .find(|col| col.col_id == column.idx)
+                .and_then(|col| self.system_tables.alias_type_for_column(col))
+                .map(|ty| ty.name.clone());
+        }
+        // all partitions of a table share the same layout
+        if let Some(part) = self.system_tables.partitions_for_table(tbl).next() {
+            let rs_cols: Vec<_> = self.system_tables.rs_cols_for_partition(part).collect();
+            if !rs_cols.is_empty() {
+                schema = schema.with_layout(rs_cols);
+            }
+        }
+
+        let key_columns_of = |index: &SysIdxStat| -> Vec<usize> {
+            self.system_tables
+                .key_columns_for_index(index)
+                .iter()
+                .filter_map(|col| {
+                    schema
+                        .columns
+                        .iter()
+                        .position(|column| column.idx == col.col_id())
                 })
-                .filter(|pg| pg.is_some())
-                .map(|pg| pg.unwrap())
-                .collect(),
-        })
-    }
+                .collect()
+        };
+        let key_columns = self
+            .system_tables
+            .indexes_for_table(tbl)
+            .find(|index| index.ind_id == 1)
+            .map(key_columns_of)
+            .unwrap_or_default();
+        let primary_key = self
+            .system_tables
+            .primary_key_index(tbl)
+            .map(key_columns_of)
+            .unwrap_or_default();
 
-    pub fn tables(&self) -> impl Iterator<Item = Table<T>> {
-        self.system_tables.tables().map(move |tbl| Table {
+        Ok(Table {
             name: tbl.name.clone(),
+            object_id: tbl.id,
             page_provider: &self.page_provider,
-            schema: Schema::from_col_par(
-                self.system_tables
-                    .columns_for_table(tbl)
-                    .map(|col| (col, self.system_tables.type_for_column(col))),
-            ),
-            partition_pointer: self
+            schema,
+            index_id,
+            page_owner_ids: alloc_units.iter().map(|au| au.page_owner_ids()).collect(),
+            partition_pointer: alloc_units.iter().filter_map(|au| au.pg_first).collect(),
+            iam_pointer: alloc_units.iter().filter_map(|au| au.pg_firstiam).collect(),
+            index_root: if index_id == 1 {
+                alloc_units.iter().filter_map(|au| au.pg_root).collect()
+            } else {
+                vec![]
+            },
+            key_columns,
+            primary_key,
+            estimated_rows: self
                 .system_tables
                 .partitions_for_table(tbl)
-                .map(|part| {
-                    self.system_tables
-                        .allocation_unit_for_partition(part)
-                        .pg_first
-                })
-                .filter(|pg| pg.is_some())
-                .map(|pg| pg.unwrap())
-                .collect(),
+                .map(|part| part.rcrows)
+                .sum(),
         })
     }
 }
 
+// A foreign key of `table` referencing `referenced_table`,
+// `columns` and `referenced_columns` are in the same order.
+// `referenced_table_id` is `None` if the reference is missing from the system tables,
+// `referenced_table` also if the referenced object can't be found
+#[derive(Debug, Clone)]
+pub struct ForeignKey {
+    pub name: String,
+    pub object_id: i32,
+    pub table: String,
+    pub table_id: i32,
+    pub columns: Vec<String>,
+    pub referenced_table: Option<String>,
+    pub referenced_table_id: Option<i32>,
+    pub referenced_columns: Vec<String>,
+}
+
+// The parsed system tables that describe the objects of the database.
+// They reference each other by id:
+// - `SysSchObj::id` is the object id of a table, view, procedure, ...
+// - `SysColPar::id` is the object id of the table (or procedure) the column belongs to
+// - `SysRowSet::id_major` is the object id and `SysRowSet::id_minor` the index id
+//   (0 for the heap, 1 for the clustered index and > 1 for nonclustered indexes)
+//   of a partition (rowset)
+// - `SysAllocUnit::owner_id` is the `SysRowSet::row_set_id` of the partition it stores data of
+// - `SysRsCol::row_set_id` is the `SysRowSet::row_set_id` of the partition it describes
+// - `SysIdxStat::id` and `SysIdxStat::ind_id` are the object id and index id of an index,
+//   `SysIsCol::id_major` and `SysIsCol::id_minor` refer to them
+// - `SysObjValue::obj_id` is the object id of the module or constraint the value belongs to
 #[derive(Debug)]
 pub struct SystemTables {
     alloc_units: Vec<SysAllocUnit>,
@@ -91,25 +452,113 @@ pub struct SystemTables {
     scalar_types: Vec<SysScalarType>,
     rs_cols: Vec<SysRsCol>,
     single_object_refs: Vec<SysSingleObjRef>,
+    multi_object_refs: Vec<SysMultiObjRef>,
+    idx_stats: Vec<SysIdxStat>,
+    is_cols: Vec<SysIsCol>,
+    obj_values: Vec<SysObjValue>,
 }
 
 impl SystemTables {
+    pub fn alloc_units(&self) -> &[SysAllocUnit] {
+        &self.alloc_units
+    }
+
+    pub fn row_sets(&self) -> &[SysRowSet] {
+        &self.row_sets
+    }
+
+    pub fn sch_objs(&self) -> &[SysSchObj] {
+        &self.sch_objs
+    }
+
+    pub fn col_pars(&self) -> &[SysColPar] {
+        &self.col_pars
+    }
+
+    pub fn scalar_types(&self) -> &[SysScalarType] {
+        &self.scalar_types
+    }
+
+    // empty for databases older than SQL Server 2008
+    pub fn rs_cols(&self) -> &[SysRsCol] {
+        &self.rs_cols
+    }
+
+    pub fn single_object_refs(&self) -> &[SysSingleObjRef] {
+        &self.single_object_refs
+    }
+
+    pub fn multi_object_refs(&self) -> &[SysMultiObjRef] {
+        &self.multi_object_refs
+    }
+
+    pub fn obj_values(&self) -> &[SysObjValue] {
+        &self.obj_values
+    }
+
+    // All `sysobjvalues` rows of the object, ordered by class, sub object and `val_num`
+    pub fn obj_values_for(&self, id: i32) -> impl Iterator<Item = &SysObjValue> {
+        // `sysobjvalues` is clustered by (val_class, obj_id, sub_obj_id, val_num)
+        let mut values: Vec<_> = self
+            .obj_values
+            .iter()
+            .filter(|value| value.obj_id == id)
+            .collect();
+        values.sort_by_key(|value| (value.val_class, value.sub_obj_id, value.val_num));
+        values.into_iter()
+    }
+
+    pub fn idx_stats(&self) -> &[SysIdxStat] {
+        &self.idx_stats
+    }
+
+    pub fn is_cols(&self) -> &[SysIsCol] {
+        &self.is_cols
+    }
+
+    // User and system tables
     pub fn tables(&self) -> impl Iterator<Item = &SysSchObj> {
         self.sch_objs
             .iter()
             .filter(|obj| obj.ty == SchType::UserTable || obj.ty == SchType::SystemTable)
     }
 
+    pub fn views(&self) -> impl Iterator<Item = &SysSchObj> {
+        self.objects_of_type(SchType::View)
+    }
+
+    pub fn objects_of_type(&self, ty: SchType) -> impl Iterator<Item = &SysSchObj> {
+        self.sch_objs.iter().filter(move |obj| obj.ty == ty)
+    }
+
+    pub fn object_by_id(&self, id: i32) -> Option<&SysSchObj> {
+        self.sch_objs.iter().find(|obj| obj.id == id)
+    }
+
     pub fn partitions_for_table<'a>(
         &'a self,
         table: &'a SysSchObj,
     ) -> impl Iterator<Item = &'a SysRowSet> {
-        // TODO(robin): wtf ever does minor_id mean?
+        // `id_minor` is the index id, the data of the table itself is stored in the heap (0)
+        // or the clustered index (1), a table has one of them for each partition.
+        // Nonclustered indexes (> 1) only contain copies of the data
         self.row_sets
             .iter()
             .filter(move |row_set| row_set.id_major == table.id && row_set.id_minor <= 1)
     }
 
+    // The physical layout of the columns of a partition, empty if `sysrscols` is missing
+    pub fn rs_cols_for_partition<'a>(
+        &'a self,
+        partition: &'a SysRowSet,
+    ) -> impl Iterator<Item = &'a SysRsCol> {
+        self.rs_cols
+            .iter()
+            .filter(move |col| col.row_set_id == partition.row_set_id)
+    }
+
+    // The columns of the table, `syscolpars` is clustered by (id, number, col_id),
+    // so they are in the order of their `col_id`
     pub fn columns_for_table<'a>(
         &'a self,
         table: &'a SysSchObj,
@@ -117,129 +566,307 @@ impl SystemTables {
         self.col_pars.iter().filter(move |col| col.id == table.id)
     }
 
-    pub fn type_for_column(&self, col: &SysColPar) -> &SysScalarType {
+    pub fn column_by_name<'a>(&'a self, table: &'a SysSchObj, name: &str) -> Option<&'a SysColPar> {
+        self.columns_for_table(table)
+            .find(|col| col.name.as_deref() == Some(name))
+    }
+
+    // The default collation of the database. The boot page stores it as well (`dbi_collation`
+    // in the output of `DBCC PAGE`), but its offset in the page is not known. The builtin
+    // character types are created with the default collation, so it is read from `varchar`
+    pub fn default_collation_id(&self) -> Option<i32> {
+        self.scalar_types
+            .iter()
+            .find(|ty| ty.name == "varchar" && ty.id <= 255)
+            .map(|ty| ty.collation_id)
+    }
+
+    // The indexes of the table, including the heap or clustered index (`ind_id` 0 or 1)
+    pub fn indexes_for_table<'a>(
+        &'a self,
+        table: &'a SysSchObj,
+    ) -> impl Iterator<Item = &'a SysIdxStat> {
+        self.idx_stats
+            .iter()
+            .filter(move |index| index.id == table.id && index.ty <= 2)
+    }
+
+    pub fn primary_key_index<'a>(&'a self, table: &'a SysSchObj) -> Option<&'a SysIdxStat> {
+        self.indexes_for_table(table)
+            .find(|index| index.status.contains(IdxStatStatus::PRIMARY_KEY))
+    }
+
+    // The key columns of the index in key order, without included columns
+    pub fn key_columns_for_index<'a>(&'a self, index: &SysIdxStat) -> Vec<&'a SysIsCol> {
+        let mut columns: Vec<_> = self
+            .is_cols
+            .iter()
+            .filter(|col| {
+                col.id_major == index.id
+                    && col.id_minor == index.ind_id
+                    && col.key_ordinal() > 0
+                    && !col.status.contains(IsColStatus::INCLUDED)
+            })
+            .collect();
+        columns.sort_by_key(|col| col.key_ordinal());
+        columns
+    }
+
+    // The foreign keys are objects of their own, with the referencing table as parent (`pid`).
+    // The column pairs are in the key order of the unique index the foreign key references
+    pub fn foreign_keys(&self) -> Vec<ForeignKey> {
+        let column_name = |table_id: i32, col_id: i32| {
+            self.col_pars
+                .iter()
+                .find(|col| col.id == table_id && col.col_id == col_id)
+                .and_then(|col| col.name.clone())
+                .unwrap_or_else(|| format!("<column {}>", col_id))
+        };
+        let table_name = |id: i32| {
+            self.object_by_id(id)
+                .map(|obj| obj.name.clone())
+                .unwrap_or_else(|| format!("<object {}>", id))
+        };
+
+        self.objects_of_type(SchType::ForeignKey)
+            .map(|fk| {
+                let key_ref = self
+                    .single_object_refs
+                    .iter()
+                    .find(|r| r.class == SysSingleObjRef::FOREIGN_KEY_CLASS && r.dep_id == fk.id);
+                let referenced_table_id = key_ref.map(|r| r.in_dep_id);
+                // position of each referenced column in the key of the referenced index
+                let key_columns: Vec<i32> = key_ref
+                    .and_then(|r| {
+                        self.idx_stats.iter().find(|index| {
+                            index.id == r.in_dep_id && index.ind_id == r.in_dep_sub_id
+                        })
+                    })
+                    .map(|index| {
+                        self.key_columns_for_index(index)
+                            .iter()
+                            .map(|col| col.int_prop)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                let mut column_refs: Vec<_> = self
+                    .multi_object_refs
+                    .iter()
+                    .filter(|r| {
+                        r.class == SysMultiObjRef::FOREIGN_KEY_COLUMN_CLASS
+                            && r.dep_id == fk.id
+                            && Some(r.in_dep_id) == referenced_table_id
+                    })
+                    .collect();
+                // stable, columns missing from the key (or an unknown key) keep their row order
+                column_refs.sort_by_key(|r| {
+                    key_columns
+                        .iter()
+                        .position(|&col_id| col_id == r.in_dep_sub_id)
+                        .unwrap_or(usize::MAX)
+                });
+                let (columns, referenced_columns) = column_refs
+                    .into_iter()
+                    .map(|r| {
+                        (
+                            column_name(fk.pid, r.dep_sub_id),
+                            column_name(r.in_dep_id, r.in_dep_sub_id),
+                        )
+                    })
+                    .unzip();
+
+                ForeignKey {
+                    name: fk.name.clone(),
+                    object_id: fk.id,
+                    table: table_name(fk.pid),
+                    table_id: fk.pid,
+                    columns,
+                    referenced_table: referenced_table_id
+                        .and_then(|id| self.object_by_id(id))
+                        .map(|obj| obj.name.clone()),
+                    referenced_table_id,
+                    referenced_columns,
+                }
+            })
+            .collect()
+    }
+
+    pub fn scalar_type_by_id(&self, id: i32) -> Option<&SysScalarType> {
+        self.scalar_types.iter().find(|ty| ty.id == id)
+    }
+
+    // The builtin type the column is stored as. The `utype` of the column refers to its type,
+    // which for alias types (including `sysname`) shares the `xtype` of its base type
+    pub fn type_for_column(&self, col: &SysColPar) -> Option<&SysScalarType> {
+        let xtype = match self.scalar_type_by_id(col.utype) {
+            Some(ty) if ty.id <= 255 => return Some(ty),
+            Some(alias) => alias.xtype,
+            None => col.xtype,
+        };
         self.scalar_types
             .iter()
-            .find(|ty| ty.xtype == col.xtype && ty.id <= 255)
-            .unwrap()
+            .find(|ty| ty.xtype == xtype && ty.id <= 255)
+    }
+
+    // The user defined alias type of the column, `None` for columns of builtin types
+    pub fn alias_type_for_column(&self, col: &SysColPar) -> Option<&SysScalarType> {
+        self.scalar_type_by_id(col.utype).filter(|ty| ty.id > 255)
     }
 
-    pub fn allocation_unit_for_partition(&self, partition: &SysRowSet) -> &SysAllocUnit {
+    // The allocation unit holding the in-row data of the partition,
+    // its LOB and row overflow data is stored in separate allocation units
+    pub fn allocation_unit_for_partition(&self, partition: &SysRowSet) -> Option<&SysAllocUnit> {
         self.alloc_units
             .iter()
             .find(|au| au.owner_id == partition.row_set_id && au.ty == AllocUnitType::InRowData)
-            .unwrap()
     }
 
-    fn parse<T: PageProvider>(page_provider: &T, boot_page: &BootPage) -> Self {
-        let alloc_units: Vec<_> = page_provider
-            .get(boot_page.first_sys_indices)
-            .unwrap()
-            .into_records()
-            .map(SysAllocUnit::parse)
-            .collect();
-        let row_sets: Vec<_> = page_provider
-            .get(
-                Self::find_alloc_unit_by_id(
-                    &alloc_units[..],
-                    SYS_ROW_SET_AUID,
-                    AllocUnitType::InRowData,
-                )
-                .unwrap()
-                .pg_first
-                .unwrap(),
+    // Without a `server_version` the layout of the newest version is assumed
+    fn parse<T: PageProvider>(
+        page_provider: &T,
+        first_sys_indices: PagePointer,
+        server_version: Option<ServerVersion>,
+    ) -> Result<Self, MdfError> {
+        let alloc_units = Self::read_table(
+            page_provider,
+            "sysallocunits",
+            Some(first_sys_indices),
+            SysAllocUnit::try_parse,
+        )?;
+        let row_sets = Self::read_table(
+            page_provider,
+            "sysrowsets",
+            Self::find_alloc_unit_by_id(
+                &alloc_units[..],
+                SYS_ROW_SET_AUID,
+                AllocUnitType::InRowData,
             )
-            .unwrap()
-            .into_records()
-            .map(SysRowSet::parse)
-            .collect();
+            .and_then(|alloc_unit| alloc_unit.pg_first),
+            SysRowSet::try_parse,
+        )?;
+        let first_page = |id_major| {
+            Self::find_alloc_unit_by_rowset_ids(&alloc_units, &row_sets, id_major, 1)
+                .and_then(|alloc_unit| alloc_unit.pg_first)
+        };
 
         // TODO(robin): figure out what the id_minor stands for,
-        let sch_objs = page_provider
-            .get(
-                Self::find_alloc_unit_by_rowset_ids(
-                    &alloc_units,
-                    &row_sets,
-                    SYS_SCH_OBJS_IDMAJOR,
-                    1,
-                )
-                .unwrap()
-                .pg_first
-                .unwrap(),
-            )
-            .unwrap()
-            .records()
-            .map(SysSchObj::parse)
-            .collect();
-
-        let col_pars = page_provider
-            .get(
-                Self::find_alloc_unit_by_rowset_ids(
-                    &alloc_units,
-                    &row_sets,
-                    SYS_COL_PARS_IDMAJOR,
-                    1,
-                )
-                .unwrap()
-                .pg_first
-                .unwrap(),
-            )
-            .unwrap()
-            .records()
-            .map(SysColPar::parse)
-            .collect();
+        let sch_objs = Self::read_table(
+            page_provider,
+            "sysschobjs",
+            first_page(SYS_SCH_OBJS_IDMAJOR),
+            SysSchObj::try_parse,
+        )?;
+        let col_pars = Self::read_table(
+            page_provider,
+            "syscolpars",
+            first_page(SYS_COL_PARS_IDMAJOR),
+            SysColPar::try_parse,
+        )?;
+        let scalar_types = Self::read_table(
+            page_provider,
+            "sysscalartypes",
+            first_page(SYS_SCALAR_TYPES_IDMAJOR),
+            SysScalarType::try_parse,
+        )?;
 
-        let scalar_types = page_provider
-            .get(
-                Self::find_alloc_unit_by_rowset_ids(
-                    &alloc_units,
-                    &row_sets,
-                    SYS_SCALAR_TYPES_IDMAJOR,
-                    1,
-                )
-                .unwrap()
-                .pg_first
-                .unwrap(),
+        // SQL Server 2005 has a `sysrowsetcolumns` with a different layout instead
+        let has_rs_cols = server_version.is_none_or(|version| version >= ServerVersion::Sql2008);
+        let rs_cols = if has_rs_cols {
+            Self::read_optional_table(
+                page_provider,
+                "sysrscols",
+                first_page(SYS_RS_COLS_IDMAJOR),
+                SysRsCol::try_parse,
             )
-            .unwrap()
-            .records()
-            .map(SysScalarType::parse)
-            .collect();
+        } else {
+            vec![]
+        };
 
-        /*
-        let rs_cols = page_provider.get(
-            Self::find_alloc_unit_by_rowset_ids(
-                &alloc_units, &row_sets, SYS_RS_COLS_IDMAJOR, 1
-            ).unwrap().pg_first.unwrap()
-        ).records().take(530).map(SysRsCol::parse).collect();
-        */
-
-        let single_object_refs = page_provider
-            .get(
-                Self::find_alloc_unit_by_rowset_ids(
-                    &alloc_units,
-                    &row_sets,
-                    SYS_SINGLE_OBJECT_REFS_IDMAJOR,
-                    1,
-                )
-                .unwrap()
-                .pg_first
-                .unwrap(),
-            )
-            .unwrap()
-            .records()
-            .map(SysSingleObjRef::parse)
-            .collect();
+        let single_object_refs = Self::read_table(
+            page_provider,
+            "syssingleobjrefs",
+            first_page(SYS_SINGLE_OBJECT_REFS_IDMAJOR),
+            SysSingleObjRef::try_parse,
+        )?;
+        let multi_object_refs = Self::read_optional_table(
+            page_provider,
+            "sysmultiobjrefs",
+            first_page(SYS_MULTI_OBJECT_REFS_IDMAJOR),
+            SysMultiObjRef::try_parse,
+        );
+        let idx_stats = Self::read_optional_table(
+            page_provider,
+            "sysidxstats",
+            first_page(SYS_IDX_STATS_IDMAJOR),
+            SysIdxStat::try_parse,
+        );
+        let is_cols = Self::read_optional_table(
+            page_provider,
+            "sysiscols",
+            first_page(SYS_IS_COLS_IDMAJOR),
+            SysIsCol::try_parse,
+        );
+        let obj_values = Self::read_optional_table(
+            page_provider,
+            "sysobjvalues",
+            first_page(SYS_OBJ_VALUES_IDMAJOR),
+            SysObjValue::try_parse,
+        );
 
-        Self {
+        Ok(Self {
             alloc_units,
             row_sets,
             sch_objs,
             col_pars,
             scalar_types,
-            rs_cols: vec![],
+            rs_cols,
             single_object_refs,
-        }
+            multi_object_refs,
+            idx_stats,
+            is_cols,
+            obj_values,
+        })
+    }
+
+    // All the rows of the system table `name` starting at `first_page`
+    fn read_table<T: PageProvider, R>(
+        page_provider: &T,
+        name: &str,
+        first_page: Option<PagePointer>,
+        parse: impl Fn(Record) -> Result<R, MdfError>,
+    ) -> Result<Vec<R>, MdfError> {
+        let first_page = first_page
+            .ok_or_else(|| MdfError::Corrupt(format!("could not find the {} table", name)))?;
+        let page = page_provider.get(first_page).ok_or_else(|| {
+            MdfError::Corrupt(format!(
+                "could not read the first page of {} at {:?}",
+                name, first_page
+            ))
+        })?;
+        page.into_records().map(parse).collect()
+    }
+
+    // Like `read_table`, but the table is only needed for additional information,
+    // so unreadable rows are skipped and a missing table is empty
+    fn read_optional_table<T: PageProvider, R>(
+        page_provider: &T,
+        name: &str,
+        first_page: Option<PagePointer>,
+        parse: impl Fn(Record) -> Result<R, MdfError>,
+    ) -> Vec<R> {
+        first_page
+            .and_then(|first_page| page_provider.get(first_page))
+            .map(|page| {
+                page.into_records()
+                    .filter_map(|record| {
+                        parse(record)
+                            .map_err(|e| error!("skipping {} row: {}", name, e))
+                            .ok()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
     fn find_alloc_unit_by_id(
@@ -270,3 +897,206 @@ impl SystemTables {
             })
     }
 }
+
+// The file name (without extension) for a table, unique among `used_names`.
+// Table names can contain anything, but different tables can share a name (in different
+// schemas), so this falls back to appending the object id, and a counter if that is taken too
+fn unique_file_name(used_names: &mut HashSet<String>, name: &str, id: i32) -> String {
+    let base: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let mut file_name = base.clone();
+    let mut counter = 0;
+    while !used_names.insert(file_name.clone()) {
+        file_name = match counter {
+            0 => format!("{}_{}", base, id),
+            _ => format!("{}_{}_{}", base, id, counter),
+        };
+        counter += 1;
+    }
+    file_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system_tables::ColParStatus;
+
+    fn object(id: i32, name: &str, ty: SchType, pid: i32) -> SysSchObj {
+        let created = chrono::NaiveDate::from_ymd_opt(2020, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .unwrap();
+        SysSchObj {
+            id,
+            name: name.to_string(),
+            ns_id: 1,
+            ns_class: 0,
+            status: 0,
+            ty,
+            pid,
+            pcall: 0,
+            int_prop: 0,
+            created,
+            modified: created,
+        }
+    }
+
+    fn column(id: i32, col_id: i32, name: &str) -> SysColPar {
+        SysColPar {
+            id,
+            number: 0,
+            col_id,
+            name: Some(name.to_string()),
+            xtype: 56,
+            utype: 56,
+            length: 4,
+            prec: 10,
+            scale: 0,
+            collation_id: 0,
+            status: ColParStatus::empty(),
+            max_in_row: 0,
+            xml_ns: 0,
+            dflt: 0,
+            chk: 0,
+            idt_val: None,
+        }
+    }
+
+    // the class values are spelled out, to catch changes of the constants
+    fn single_ref(dep_id: i32, in_dep_id: i32, in_dep_sub_id: i32) -> SysSingleObjRef {
+        SysSingleObjRef {
+            class: 11,
+            dep_id,
+            dep_sub_id: 0,
+            in_dep_id,
+            in_dep_sub_id,
+            status: 0,
+        }
+    }
+
+    fn column_ref(
+        dep_id: i32,
+        dep_sub_id: i32,
+        in_dep_id: i32,
+        in_dep_sub_id: i32,
+    ) -> SysMultiObjRef {
+        SysMultiObjRef {
+            class: 3,
+            dep_id,
+            dep_sub_id,
+            in_dep_id,
+            in_dep_sub_id,
+            status: 0,
+        }
+    }
+
+    fn index(id: i32, ind_id: i32) -> SysIdxStat {
+        SysIdxStat {
+            id,
+            ind_id,
+            name: Some(format!("index_{}", ind_id)),
+            status: IdxStatStatus::PRIMARY_KEY,
+            int_prop: 0,
+            fill_fact: 0,
+            ty: 1,
+            tiny_prop: 0,
+            data_space: 1,
+            lob_ds: 0,
+            row_set: 0,
+        }
+    }
+
+    fn key_column(id: i32, ind_id: i32, col_id: i32, key_ordinal: i8) -> SysIsCol {
+        SysIsCol {
+            id_major: id,
+            id_minor: ind_id,
+            sub_id: col_id,
+            status: IsColStatus::empty(),
+            int_prop: col_id,
+            tiny_prop1: key_ordinal,
+            tiny_prop2: 0,
+        }
+    }
+
+    // orders (id 10) references customers (id 20) with the foreign key 30,
+    // the foreign key 40 of orders references a table missing from sysschobjs
+    fn system_tables() -> SystemTables {
+        SystemTables {
+            alloc_units: vec![],
+            row_sets: vec![],
+            sch_objs: vec![
+                object(10, "orders", SchType::UserTable, 0),
+                object(20, "customers", SchType::UserTable, 0),
+                object(30, "fk_orders_customers", SchType::ForeignKey, 10),
+                object(40, "fk_orders_missing", SchType::ForeignKey, 10),
+                object(45, "fk_orders_unknown", SchType::ForeignKey, 10),
+            ],
+            col_pars: vec![
+                column(10, 1, "id"),
+                column(10, 2, "customer_id"),
+                column(10, 3, "region"),
+                column(20, 1, "id"),
+                column(20, 2, "region"),
+            ],
+            scalar_types: vec![],
+            rs_cols: vec![],
+            single_object_refs: vec![single_ref(30, 20, 1), single_ref(40, 50, 1)],
+            multi_object_refs: vec![
+                column_ref(30, 2, 20, 1),
+                // same foreign key, but not referencing the table of the foreign key
+                column_ref(30, 1, 60, 1),
+                column_ref(30, 3, 20, 2),
+            ],
+            // the primary key of customers is (region, id)
+            idx_stats: vec![index(20, 1)],
+            is_cols: vec![key_column(20, 1, 1, 2), key_column(20, 1, 2, 1)],
+            obj_values: vec![],
+        }
+    }
+
+    #[test]
+    fn unique_file_names() {
+        let mut used_names = HashSet::new();
+        let names: Vec<_> = vec![("a b", 1), ("a_b", 2), ("a_b_2", 3), ("a_b", 4), ("a.b", 2)]
+            .into_iter()
+            .map(|(name, id)| unique_file_name(&mut used_names, name, id))
+            .collect();
+        assert_eq!(names, vec!["a_b", "a_b_2", "a_b_2_3", "a_b_4", "a_b_2_1"]);
+    }
+
+    #[test]
+    fn foreign_keys() {
+        let foreign_keys = system_tables().foreign_keys();
+        assert_eq!(foreign_keys.len(), 3);
+
+        let fk = &foreign_keys[0];
+        assert_eq!(fk.name, "fk_orders_customers");
+        assert_eq!(fk.table, "orders");
+        assert_eq!(fk.table_id, 10);
+        // in key order of the primary key, not in row order
+        assert_eq!(fk.columns, vec!["region", "customer_id"]);
+        assert_eq!(fk.referenced_table.as_deref(), Some("customers"));
+        assert_eq!(fk.referenced_table_id, Some(20));
+        assert_eq!(fk.referenced_columns, vec!["region", "id"]);
+
+        // the referenced object does not exist
+        let fk = &foreign_keys[1];
+        assert_eq!(fk.referenced_table, None);
+        assert_eq!(fk.referenced_table_id, Some(50));
+        assert!(fk.columns.is_empty());
+
+        // the reference to the referenced table is missing
+        let fk = &foreign_keys[2];
+        assert_eq!(fk.referenced_table, None);
+        assert_eq!(fk.referenced_table_id, None);
+        assert!(fk.referenced_columns.is_empty());
+    }
+}
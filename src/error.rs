@@ -0,0 +1,65 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MdfError {
+    // reading the database files failed
+    #[error("I/O error: {0}")]
+    Io(#[source] std::io::Error),
+    // a column type name we don't know how to parse
+    #[error("unknown column type {0}")]
+    UnknownType(String),
+    // the data ended before the structure we were parsing
+    #[error("unexpected end of data")]
+    UnexpectedEnd,
+    // a slot index past the slot count of the page
+    #[error("slot {0} does not exist")]
+    InvalidSlot(u16),
+    // a record type we can't parse (yet)
+    #[error("unsupported record type {0}")]
+    UnsupportedRecord(String),
+    // valid data using a feature we can't parse (yet)
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+    // the data does not make sense
+    #[error("corrupt data: {0}")]
+    Corrupt(String),
+    // a column passed to `Schema::parse_fixed_column` that is not stored as a
+    // fixed length column
+    #[error("invalid column: {0}")]
+    InvalidColumn(String),
+    // a key (or key column) passed to `Table::seek` / `Table::with_key_columns`
+    // that does not fit the table
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+}
+
+// byteorder fails with `UnexpectedEof` if there is not enough data left,
+// everything else is a real I/O error
+impl From<std::io::Error> for MdfError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            Self::UnexpectedEnd
+        } else {
+            Self::Io(e)
+        }
+    }
+}
+
+// The first difference between the schema of a table and the one it was expected to have
+#[derive(Debug, Error)]
+pub enum SchemaMismatch {
+    #[error("expected {expected} columns, found {actual}")]
+    ColumnCount { expected: usize, actual: usize },
+    #[error("expected column {idx} to be named {expected}, found {actual}")]
+    Name {
+        idx: usize,
+        expected: String,
+        actual: String,
+    },
+    #[error("expected column {column} to be of type {expected}, found {actual}")]
+    Type {
+        column: String,
+        expected: String,
+        actual: String,
+    },
+}
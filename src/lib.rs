@@ -10,6 +10,9 @@ pub use pages::*;
 
 pub(crate) mod util;
 
+pub mod error;
+pub use error::*;
+
 pub mod types;
 pub use types::*;
 
@@ -24,3 +27,14 @@ pub use table::*;
 
 pub mod lob;
 pub use lob::*;
+
+pub mod providers;
+pub use providers::*;
+
+#[cfg(feature = "async")]
+pub mod async_provider;
+#[cfg(feature = "async")]
+pub use async_provider::*;
+
+#[cfg(feature = "arrow")]
+pub mod arrow_export;
@@ -1,36 +1,78 @@
-use crate::{PageProvider, Record, RecordPointer};
+use crate::util::{read_u16_at, read_u32_at, read_u64_at, slice, to_hex};
+use crate::{MdfError, PageBytes, PageProvider, PageType, Record, RecordPointer};
 use byteorder::{LittleEndian, ReadBytesExt};
 use derivative::Derivative;
 use log::{error, warn};
 use serde::{Deserialize, Serialize};
-use std::io::Write;
+use std::collections::HashSet;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+
+fn discontiguous(what: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, what)
+}
 
 #[derive(Debug)]
 pub struct LobDataBlocks<'a> {
-    pub data_blocks: Vec<(u64, &'a [u8])>,
+    pub data_blocks: Vec<(u64, PageBytes<'a>)>,
 }
 
 impl<'a> LobDataBlocks<'a> {
     pub fn write_to_file(&self, filename: &str) -> Result<(), std::io::Error> {
-        let mut file = std::fs::File::create(filename)?;
-        let mut last_offs = 0;
-        for data_block in &self.data_blocks {
-            if data_block.0 > last_offs {
-                // rather allow out of order block than not dumping anything
-                // let length = data_block.0 - last_offs;
-                // assert_eq!(data_block.1.len(), length as usize);
-                warn!("potentially out of order data blocks for {}", filename);
-            } else {
-                // it seems that this can happen for very big files
-                warn!("potentially out of order data blocks for {}", filename);
+        let file = std::fs::File::create(filename)?;
+        self.write_to(std::io::BufWriter::new(file), false)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", filename, e)))
+    }
+
+    // Writes the blocks in offset order. Missing data is either filled with zeros or reported
+    // as an error, overlapping blocks are always an error.
+    pub fn write_to<W: Write>(&self, mut writer: W, fill_gaps: bool) -> std::io::Result<()> {
+        let mut end = 0;
+        for (block_end, data) in self.sorted_blocks() {
+            // the offset stored with each block is the offset of its end
+            let start = block_end.checked_sub(data.len() as u64).ok_or_else(|| {
+                discontiguous(format!("block ending at {} is too long", block_end))
+            })?;
+            if start < end {
+                return Err(discontiguous(format!(
+                    "block at {} overlaps with the data up to {}",
+                    start, end
+                )));
             }
+            if start > end {
+                if !fill_gaps {
+                    return Err(discontiguous(format!(
+                        "missing data between {} and {}",
+                        end, start
+                    )));
+                }
+                warn!(
+                    "filling missing LOB data between {} and {} with zeros",
+                    end, start
+                );
+                std::io::copy(&mut std::io::repeat(0).take(start - end), &mut writer)?;
+            }
+            writer.write_all(&data)?;
+            end = block_end;
+        }
 
-            file.write_all(data_block.1)?;
+        writer.flush()
+    }
 
-            last_offs = data_block.0;
-        }
+    // The blocks are collected in the order the LOB tree is traversed, which is not
+    // necessarily the order of the data
+    fn sorted_blocks(&self) -> Vec<(u64, PageBytes<'a>)> {
+        let mut blocks = self.data_blocks.clone();
+        blocks.sort_by_key(|(offset, _)| *offset);
+        blocks
+    }
 
-        Ok(())
+    // All the data blocks joined together in offset order
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.sorted_blocks()
+            .into_iter()
+            .flat_map(|(_, data)| data.to_vec())
+            .collect()
     }
 
     pub fn length(&self) -> u32 {
@@ -42,23 +84,105 @@ impl<'a> LobDataBlocks<'a> {
     }
 }
 
-#[derive(Debug)]
+// Points to data stored outside of the row. Either a 16 byte text pointer (`text`, `ntext`,
+// `image` and max types with `large value types out of row`) or a 24+ byte inline root for
+// row-overflow data and max types, which can point to multiple chunks of data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LobPointer {
     timestamp: u32,
-    ptr: RecordPointer,
+    // the LOB data is the concatenation of the data of the trees starting at these records
+    roots: Vec<RecordPointer>,
 }
 
+const TEXT_POINTER_SIZE: usize = 16;
+const INLINE_ROOT_TYPE: u8 = 2;
+const INLINE_ROOT_HEADER_SIZE: usize = 12;
+const INLINE_ROOT_ENTRY_SIZE: usize = 12;
+
 impl LobPointer {
-    pub fn parse(data: &[u8]) -> Self {
+    pub fn parse(data: &[u8]) -> Result<Self, MdfError> {
+        if data.len() == TEXT_POINTER_SIZE {
+            Ok(Self {
+                timestamp: slice(data, 0..4)?.read_u32::<LittleEndian>()?,
+                roots: vec![Self::parse_record_pointer(slice(data, 8..16)?)?],
+            })
+        } else if data.first() == Some(&INLINE_ROOT_TYPE) {
+            let entries = slice(data, INLINE_ROOT_HEADER_SIZE..)?;
+            if entries.is_empty() || entries.len() % INLINE_ROOT_ENTRY_SIZE != 0 {
+                return Err(MdfError::Corrupt(format!(
+                    "inline LOB root of invalid length {}",
+                    data.len()
+                )));
+            }
+            Ok(Self {
+                timestamp: slice(data, 8..12)?.read_u32::<LittleEndian>()?,
+                // every entry is the length of the data followed by the pointer to it
+                roots: entries
+                    .chunks(INLINE_ROOT_ENTRY_SIZE)
+                    .map(|entry| Self::parse_record_pointer(&entry[4..]))
+                    .collect::<Result<_, _>>()?,
+            })
+        } else {
+            Err(MdfError::Unsupported(format!(
+                "complex column of length {} ({})",
+                data.len(),
+                to_hex(data)
+            )))
+        }
+    }
+
+    fn parse_record_pointer(data: &[u8]) -> Result<RecordPointer, MdfError> {
+        RecordPointer::parse(data)
+            .ok_or_else(|| MdfError::Corrupt("LOB pointer to file 0".to_string()))
+    }
+
+    // A pointer to the LOB whose root is stored at `root`
+    pub fn from_root(root: RecordPointer) -> Self {
         Self {
-            timestamp: (&data[0..4]).read_u32::<LittleEndian>().unwrap(),
-            ptr: RecordPointer::parse(&data[8..16]).unwrap(),
+            timestamp: 0,
+            roots: vec![root],
         }
     }
 
-    // TODO(robin): refactor!!!
+    // the record the LOB data starts at
+    pub fn record_pointer(&self) -> RecordPointer {
+        self.roots[0]
+    }
+
     pub fn read<'a, T: PageProvider>(&self, page_provider: &'a T) -> Option<LobDataBlocks<'a>> {
-        let record = page_provider.get_record(self.ptr)?;
+        let mut data_blocks = vec![];
+        // the offsets of each tree start at zero
+        let mut base = 0;
+        for root in &self.roots {
+            let blocks = Self::read_tree(page_provider, *root)?;
+            let length = blocks.length() as u64;
+            data_blocks.extend(
+                blocks
+                    .data_blocks
+                    .into_iter()
+                    .map(|(offset, data)| (base + offset, data)),
+            );
+            base += length;
+        }
+        Some(LobDataBlocks { data_blocks })
+    }
+
+    // Streams the LOB data, only reading the pages when they are needed
+    pub fn reader<'a, T: PageProvider>(&self, page_provider: &'a T) -> LobReader<'a, T> {
+        LobReader {
+            page_provider,
+            roots: self.roots.clone().into_iter(),
+            stack: vec![],
+            current: PageBytes::empty(),
+        }
+    }
+
+    // TODO(robin): refactor!!!
+    fn read_tree<'a, T: PageProvider>(
+        page_provider: &'a T,
+        root: RecordPointer,
+    ) -> Option<LobDataBlocks<'a>> {
+        let record = page_provider.get_record(root)?;
         let mut entries = vec![LobEntry::parse(record)?];
         let mut data_blocks = vec![];
 
@@ -69,15 +193,19 @@ impl LobPointer {
                     LobEntry::SmallRoot(LobSmallRoot { data, .. })
                     | LobEntry::Data(LobData { data, .. }) => {
                         // this can basically only happen at the first entry
-                        data_blocks.push((data.len() as u64, *data));
+                        data_blocks.push((data.len() as u64, data.clone()));
                     }
                     _ => {
-                        for (offs, entry) in entry.sub_entries(page_provider) {
-                            let entry = entry?;
+                        // all children are fetched at once, so the provider can batch the reads
+                        let links = entry.links();
+                        let ptrs: Vec<_> = links.iter().map(|(_, ptr)| *ptr).collect();
+                        let records = page_provider.get_records(&ptrs);
+                        for ((offs, _), record) in links.into_iter().zip(records) {
+                            let entry = LobEntry::parse(record?)?;
                             match &entry {
                                 LobEntry::SmallRoot(LobSmallRoot { data, .. })
                                 | LobEntry::Data(LobData { data, .. }) => {
-                                    data_blocks.push((offs, *data));
+                                    data_blocks.push((offs, data.clone()));
                                 }
                                 _ => new_entries.push(entry),
                             }
@@ -92,18 +220,99 @@ impl LobPointer {
     }
 }
 
+// Walks the LOB trees depth first, so the data is returned in order
+pub struct LobReader<'a, T> {
+    page_provider: &'a T,
+    roots: std::vec::IntoIter<RecordPointer>,
+    // the entries we are currently descending into, with the index of the next link to follow
+    stack: Vec<(LobEntry<'a>, u16)>,
+    current: PageBytes<'a>,
+}
+
+impl<'a, T: PageProvider> LobReader<'a, T> {
+    fn next_block(&mut self) -> std::io::Result<Option<PageBytes<'a>>> {
+        fn broken(what: String) -> std::io::Error {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, what)
+        }
+
+        loop {
+            let (entry, idx) = match self.stack.last_mut() {
+                Some(top) => top,
+                None => match self.roots.next() {
+                    Some(root) => {
+                        let entry = self
+                            .page_provider
+                            .get_record(root)
+                            .and_then(LobEntry::parse)
+                            .ok_or_else(|| broken(format!("could not read LOB root {:?}", root)))?;
+                        self.stack.push((entry, 0));
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+            };
+
+            let child = match entry {
+                LobEntry::SmallRoot(LobSmallRoot { data, .. })
+                | LobEntry::Data(LobData { data, .. }) => {
+                    let data = data.clone();
+                    self.stack.pop();
+                    return Ok(Some(data));
+                }
+                _ if *idx < entry.link_count() => entry.read_link(self.page_provider, *idx),
+                _ => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let link = *idx;
+            *idx += 1;
+
+            match child {
+                Some((_, Some(child))) => self.stack.push((child, 0)),
+                _ => {
+                    return Err(broken(format!(
+                        "could not read link {} of {:?}",
+                        link, entry
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T: PageProvider> std::io::Read for LobReader<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.next_block()? {
+                Some(block) => self.current = block,
+                None => return Ok(0),
+            }
+        }
+
+        let length = buf.len().min(self.current.len());
+        buf[..length].copy_from_slice(&self.current[..length]);
+        self.current = self.current.slice(length..).unwrap();
+        Ok(length)
+    }
+}
+
 #[derive(Debug)]
 pub enum LobEntry<'a> {
     SmallRoot(LobSmallRoot<'a>),
-    LargeRootYukon(LobLargeRootYukon<'a>),
+    LargeRootShiloh(LobNode<'a>),
+    LargeRootYukon(LobNode<'a>),
+    SuperLargeRoot(LobNode<'a>),
     Data(LobData<'a>),
-    Internal(LobInternal<'a>),
+    Internal(LobNode<'a>),
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum LobType {
     SmallRoot,
+    LargeRootShiloh,
     LargeRootYukon,
+    SuperLargeRoot,
     Data,
     Internal,
     Null,
@@ -111,17 +320,15 @@ pub enum LobType {
 
 impl LobType {
     fn parse(record: &Record) -> Option<Self> {
-        let ty = (&record.fixed_data[8..10])
-            .read_u16::<LittleEndian>()
-            .unwrap();
+        let ty = read_u16_at(&record.fixed_data, 8)?;
         match ty {
             0 => Some(Self::SmallRoot),
             // 1 => Self::LargeRoot,
             2 => Some(Self::Internal),
             3 => Some(Self::Data),
-            // 4 => Self::LargeRootShiloh,
+            4 => Some(Self::LargeRootShiloh),
             5 => Some(Self::LargeRootYukon),
-            // 6 => Self::SuperLargeRoot,
+            6 => Some(Self::SuperLargeRoot),
             8 => Some(Self::Null),
             _ => {
                 error!("unknown lob type {}", ty);
@@ -135,11 +342,11 @@ impl<'a> LobEntry<'a> {
     pub fn parse(record: Record<'a>) -> Option<Self> {
         LobType::parse(&record).and_then(|ty| match ty {
             LobType::SmallRoot => Some(Self::SmallRoot(LobSmallRoot::parse(record)?)),
-            LobType::LargeRootYukon => {
-                Some(Self::LargeRootYukon(LobLargeRootYukon::parse(record)?))
-            }
+            LobType::LargeRootShiloh => Some(Self::LargeRootShiloh(LobNode::parse(record)?)),
+            LobType::LargeRootYukon => Some(Self::LargeRootYukon(LobNode::parse(record)?)),
+            LobType::SuperLargeRoot => Some(Self::SuperLargeRoot(LobNode::parse(record)?)),
             LobType::Data => Some(Self::Data(LobData::parse(record)?)),
-            LobType::Internal => Some(Self::Internal(LobInternal::parse(record)?)),
+            LobType::Internal => Some(Self::Internal(LobNode::parse(record)?)),
             LobType::Null => None,
         })
     }
@@ -150,6 +357,60 @@ impl<'a> LobEntry<'a> {
     ) -> LobEntrySubEntryIterator<'a, 'b, T> {
         LobEntrySubEntryIterator::new(self, page_provider)
     }
+
+    // All the entries of a LOB share the same id
+    pub fn blob_id(&self) -> u64 {
+        match self {
+            Self::SmallRoot(root) => root.blob_id,
+            Self::Data(data) => data.blob_id,
+            Self::LargeRootShiloh(node)
+            | Self::LargeRootYukon(node)
+            | Self::SuperLargeRoot(node)
+            | Self::Internal(node) => node.blob_id,
+        }
+    }
+
+    // The entry if it links to further entries, `None` for entries containing data
+    pub fn node(&self) -> Option<&LobNode<'a>> {
+        match self {
+            Self::LargeRootShiloh(node)
+            | Self::LargeRootYukon(node)
+            | Self::SuperLargeRoot(node)
+            | Self::Internal(node) => Some(node),
+            Self::SmallRoot(_) | Self::Data(_) => None,
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        matches!(
+            self,
+            Self::SmallRoot(_)
+                | Self::LargeRootShiloh(_)
+                | Self::LargeRootYukon(_)
+                | Self::SuperLargeRoot(_)
+        )
+    }
+
+    // number of children of this entry, zero for entries containing data
+    fn link_count(&self) -> u16 {
+        self.node().map_or(0, |node| node.cur_links)
+    }
+
+    // The children of this entry, together with the offset (or size) stored for each of them
+    fn links(&self) -> Vec<(u64, RecordPointer)> {
+        (0..self.link_count())
+            .map_while(|idx| self.node()?.link(idx))
+            .collect()
+    }
+
+    // `None` past the last child and for entries containing data, which have no children
+    fn read_link<T: PageProvider>(
+        &self,
+        page_provider: &'a T,
+        idx: u16,
+    ) -> Option<(u64, Option<LobEntry<'a>>)> {
+        self.node()?.read(page_provider, idx)
+    }
 }
 
 pub struct LobEntrySubEntryIterator<'a, 'b, T> {
@@ -172,11 +433,7 @@ impl<'a, 'b, T: PageProvider> Iterator for LobEntrySubEntryIterator<'a, 'b, T> {
     type Item = (u64, Option<LobEntry<'a>>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let item = match self.lob_entry {
-            LobEntry::LargeRootYukon(root) => root.read(self.page_provider, self.idx),
-            LobEntry::Internal(internal) => internal.read(self.page_provider, self.idx),
-            _ => panic!("cannot read subentries of {:?}", self.lob_entry),
-        };
+        let item = self.lob_entry.read_link(self.page_provider, self.idx);
 
         self.idx += 1;
 
@@ -191,26 +448,26 @@ pub struct LobSmallRoot<'a> {
     ty: LobType,
     length: u16,
     #[derivative(Debug = "ignore")]
-    pub data: &'a [u8],
+    pub data: PageBytes<'a>,
 }
 
 impl<'a> LobSmallRoot<'a> {
+    pub fn blob_id(&self) -> u64 {
+        self.blob_id
+    }
+
     fn parse(record: Record<'a>) -> Option<Self> {
-        let blob_id = (&record.fixed_data[..8])
-            .read_u64::<LittleEndian>()
-            .unwrap();
+        let blob_id = read_u64_at(&record.fixed_data, 0)?;
         let ty = LobType::parse(&record)?;
         assert_eq!(ty, LobType::SmallRoot);
 
-        let length = (&record.fixed_data[10..12])
-            .read_u16::<LittleEndian>()
-            .unwrap();
+        let length = read_u16_at(&record.fixed_data, 10)?;
 
         Some(Self {
             blob_id,
             ty,
             length,
-            data: &record.fixed_data[16..16 + length as usize],
+            data: record.fixed_data.slice(16..16 + length as usize).ok()?,
         })
     }
 }
@@ -222,11 +479,11 @@ pub struct SizedRecordPointer {
 }
 
 impl SizedRecordPointer {
-    fn parse(data: &[u8]) -> Self {
-        Self {
-            size: (&data[0..4]).read_u32::<LittleEndian>().unwrap(),
-            ptr: RecordPointer::parse(&data[4..]).unwrap(),
-        }
+    fn parse(data: &[u8]) -> Option<Self> {
+        Some(Self {
+            size: read_u32_at(data, 0)?,
+            ptr: RecordPointer::parse(slice(data, 4..).ok()?)?,
+        })
     }
 }
 
@@ -237,16 +494,18 @@ pub struct RecordPointerWithOffset {
 }
 
 impl RecordPointerWithOffset {
-    fn parse(data: &[u8]) -> Self {
-        Self {
-            offset: (&data[0..8]).read_u64::<LittleEndian>().unwrap(),
-            ptr: RecordPointer::parse(&data[8..]).unwrap(),
-        }
+    fn parse(data: &[u8]) -> Option<Self> {
+        Some(Self {
+            offset: read_u64_at(data, 0)?,
+            ptr: RecordPointer::parse(slice(data, 8..).ok()?)?,
+        })
     }
 }
 
+// An entry of a LOB tree linking to further entries: one of the large roots or an internal
+// node. They share the same header and only differ in where the links start and their format
 #[derive(Debug)]
-pub struct LobLargeRootYukon<'a> {
+pub struct LobNode<'a> {
     pub blob_id: u64,
     ty: LobType,
     pub max_links: u16,
@@ -255,23 +514,26 @@ pub struct LobLargeRootYukon<'a> {
     record: Record<'a>,
 }
 
-impl<'a> LobLargeRootYukon<'a> {
+pub type LobLargeRootYukon<'a> = LobNode<'a>;
+pub type LobInternal<'a> = LobNode<'a>;
+
+impl<'a> LobNode<'a> {
     fn parse(record: Record<'a>) -> Option<Self> {
-        let blob_id = (&record.fixed_data[..8])
-            .read_u64::<LittleEndian>()
-            .unwrap();
+        let blob_id = read_u64_at(&record.fixed_data, 0)?;
         let ty = LobType::parse(&record)?;
-        assert_eq!(ty, LobType::LargeRootYukon);
-
-        let max_links = (&record.fixed_data[10..12])
-            .read_u16::<LittleEndian>()
-            .unwrap();
-        let cur_links = (&record.fixed_data[12..14])
-            .read_u16::<LittleEndian>()
-            .unwrap();
-        let level = (&record.fixed_data[14..16])
-            .read_u16::<LittleEndian>()
-            .unwrap();
+        if !matches!(
+            ty,
+            LobType::LargeRootShiloh
+                | LobType::LargeRootYukon
+                | LobType::SuperLargeRoot
+                | LobType::Internal
+        ) {
+            return None;
+        }
+
+        let max_links = read_u16_at(&record.fixed_data, 10)?;
+        let cur_links = read_u16_at(&record.fixed_data, 12)?;
+        let level = read_u16_at(&record.fixed_data, 14)?;
 
         Some(Self {
             blob_id,
@@ -284,16 +546,35 @@ impl<'a> LobLargeRootYukon<'a> {
     }
 
     pub fn read_idx(&self, idx: u16) -> Option<RecordPointer> {
+        self.link(idx).map(|(_, ptr)| ptr)
+    }
+
+    // The `idx`th child, together with the size of its data for the Shiloh and Yukon roots
+    // or the offset of the end of its data for all others
+    fn link(&self, idx: u16) -> Option<(u64, RecordPointer)> {
         if idx >= self.cur_links {
-            None
-        } else {
-            let idx = idx as usize;
-            Some(
-                SizedRecordPointer::parse(
-                    &self.record.fixed_data[20 + 12 * idx..20 + 12 * (idx + 1)],
-                )
-                .ptr,
-            )
+            return None;
+        }
+
+        let idx = idx as usize;
+        let data = &self.record.fixed_data;
+        match self.ty {
+            // the links of SQL Server 2000 roots directly follow the header,
+            // Yukon roots have 4 more bytes in front of them
+            LobType::LargeRootShiloh | LobType::LargeRootYukon => {
+                let start = if self.ty == LobType::LargeRootShiloh {
+                    16
+                } else {
+                    20
+                } + 12 * idx;
+                let ptr = SizedRecordPointer::parse(slice(data, start..start + 12).ok()?)?;
+                Some((ptr.size as u64, ptr.ptr))
+            }
+            _ => {
+                let start = 16 * (idx + 1);
+                let ptr = RecordPointerWithOffset::parse(slice(data, start..start + 16).ok()?)?;
+                Some((ptr.offset, ptr.ptr))
+            }
         }
     }
 
@@ -302,18 +583,11 @@ impl<'a> LobLargeRootYukon<'a> {
         page_provider: &'a T,
         idx: u16,
     ) -> Option<(u64, Option<LobEntry<'a>>)> {
-        if idx >= self.cur_links {
-            None
-        } else {
-            let idx = idx as usize;
-            let ptr = SizedRecordPointer::parse(
-                &self.record.fixed_data[20 + 12 * idx..20 + 12 * (idx + 1)],
-            );
-            Some((
-                ptr.size as u64,
-                Some(LobEntry::parse(page_provider.get_record(ptr.ptr)?)?),
-            ))
-        }
+        let (offset, ptr) = self.link(idx)?;
+        Some((
+            offset,
+            Some(LobEntry::parse(page_provider.get_record(ptr)?)?),
+        ))
     }
 }
 
@@ -323,93 +597,312 @@ pub struct LobData<'a> {
     blob_id: u64,
     ty: LobType,
     #[derivative(Debug = "ignore")]
-    pub data: &'a [u8],
+    pub data: PageBytes<'a>,
 }
 
 impl<'a> LobData<'a> {
+    pub fn blob_id(&self) -> u64 {
+        self.blob_id
+    }
+
     fn parse(record: Record<'a>) -> Option<Self> {
-        let blob_id = (&record.fixed_data[..8])
-            .read_u64::<LittleEndian>()
-            .unwrap();
+        let blob_id = read_u64_at(&record.fixed_data, 0)?;
         let ty = LobType::parse(&record)?;
         assert_eq!(ty, LobType::Data);
 
         Some(Self {
             blob_id,
             ty,
-            data: &record.fixed_data[10..],
+            data: record.fixed_data.slice(10..).ok()?,
         })
     }
 }
 
-#[derive(Debug)]
-pub struct LobInternal<'a> {
+// A LOB root or internal node found by `LobRootIndex`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LobRootEntry {
+    pub ptr: RecordPointer,
     pub blob_id: u64,
-    ty: LobType,
     pub max_links: u16,
     pub cur_links: u16,
     pub level: u16,
-    record: Record<'a>,
+    pub links: Vec<RecordPointer>,
 }
 
-impl<'a> LobInternal<'a> {
-    fn parse(record: Record<'a>) -> Option<Self> {
-        let blob_id = (&record.fixed_data[..8])
-            .read_u64::<LittleEndian>()
-            .unwrap();
-        let ty = LobType::parse(&record)?;
-        assert_eq!(ty, LobType::Internal);
-
-        let max_links = (&record.fixed_data[10..12])
-            .read_u16::<LittleEndian>()
-            .unwrap();
-        let cur_links = (&record.fixed_data[12..14])
-            .read_u16::<LittleEndian>()
-            .unwrap();
-        let level = (&record.fixed_data[14..16])
-            .read_u16::<LittleEndian>()
-            .unwrap();
-
+impl LobRootEntry {
+    fn from_record(ptr: RecordPointer, record: Record) -> Option<Self> {
+        let entry = LobEntry::parse(record)?;
+        let node = entry.node()?;
         Some(Self {
-            blob_id,
-            ty,
-            max_links,
-            cur_links,
-            level,
-            record,
+            ptr,
+            blob_id: node.blob_id,
+            max_links: node.max_links,
+            cur_links: node.cur_links,
+            level: node.level,
+            links: (0..node.cur_links)
+                .map_while(|idx| node.read_idx(idx))
+                .collect(),
         })
     }
+}
 
-    pub fn read_idx(&self, idx: u16) -> Option<RecordPointer> {
-        if idx >= self.cur_links {
-            None
-        } else {
-            let idx = idx as usize;
-            Some(
-                RecordPointerWithOffset::parse(
-                    &self.record.fixed_data[16 * (idx + 1)..16 * (idx + 2)],
-                )
-                .ptr,
-            )
+// The first line of saved `LobRootIndex` files, the version is increased whenever the format
+// of the entries changes
+const LOB_ROOT_INDEX_HEADER: &str = "mdf lob root index v1";
+
+// All the LOB roots and internal nodes of a database, found by scanning every text page.
+// Building it reads the whole database, so it can be saved to a file and loaded again
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LobRootIndex {
+    pub entries: Vec<LobRootEntry>,
+}
+
+impl LobRootIndex {
+    pub fn build<T: PageProvider>(page_provider: &T) -> Self {
+        let entries = page_provider
+            .extents()
+            .into_iter()
+            .flat_map(|extent| page_provider.get_extent(extent))
+            .flatten()
+            .filter(|page| matches!(page.header.ty, PageType::TextMix | PageType::TextTree))
+            .flat_map(|page| {
+                page.local_records_with_pointers()
+                    .filter_map(|(ptr, record)| LobRootEntry::from_record(ptr, record))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Self { entries }
+    }
+
+    // Saved as JSON, after a line with `LOB_ROOT_INDEX_HEADER`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(writer, "{}", LOB_ROOT_INDEX_HEADER)?;
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+
+    // Fails with `InvalidData` for files not written by `save` of this version (like the
+    // bincode files of older versions), those have to be rebuilt
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut header = vec![];
+        reader.read_until(b'\n', &mut header)?;
+        if header != format!("{}\n", LOB_ROOT_INDEX_HEADER).as_bytes() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("not a {} file", LOB_ROOT_INDEX_HEADER),
+            ));
         }
+        Ok(serde_json::from_reader(reader)?)
     }
 
-    fn read<T: PageProvider>(
-        &self,
-        page_provider: &'a T,
-        idx: u16,
-    ) -> Option<(u64, Option<LobEntry<'a>>)> {
-        if idx >= self.cur_links {
-            None
-        } else {
-            let idx = idx as usize;
-            let ptr = RecordPointerWithOffset::parse(
-                &self.record.fixed_data[16 * (idx + 1)..16 * (idx + 2)],
-            );
-            Some((
-                ptr.offset,
-                Some(LobEntry::parse(page_provider.get_record(ptr.ptr)?)?),
-            ))
+    // The entries not linked to by any other entry, these are the roots of complete LOBs,
+    // in the order they are stored in. Their data can be read using `LobPointer::from_root`
+    pub fn real_roots(&self) -> Vec<&LobRootEntry> {
+        let linked: HashSet<_> = self
+            .entries
+            .iter()
+            .flat_map(|entry| entry.links.iter().copied())
+            .collect();
+        let mut roots: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|entry| !linked.contains(&entry.ptr))
+            .collect();
+        roots.sort_by_key(|entry| entry.ptr);
+        roots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::fixtures::{page_of_type, PTR};
+    use crate::MemoryPageProvider;
+
+    // `data` split into blocks of `size` bytes, each stored with the offset of its end
+    fn blocks(data: &[u8], size: usize) -> Vec<(u64, PageBytes<'_>)> {
+        data.chunks(size)
+            .scan(0, |end, block| {
+                *end += block.len() as u64;
+                Some((*end, PageBytes::borrowed(block)))
+            })
+            .collect()
+    }
+
+    fn write(blocks: &LobDataBlocks, fill_gaps: bool) -> std::io::Result<Vec<u8>> {
+        let mut out = vec![];
+        blocks.write_to(&mut out, fill_gaps)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn shuffled_blocks_are_written_in_offset_order() {
+        let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let mut data_blocks = blocks(&data, 999);
+        // a fixed permutation of the 11 blocks
+        let order = [7, 2, 10, 0, 5, 9, 1, 8, 3, 6, 4];
+        data_blocks = order.iter().map(|idx| data_blocks[*idx].clone()).collect();
+
+        let lob = LobDataBlocks { data_blocks };
+        assert_eq!(write(&lob, false).unwrap(), data);
+        assert_eq!(lob.to_vec(), data);
+        assert_eq!(lob.length(), 10_000);
+    }
+
+    #[test]
+    fn gaps_and_overlaps() {
+        let data = [1u8; 30];
+        let mut data_blocks = blocks(&data, 10);
+        data_blocks.remove(1);
+        let lob = LobDataBlocks { data_blocks };
+        assert!(write(&lob, false).is_err());
+        let expected: Vec<u8> = [[1; 10], [0; 10], [1; 10]].concat();
+        assert_eq!(write(&lob, true).unwrap(), expected);
+
+        let mut data_blocks = blocks(&data, 10);
+        data_blocks.push((15, PageBytes::borrowed(&data[..10])));
+        assert!(write(&LobDataBlocks { data_blocks }, true).is_err());
+    }
+
+    fn lob_record(blob_id: u64, ty: u16, rest: &[u8]) -> Vec<u8> {
+        let mut fixed = blob_id.to_le_bytes().to_vec();
+        fixed.extend_from_slice(&ty.to_le_bytes());
+        fixed.extend_from_slice(rest);
+        let mut rec = vec![0x08, 0];
+        rec.extend_from_slice(&(4 + fixed.len() as u16).to_le_bytes());
+        rec.extend(fixed);
+        // column count
+        rec.extend_from_slice(&[0, 0]);
+        rec
+    }
+
+    fn record_ptr(slot_id: u16) -> Vec<u8> {
+        let mut data = PTR.page_id.to_le_bytes().to_vec();
+        data.extend_from_slice(&PTR.file_id.to_le_bytes());
+        data.extend_from_slice(&slot_id.to_le_bytes());
+        data
+    }
+
+    // a root of type `ty` linking to `links`, given as (size / offset, slot)
+    fn node(blob_id: u64, ty: u16, links: &[(u64, u16)]) -> Vec<u8> {
+        let mut rest = vec![];
+        for count in [links.len() as u16, links.len() as u16, 0] {
+            rest.extend_from_slice(&count.to_le_bytes());
+        }
+        if ty == 5 {
+            rest.extend_from_slice(&[0; 4]);
         }
+        for (offset, slot_id) in links {
+            if ty == 4 || ty == 5 {
+                rest.extend_from_slice(&(*offset as u32).to_le_bytes());
+            } else {
+                rest.extend_from_slice(&offset.to_le_bytes());
+            }
+            rest.extend(record_ptr(*slot_id));
+        }
+        lob_record(blob_id, ty, &rest)
+    }
+
+    fn slot(slot_id: u16) -> RecordPointer {
+        RecordPointer::parse(&record_ptr(slot_id)).unwrap()
+    }
+
+    #[test]
+    fn large_roots() {
+        let pp = page_of_type(
+            PageType::TextMix,
+            vec![
+                lob_record(1, 3, b"abc"),
+                lob_record(1, 3, b"def"),
+                node(1, 4, &[(3, 0), (6, 1)]),
+                node(1, 5, &[(3, 0), (6, 1)]),
+                node(1, 6, &[(3, 0), (6, 1)]),
+                node(1, 2, &[(3, 0), (6, 1)]),
+            ],
+        );
+
+        for root in 2..6 {
+            let lob = LobPointer::from_root(slot(root));
+            assert_eq!(lob.read(&pp).unwrap().to_vec(), b"abcdef", "root {}", root);
+            let mut data = vec![];
+            lob.reader(&pp).read_to_end(&mut data).unwrap();
+            assert_eq!(data, b"abcdef", "root {}", root);
+
+            let entry = LobEntry::parse(pp.get_record(slot(root)).unwrap()).unwrap();
+            assert_eq!(entry.sub_entries(&pp).count(), 2);
+        }
+
+        // entries containing data have no links
+        let data = LobEntry::parse(pp.get_record(slot(0)).unwrap()).unwrap();
+        assert!(data.node().is_none());
+        assert!(data.sub_entries(&pp).next().is_none());
+    }
+
+    // a Yukon root linking to an internal node linking to the data, and an unrelated
+    // internal node
+    fn lob_tree() -> MemoryPageProvider {
+        page_of_type(
+            PageType::TextMix,
+            vec![
+                lob_record(1, 3, b"abc"),
+                lob_record(1, 3, b"def"),
+                node(1, 2, &[(3, 0), (6, 1)]),
+                node(1, 5, &[(6, 2)]),
+                node(2, 2, &[(3, 0)]),
+            ],
+        )
+    }
+
+    #[test]
+    fn lob_root_index() {
+        let pp = lob_tree();
+        let index = LobRootIndex::build(&pp);
+
+        let ptrs: Vec<_> = index.entries.iter().map(|entry| entry.ptr).collect();
+        assert_eq!(ptrs, vec![slot(2), slot(3), slot(4)]);
+        assert_eq!(index.entries[0].links, vec![slot(0), slot(1)]);
+        assert_eq!(index.entries[1].links, vec![slot(2)]);
+
+        let roots: Vec<_> = index.real_roots().iter().map(|entry| entry.ptr).collect();
+        assert_eq!(roots, vec![slot(3), slot(4)]);
+        let lob = LobPointer::from_root(roots[0]).read(&pp).unwrap();
+        assert_eq!(lob.to_vec(), b"abcdef");
+    }
+
+    #[test]
+    fn lob_root_index_save_and_load() {
+        let path = std::env::temp_dir().join(format!("mdf-lob-root-index-{}", std::process::id()));
+        let index = LobRootIndex::build(&lob_tree());
+        index.save(&path).unwrap();
+        let loaded = LobRootIndex::load(&path);
+
+        // like the bincode files written by older versions
+        std::fs::write(&path, [3, 0, 0, 0, 0, 0, 0, 0, 1, 2]).unwrap();
+        let stale = LobRootIndex::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.entries.len(), index.entries.len());
+        for (loaded, entry) in loaded.entries.iter().zip(&index.entries) {
+            assert_eq!(loaded.ptr, entry.ptr);
+            assert_eq!(loaded.blob_id, entry.blob_id);
+            assert_eq!(loaded.level, entry.level);
+            assert_eq!(loaded.links, entry.links);
+        }
+        assert_eq!(stale.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_root() {
+        // claims two links, but only stores one
+        let mut root = node(1, 6, &[(3, 0)]);
+        root[4 + 12] = 2;
+        let pp = page_of_type(PageType::TextMix, vec![lob_record(1, 3, b"abc"), root]);
+
+        let entry = LobEntry::parse(pp.get_record(slot(1)).unwrap()).unwrap();
+        assert_eq!(entry.node().unwrap().cur_links, 2);
+        assert_eq!(entry.sub_entries(&pp).count(), 1);
     }
 }
@@ -1,6 +1,46 @@
-use crate::util::parse_utf16_string;
-use crate::{PagePointer, PageProvider, PageType, RawPage};
-use byteorder::{LittleEndian, ReadBytesExt};
+use crate::util::{parse_utf16_string, read_u16_at, read_u32_at, read_u64_at, slice};
+use crate::{
+    MdfError, PagePointer, PageProvider, PageType, RawPage, PAGES_PER_EXTENT,
+    PAGES_PER_GAM_INTERVAL, PAGES_PER_PFS_INTERVAL,
+};
+use bitflags::bitflags;
+use log::error;
+
+// The SQL Server release a database was written by, some on disk structures
+// (like the system tables) differ between them. LOB records don't need it,
+// their type tells the SQL Server 2000 (Shiloh) and 2005 (Yukon) roots apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ServerVersion {
+    Sql2000,
+    Sql2005,
+    Sql2008,
+    Sql2008R2,
+    Sql2012,
+    Sql2014,
+    Sql2016,
+    Sql2017,
+    Sql2019,
+    Sql2022,
+}
+
+impl ServerVersion {
+    // From the internal database version stored in the boot page,
+    // versions newer than the ones we know are assumed to be like the newest one
+    pub fn from_db_version(version: u16) -> Self {
+        match version {
+            0..=539 => Self::Sql2000,
+            540..=612 => Self::Sql2005,
+            613..=655 => Self::Sql2008,
+            656..=661 => Self::Sql2008R2,
+            662..=706 => Self::Sql2012,
+            707..=782 => Self::Sql2014,
+            783..=852 => Self::Sql2016,
+            853..=869 => Self::Sql2017,
+            870..=904 => Self::Sql2019,
+            _ => Self::Sql2022,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct BootPage {
@@ -10,33 +50,240 @@ pub struct BootPage {
     next_id: u32,
     database_name: String,
     db_id: u16,
-    max_db_timestamp: u64,
+    compatibility_level: u8,
+    pub max_db_timestamp: u64,
     pub first_sys_indices: PagePointer,
 }
 
 impl BootPage {
-    pub fn parse<T: PageProvider>(page: RawPage<T>) -> Self {
-        assert_eq!(page.header.ty, PageType::Boot);
-
-        let data = page.record(0).unwrap().fixed_data;
-        let version = (&data[..2]).read_u16::<LittleEndian>().unwrap();
-        let create_version = (&data[2..4]).read_u16::<LittleEndian>().unwrap();
-        let status = (&data[32..36]).read_u32::<LittleEndian>().unwrap();
-        let next_id = (&data[36..40]).read_u32::<LittleEndian>().unwrap();
-        let database_name = parse_utf16_string(&data[48..304]);
-        let db_id = (&data[308..310]).read_u16::<LittleEndian>().unwrap();
-        let max_db_timestamp = (&data[312..320]).read_u64::<LittleEndian>().unwrap();
-        let first_sys_indices = PagePointer::parse(&data[512..518]).unwrap();
-
-        Self {
+    pub fn parse<T: PageProvider>(page: RawPage<T>) -> Result<Self, MdfError> {
+        if page.header.ty != PageType::Boot {
+            return Err(MdfError::Corrupt(format!(
+                "expected the boot page, got {:?}",
+                page.header.ty
+            )));
+        }
+
+        let data = page.try_record(0)?.fixed_data;
+        let u16_at = |offset| read_u16_at(&data, offset).ok_or(MdfError::UnexpectedEnd);
+        let u32_at = |offset| read_u32_at(&data, offset).ok_or(MdfError::UnexpectedEnd);
+        let version = u16_at(0)?;
+        let create_version = u16_at(2)?;
+        let status = u32_at(32)?;
+        let next_id = u32_at(36)?;
+        let database_name = parse_utf16_string(slice(&data, 48..304)?);
+        let db_id = u16_at(308)?;
+        let compatibility_level = *slice(&data, 310)?;
+        let max_db_timestamp = read_u64_at(&data, 312).ok_or(MdfError::UnexpectedEnd)?;
+        let first_sys_indices = PagePointer::parse(slice(&data, 512..518)?).ok_or_else(|| {
+            MdfError::Corrupt("boot page without pointer to sysallocunits".to_string())
+        })?;
+
+        Ok(Self {
             version,
             create_version,
             status,
             next_id,
             database_name,
             db_id,
+            compatibility_level,
             max_db_timestamp,
             first_sys_indices,
+        })
+    }
+
+    // The internal database version of the SQL Server that last wrote the database
+    // (for example 661 for SQL Server 2008 R2)
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    // The internal database version of the SQL Server that created the database
+    pub fn create_version(&self) -> u16 {
+        self.create_version
+    }
+
+    // The SQL Server release that last wrote the database, which determines its layout
+    pub fn server_version(&self) -> ServerVersion {
+        ServerVersion::from_db_version(self.version)
+    }
+
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    pub fn next_id(&self) -> u32 {
+        self.next_id
+    }
+
+    pub fn database_name(&self) -> &str {
+        // stored as a nchar(128), padded with spaces
+        self.database_name.trim_end()
+    }
+
+    pub fn db_id(&self) -> u16 {
+        self.db_id
+    }
+
+    // Like `ALTER DATABASE ... SET COMPATIBILITY_LEVEL`, for example 100 for SQL Server 2008
+    pub fn compatibility_level(&self) -> u8 {
+        self.compatibility_level
+    }
+}
+
+// Index Allocation Map, tracks which pages of one GAM interval belong to an allocation unit.
+// The IAM pages of an allocation unit are linked using `next_page_ptr`, starting at the
+// `pg_firstiam` of the allocation unit.
+#[derive(Debug)]
+pub struct IamPage {
+    // first page of the GAM interval this IAM page covers
+    pub start_page: PagePointer,
+    // the first 8 pages of an allocation unit are allocated from mixed extents
+    pub single_page_slots: Vec<PagePointer>,
+    // uniform extents of the GAM interval belonging to the allocation unit
+    pub extents: Vec<PagePointer>,
+}
+
+impl IamPage {
+    const SINGLE_PAGE_SLOTS: usize = 8;
+
+    pub fn parse<T: PageProvider>(page: &RawPage<T>) -> Result<Self, MdfError> {
+        if page.header.ty != PageType::IAM {
+            return Err(MdfError::Corrupt(format!(
+                "expected an IAM page, got {:?}",
+                page.header.ty
+            )));
+        }
+
+        // slot 0 is the IAM header, the single page slots follow the start page
+        let header = page.slot_data(0)?;
+        let start_page = PagePointer::parse(slice(&header, 40..46)?)
+            .ok_or_else(|| MdfError::Corrupt("IAM page without start page".to_string()))?;
+        let single_page_slots = slice(&header, 46..46 + 6 * Self::SINGLE_PAGE_SLOTS)?
+            .chunks(6)
+            .filter_map(PagePointer::parse)
+            .collect();
+
+        // slot 1 is a bitmap with one bit per extent of the GAM interval
+        let bitmap_len = (PAGES_PER_GAM_INTERVAL / PAGES_PER_EXTENT / 8) as usize;
+        let bitmap = page.slot_data(1)?.slice(4..4 + bitmap_len)?;
+        let extents = (0..bitmap.len() * 8)
+            .filter(|bit| bitmap[bit / 8] & (1 << (bit % 8)) != 0)
+            .map(|bit| PagePointer {
+                file_id: start_page.file_id,
+                page_id: start_page.page_id + bit as u32 * PAGES_PER_EXTENT,
+            })
+            .collect();
+
+        Ok(Self {
+            start_page,
+            single_page_slots,
+            extents,
+        })
+    }
+
+    // All the pages this IAM page tracks
+    pub fn pages(&self) -> impl Iterator<Item = PagePointer> + '_ {
+        self.single_page_slots
+            .iter()
+            .copied()
+            .chain(self.extents.iter().flat_map(|extent| {
+                (0..PAGES_PER_EXTENT).map(move |i| PagePointer {
+                    file_id: extent.file_id,
+                    page_id: extent.page_id + i,
+                })
+            }))
+    }
+
+    // Follows the IAM chain starting at `first_iam`
+    pub fn chain<T: PageProvider>(
+        page_provider: &T,
+        first_iam: PagePointer,
+    ) -> impl Iterator<Item = IamPage> + '_ {
+        let mut next = Some(first_iam);
+        std::iter::from_fn(move || loop {
+            let ptr = next?;
+            let page = page_provider.get(ptr)?;
+            next = page.header.next_page_ptr();
+            match Self::parse(&page) {
+                Ok(iam) => return Some(iam),
+                Err(e) => error!("skipping IAM page {:?}: {}", ptr, e),
+            }
+        })
+    }
+}
+
+bitflags! {
+    // The lowest three bits store how full the page is, see `PfsStatus::fullness`
+    pub struct PfsStatus: u8 {
+        const HAS_GHOST_RECORDS = 1 << 3;
+        const IAM_PAGE          = 1 << 4;
+        const MIXED_EXTENT      = 1 << 5;
+        const ALLOCATED         = 1 << 6;
+    }
+}
+
+impl PfsStatus {
+    pub fn is_allocated(&self) -> bool {
+        self.contains(Self::ALLOCATED)
+    }
+
+    pub fn has_ghost_records(&self) -> bool {
+        self.contains(Self::HAS_GHOST_RECORDS)
+    }
+
+    // Upper bound of how full the page is, in percent. Only tracked for heap and LOB pages
+    pub fn fullness(&self) -> u8 {
+        match self.bits() & 0x7 {
+            0 => 0,
+            1 => 50,
+            2 => 80,
+            3 => 95,
+            _ => 100,
+        }
+    }
+}
+
+// Page Free Space, stores one status byte for each of the pages of its interval
+#[derive(Debug)]
+pub struct PfsPage {
+    // first page of the interval this PFS page covers
+    pub start_page: PagePointer,
+    status: Vec<PfsStatus>,
+}
+
+impl PfsPage {
+    pub fn parse<T: PageProvider>(page: &RawPage<T>) -> Result<Self, MdfError> {
+        if page.header.ty != PageType::PFS {
+            return Err(MdfError::Corrupt(format!(
+                "expected a PFS page, got {:?}",
+                page.header.ty
+            )));
+        }
+
+        let ptr = page.header.ptr;
+        let bytes = page
+            .slot_data(0)?
+            .slice(4..4 + PAGES_PER_PFS_INTERVAL as usize)?;
+        Ok(Self {
+            start_page: PagePointer {
+                page_id: ptr.page_id - ptr.page_id % PAGES_PER_PFS_INTERVAL,
+                file_id: ptr.file_id,
+            },
+            status: bytes
+                .iter()
+                .map(|byte| PfsStatus::from_bits_truncate(*byte))
+                .collect(),
+        })
+    }
+
+    // The status of `ptr`, `None` if it is not tracked by this page
+    pub fn status(&self, ptr: PagePointer) -> Option<PfsStatus> {
+        if ptr.file_id != self.start_page.file_id || ptr.page_id < self.start_page.page_id {
+            return None;
         }
+        self.status
+            .get((ptr.page_id - self.start_page.page_id) as usize)
+            .copied()
     }
 }
@@ -0,0 +1,889 @@
+use crate::{PageBytes, PageHeader, PagePointer, PageProvider, PageType, RawPage, PAGE_SIZE};
+use log::{error, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+// Reads the pages of a detached database directly from its `.mdf` (and `.ndf`) files
+// Every access reads the page from the file again, wrap the provider in a `CachingPageProvider`
+// to keep recently used pages in memory
+pub struct FilePageProvider<R> {
+    // keyed by file_id, the primary `.mdf` file has file_id 1
+    files: BTreeMap<u16, DataFile<R>>,
+    page_size: usize,
+}
+
+struct DataFile<R> {
+    reader: Mutex<R>,
+    len: u64,
+    num_pages: u32,
+}
+
+impl<R: Read + Seek> DataFile<R> {
+    fn new(mut reader: R, page_size: usize) -> std::io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        // a truncated last page is still read, padded with zeros
+        let num_pages = len.div_ceil(page_size as u64) as u32;
+        if len % page_size as u64 != 0 {
+            warn!(
+                "file length {} is not a multiple of the page size, the last page is truncated",
+                len
+            );
+        }
+
+        Ok(Self {
+            reader: Mutex::new(reader),
+            len,
+            num_pages,
+        })
+    }
+
+    // `count` consecutive pages starting at `first`, with a single seek and read
+    fn read_pages(
+        &self,
+        first: PagePointer,
+        count: usize,
+        page_size: usize,
+    ) -> std::io::Result<Arc<[u8]>> {
+        let offset = first.byte_offset(page_size);
+        let mut data = vec![0; count * page_size];
+        let available = (data.len() as u64).min(self.len.saturating_sub(offset)) as usize;
+
+        // a panic while reading leaves the reader usable, as every read seeks first
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut data[..available])?;
+        Ok(data.into())
+    }
+}
+
+impl FilePageProvider<File> {
+    // a database consisting of only a `.mdf` file
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+
+    // a database split across multiple files, given as (file_id, path) pairs
+    pub fn open_files<P: AsRef<Path>>(
+        paths: impl IntoIterator<Item = (u16, P)>,
+    ) -> std::io::Result<Self> {
+        Self::with_files(
+            paths
+                .into_iter()
+                .map(|(file_id, path)| Ok((file_id, File::open(path)?)))
+                .collect::<std::io::Result<Vec<_>>>()?,
+        )
+    }
+}
+
+impl<R: Read + Seek> FilePageProvider<R> {
+    pub fn new(reader: R) -> std::io::Result<Self> {
+        Self::with_files(vec![(1, reader)])
+    }
+
+    pub fn with_files(readers: impl IntoIterator<Item = (u16, R)>) -> std::io::Result<Self> {
+        Self::with_page_size(readers, PAGE_SIZE)
+    }
+
+    // files of a engine using a different page size than SQL Server
+    pub fn with_page_size(
+        readers: impl IntoIterator<Item = (u16, R)>,
+        page_size: usize,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            files: readers
+                .into_iter()
+                .map(|(file_id, reader)| Ok((file_id, DataFile::new(reader, page_size)?)))
+                .collect::<std::io::Result<_>>()?,
+            page_size,
+        })
+    }
+}
+
+impl<R: Read + Seek> PageProvider for FilePageProvider<R> {
+    fn file_ids(&self) -> Vec<u16> {
+        self.files.keys().copied().collect()
+    }
+
+    fn num_pages(&self, file_id: u16) -> u32 {
+        self.files.get(&file_id).map_or(0, |file| file.num_pages)
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn get(&self, ptr: PagePointer) -> Option<RawPage<Self>> {
+        self.get_many(&[ptr]).pop().flatten()
+    }
+
+    // Consecutive pages are read with a single read, all of them sharing one buffer
+    fn get_many(&self, ptrs: &[PagePointer]) -> Vec<Option<RawPage<Self>>> {
+        let mut pages = Vec::with_capacity(ptrs.len());
+        for run in contiguous_runs(ptrs) {
+            let first = run[0];
+            let count = self.files.get(&first.file_id).map_or(0, |file| {
+                (file.num_pages.saturating_sub(first.page_id) as usize).min(run.len())
+            });
+            let data = if count == 0 {
+                None
+            } else {
+                self.files[&first.file_id]
+                    .read_pages(first, count, self.page_size)
+                    .map_err(|e| error!("could not read pages {:?}: {}", run, e))
+                    .ok()
+            };
+
+            pages.extend(run.iter().enumerate().map(|(idx, ptr)| {
+                let data = PageBytes::shared(data.clone()?)
+                    .slice(idx * self.page_size..(idx + 1) * self.page_size)
+                    .ok()?;
+                parse_page(*ptr, data, self)
+            }));
+        }
+        pages
+    }
+}
+
+// Splits `ptrs` into runs of consecutive pages of the same file
+fn contiguous_runs(ptrs: &[PagePointer]) -> impl Iterator<Item = &[PagePointer]> {
+    let mut rest = ptrs;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let len = 1 + rest
+            .windows(2)
+            .take_while(|w| {
+                w[1].file_id == w[0].file_id && Some(w[1].page_id) == w[0].page_id.checked_add(1)
+            })
+            .count();
+        let (run, tail) = rest.split_at(len);
+        rest = tail;
+        Some(run)
+    })
+}
+
+// The page stored in `data`, `None` for pages that were never written (they are all zeros)
+// or could not be parsed
+pub(crate) fn parse_page<'a, T: PageProvider>(
+    ptr: PagePointer,
+    data: impl Into<PageBytes<'a>>,
+    page_provider: &'a T,
+) -> Option<RawPage<'a, T>> {
+    let data = data.into();
+    PageHeader::parse_ptr(&data)?;
+    RawPage::parse(data, page_provider)
+        .map_err(|e| error!("could not parse page {:?}: {}", ptr, e))
+        .ok()
+}
+
+// Memory maps the `.mdf` (and `.ndf`) files, the returned `RawPage`s borrow directly from the mapping
+// A truncated last page is copied and padded with zeros
+#[cfg(feature = "memmap2")]
+pub struct MmapPageProvider {
+    // keyed by file_id, the primary `.mdf` file has file_id 1
+    files: BTreeMap<u16, memmap2::Mmap>,
+    page_size: usize,
+}
+
+#[cfg(feature = "memmap2")]
+impl MmapPageProvider {
+    // a database consisting of only a `.mdf` file
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::open_files(vec![(1, path)])
+    }
+
+    // a database split across multiple files, given as (file_id, path) pairs
+    pub fn open_files<P: AsRef<Path>>(
+        paths: impl IntoIterator<Item = (u16, P)>,
+    ) -> std::io::Result<Self> {
+        Self::with_files(
+            paths
+                .into_iter()
+                .map(|(file_id, path)| Ok((file_id, File::open(path)?)))
+                .collect::<std::io::Result<Vec<_>>>()?,
+        )
+    }
+
+    pub fn with_files(files: impl IntoIterator<Item = (u16, File)>) -> std::io::Result<Self> {
+        Self::with_page_size(files, PAGE_SIZE)
+    }
+
+    // files of a engine using a different page size than SQL Server
+    pub fn with_page_size(
+        files: impl IntoIterator<Item = (u16, File)>,
+        page_size: usize,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            files: files
+                .into_iter()
+                .map(|(file_id, file)| {
+                    // SAFETY: the file must not be modified while it is mapped, which is
+                    // the case for the (detached) database files this is meant for
+                    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+                    if mmap.len() % page_size != 0 {
+                        warn!(
+                            "file length {} is not a multiple of the page size, the last page is truncated",
+                            mmap.len()
+                        );
+                    }
+                    Ok((file_id, mmap))
+                })
+                .collect::<std::io::Result<_>>()?,
+            page_size,
+        })
+    }
+}
+
+#[cfg(feature = "memmap2")]
+impl PageProvider for MmapPageProvider {
+    fn file_ids(&self) -> Vec<u16> {
+        self.files.keys().copied().collect()
+    }
+
+    fn num_pages(&self, file_id: u16) -> u32 {
+        self.files
+            .get(&file_id)
+            .map_or(0, |mmap| mmap.len().div_ceil(self.page_size) as u32)
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn get(&self, ptr: PagePointer) -> Option<RawPage<Self>> {
+        self.get_many(&[ptr]).pop().flatten()
+    }
+
+    // Consecutive pages are sliced out of the mapping at once
+    fn get_many(&self, ptrs: &[PagePointer]) -> Vec<Option<RawPage<Self>>> {
+        let mut pages = Vec::with_capacity(ptrs.len());
+        for run in contiguous_runs(ptrs) {
+            let first = run[0];
+            let count = (self.num_pages(first.file_id).saturating_sub(first.page_id) as usize)
+                .min(run.len());
+            let start = first.byte_offset(self.page_size) as usize;
+            let data = match self.files.get(&first.file_id) {
+                Some(mmap) if count > 0 => {
+                    &mmap[start..(start + count * self.page_size).min(mmap.len())]
+                }
+                _ => &[],
+            };
+
+            pages.extend(run.iter().enumerate().map(|(idx, ptr)| {
+                let page = data.get(idx * self.page_size..)?;
+                let page = match page.get(..self.page_size) {
+                    Some(page) => PageBytes::borrowed(page),
+                    None if idx < count => {
+                        // the truncated last page
+                        let mut padded = page.to_vec();
+                        padded.resize(self.page_size, 0);
+                        PageBytes::shared(padded.into())
+                    }
+                    None => return None,
+                };
+                parse_page(*ptr, page, self)
+            }));
+        }
+        pages
+    }
+}
+
+// Keeps all pages in memory, mostly useful for tests using synthetic pages (see `PageBuilder`)
+#[derive(Debug)]
+pub struct MemoryPageProvider {
+    // keyed by (file_id, page_id)
+    pages: BTreeMap<(u16, u32), Box<[u8]>>,
+    page_size: usize,
+}
+
+impl Default for MemoryPageProvider {
+    fn default() -> Self {
+        Self::with_page_size(PAGE_SIZE)
+    }
+}
+
+impl MemoryPageProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // For pages that are not 8192 bytes
+    pub fn with_page_size(page_size: usize) -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            page_size,
+        }
+    }
+
+    pub fn with_page(mut self, ptr: PagePointer, data: Vec<u8>) -> Self {
+        self.insert_page(ptr, data);
+        self
+    }
+
+    pub fn insert_page(&mut self, ptr: PagePointer, data: Vec<u8>) {
+        assert_eq!(
+            data.len(),
+            self.page_size,
+            "pages have to be {} bytes",
+            self.page_size
+        );
+        self.pages.insert(ptr.as_tuple(), data.into_boxed_slice());
+    }
+}
+
+impl PageProvider for MemoryPageProvider {
+    fn file_ids(&self) -> Vec<u16> {
+        let mut ids: Vec<_> = self.pages.keys().map(|(file_id, _)| *file_id).collect();
+        ids.dedup();
+        ids
+    }
+
+    // one past the highest page id, pages inbetween that were not inserted are missing
+    fn num_pages(&self, file_id: u16) -> u32 {
+        self.pages
+            .range((file_id, 0)..=(file_id, u32::MAX))
+            .next_back()
+            .map_or(0, |((_, page_id), _)| page_id + 1)
+    }
+
+    fn get(&self, ptr: PagePointer) -> Option<RawPage<Self>> {
+        self.pages
+            .get(&ptr.as_tuple())
+            .and_then(|data| RawPage::parse(&**data, self).ok())
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+}
+
+// Assembles the bytes of a synthetic page from a header and a list of records
+pub struct PageBuilder {
+    ptr: PagePointer,
+    ty: PageType,
+    level: u8,
+    index_id: u16,
+    p_min_len: u16,
+    object_id: u32,
+    prev_page_ptr: Option<PagePointer>,
+    next_page_ptr: Option<PagePointer>,
+    records: Vec<Vec<u8>>,
+    page_size: usize,
+}
+
+impl PageBuilder {
+    pub fn new(ptr: PagePointer, ty: PageType) -> Self {
+        Self {
+            ptr,
+            ty,
+            level: 0,
+            index_id: 0,
+            p_min_len: 0,
+            object_id: 0,
+            prev_page_ptr: None,
+            next_page_ptr: None,
+            records: vec![],
+            page_size: PAGE_SIZE,
+        }
+    }
+
+    pub fn level(mut self, level: u8) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn owner(mut self, object_id: u32, index_id: u16) -> Self {
+        self.object_id = object_id;
+        self.index_id = index_id;
+        self
+    }
+
+    pub fn p_min_len(mut self, p_min_len: u16) -> Self {
+        self.p_min_len = p_min_len;
+        self
+    }
+
+    pub fn prev_page(mut self, ptr: PagePointer) -> Self {
+        self.prev_page_ptr = Some(ptr);
+        self
+    }
+
+    pub fn next_page(mut self, ptr: PagePointer) -> Self {
+        self.next_page_ptr = Some(ptr);
+        self
+    }
+
+    // has to match the `page_size` of the provider the page is used with
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    // the raw bytes of a record, starting with the status bits
+    pub fn record(mut self, data: Vec<u8>) -> Self {
+        self.records.push(data);
+        self
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        fn write_ptr(data: &mut [u8], ptr: Option<PagePointer>) {
+            if let Some(ptr) = ptr {
+                data[0..4].copy_from_slice(&ptr.page_id.to_le_bytes());
+                data[4..6].copy_from_slice(&ptr.file_id.to_le_bytes());
+            }
+        }
+
+        let mut data = vec![0; self.page_size];
+        // header version
+        data[0] = 1;
+        data[1] = self.ty.id();
+        data[3] = self.level;
+        data[6..8].copy_from_slice(&self.index_id.to_le_bytes());
+        write_ptr(&mut data[8..14], self.prev_page_ptr);
+        data[14..16].copy_from_slice(&self.p_min_len.to_le_bytes());
+        write_ptr(&mut data[16..22], self.next_page_ptr);
+        data[22..24].copy_from_slice(&(self.records.len() as u16).to_le_bytes());
+        data[24..28].copy_from_slice(&self.object_id.to_le_bytes());
+        write_ptr(&mut data[32..38], Some(self.ptr));
+
+        // the records start after the 96 byte header,
+        // the slot array grows backwards from the end of the page
+        let mut offset = 96;
+        for (idx, record) in self.records.iter().enumerate() {
+            let slot = self.page_size - 2 * idx - 2;
+            assert!(
+                offset + record.len() <= slot,
+                "records do not fit into the page"
+            );
+            data[offset..offset + record.len()].copy_from_slice(record);
+            data[slot..slot + 2].copy_from_slice(&(offset as u16).to_le_bytes());
+            offset += record.len();
+        }
+
+        data
+    }
+}
+
+// Fixtures shared by the tests of the other modules
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    pub const PTR: PagePointer = PagePointer {
+        page_id: 1,
+        file_id: 1,
+    };
+
+    // A provider with a single data page at `PTR` containing `records`
+    pub fn page(records: Vec<Vec<u8>>) -> MemoryPageProvider {
+        page_of_type(PageType::Data, records)
+    }
+
+    pub fn page_of_type(ty: PageType, records: Vec<Vec<u8>>) -> MemoryPageProvider {
+        let builder = records
+            .into_iter()
+            .fold(PageBuilder::new(PTR, ty), PageBuilder::record);
+        MemoryPageProvider::new().with_page(PTR, builder.build())
+    }
+
+    // A primary record with a null bitmap (no column NULL) and variable length columns
+    pub fn record(fixed: &[u8], column_count: u16, var_columns: &[&[u8]]) -> Vec<u8> {
+        let mut rec = vec![0x30, 0];
+        rec.extend_from_slice(&(4 + fixed.len() as u16).to_le_bytes());
+        rec.extend_from_slice(fixed);
+        rec.extend_from_slice(&column_count.to_le_bytes());
+        rec.extend(std::iter::repeat_n(0, (column_count as usize).div_ceil(8)));
+        rec.extend_from_slice(&(var_columns.len() as u16).to_le_bytes());
+        let mut end = rec.len() + 2 * var_columns.len();
+        for data in var_columns {
+            end += data.len();
+            rec.extend_from_slice(&(end as u16).to_le_bytes());
+        }
+        for data in var_columns {
+            rec.extend_from_slice(data);
+        }
+        rec
+    }
+}
+
+// Wraps another provider and keeps copies of the most recently used pages
+//
+// The returned `RawPage`s share ownership of the page data, so evicted pages are freed as soon
+// as no page, record or value read from them is left. A capacity of 0 disables caching
+pub struct CachingPageProvider<T> {
+    inner: T,
+    capacity: usize,
+    cache: Mutex<LruCache>,
+}
+
+type CacheEntry = (u64, Arc<[u8]>);
+
+#[derive(Default)]
+struct LruCache {
+    tick: u64,
+    // (file_id, page_id) -> (last use, data)
+    entries: HashMap<(u16, u32), CacheEntry>,
+    // last use -> (file_id, page_id)
+    lru: BTreeMap<u64, (u16, u32)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<T: PageProvider> CachingPageProvider<T> {
+    pub fn new(inner: T, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            cache: Mutex::new(LruCache::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // (hits, misses)
+    pub fn stats(&self) -> (u64, u64) {
+        let cache = self.cache();
+        (cache.hits, cache.misses)
+    }
+
+    // the inner provider is not called while the lock is held, so a poisoned cache is
+    // still consistent
+    fn cache(&self) -> std::sync::MutexGuard<'_, LruCache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lookup(&self, ptr: PagePointer) -> Option<Arc<[u8]>> {
+        let key = ptr.as_tuple();
+        {
+            let mut cache = self.cache();
+            let cache = &mut *cache;
+            cache.tick += 1;
+            let tick = cache.tick;
+
+            if let Some((last_use, data)) = cache.entries.get_mut(&key) {
+                cache.hits += 1;
+                cache.lru.remove(last_use);
+                cache.lru.insert(tick, key);
+                *last_use = tick;
+                return Some(data.clone());
+            }
+            cache.misses += 1;
+        }
+
+        // the lock is not held while reading, so other threads can use the cache meanwhile
+        let data: Arc<[u8]> = Arc::from(&*self.inner.get(ptr)?.data);
+        if self.capacity == 0 {
+            return Some(data);
+        }
+
+        let mut cache = self.cache();
+        let cache = &mut *cache;
+        if cache.entries.contains_key(&key) {
+            // read by another thread in the meantime
+            return Some(data);
+        }
+        if cache.entries.len() >= self.capacity {
+            if let Some((&oldest, _)) = cache.lru.iter().next() {
+                let evicted = cache.lru.remove(&oldest).unwrap();
+                cache.entries.remove(&evicted);
+            }
+        }
+        cache.tick += 1;
+        let tick = cache.tick;
+        cache.entries.insert(key, (tick, data.clone()));
+        cache.lru.insert(tick, key);
+
+        Some(data)
+    }
+}
+
+impl<T: PageProvider> PageProvider for CachingPageProvider<T> {
+    fn file_ids(&self) -> Vec<u16> {
+        self.inner.file_ids()
+    }
+
+    fn num_pages(&self, file_id: u16) -> u32 {
+        self.inner.num_pages(file_id)
+    }
+
+    fn page_size(&self) -> usize {
+        self.inner.page_size()
+    }
+
+    fn get(&self, ptr: PagePointer) -> Option<RawPage<Self>> {
+        RawPage::parse(self.lookup(ptr)?, self).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages(count: u32) -> MemoryPageProvider {
+        (0..count).fold(MemoryPageProvider::new(), |pp, page_id| {
+            let ptr = PagePointer {
+                page_id,
+                file_id: 1,
+            };
+            pp.with_page(ptr, PageBuilder::new(ptr, PageType::Data).build())
+        })
+    }
+
+    fn ptr(page_id: u32) -> PagePointer {
+        PagePointer {
+            page_id,
+            file_id: 1,
+        }
+    }
+
+    #[test]
+    fn file_provider_reads_pages_on_every_access() {
+        fn assert_sync<T: Sync>(_: &T) {}
+
+        let mut data = PageBuilder::new(ptr(0), PageType::Data).build();
+        data.extend(PageBuilder::new(ptr(1), PageType::Index).build());
+        // truncated last page
+        data.extend(&PageBuilder::new(ptr(2), PageType::Data).build()[..PAGE_SIZE / 2]);
+
+        let pp = FilePageProvider::new(std::io::Cursor::new(data)).unwrap();
+        assert_sync(&pp);
+        assert_eq!(pp.num_pages(1), 3);
+        assert_eq!(pp.get(ptr(1)).unwrap().header.ty, PageType::Index);
+        assert_eq!(pp.get(ptr(2)).unwrap().header.ptr, ptr(2));
+        assert!(pp.get(ptr(3)).is_none());
+    }
+
+    #[cfg(feature = "memmap2")]
+    #[test]
+    fn mmap_provider_pads_the_truncated_last_page() {
+        let mut data = PageBuilder::new(ptr(0), PageType::Data).build();
+        data.extend(PageBuilder::new(ptr(1), PageType::Index).build());
+        // truncated last page
+        data.extend(&PageBuilder::new(ptr(2), PageType::Data).build()[..PAGE_SIZE / 2]);
+        let path = std::env::temp_dir().join(format!("mdf-mmap-provider-{}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+
+        let pp = MmapPageProvider::open(&path).unwrap();
+        assert_eq!(pp.num_pages(1), 3);
+        assert_eq!(pp.get(ptr(1)).unwrap().header.ty, PageType::Index);
+        let last = pp.get(ptr(2)).unwrap();
+        assert_eq!(last.header.ptr, ptr(2));
+        assert_eq!(last.data.len(), PAGE_SIZE);
+        assert!(pp.get(ptr(3)).is_none());
+
+        let ptrs: Vec<_> = pp
+            .get_many(&[ptr(1), ptr(2), ptr(3), ptr(0)])
+            .iter()
+            .map(|page| page.as_ref().map(|page| page.header.ptr))
+            .collect();
+        assert_eq!(ptrs, vec![Some(ptr(1)), Some(ptr(2)), None, Some(ptr(0))]);
+
+        drop(last);
+        drop(pp);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_provider_reads_consecutive_pages_at_once() {
+        // counts the seeks, every read of `get_many` starts with one
+        struct Counting(std::io::Cursor<Vec<u8>>, Arc<Mutex<usize>>);
+
+        impl Read for Counting {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+
+        impl Seek for Counting {
+            fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+                *self.1.lock().unwrap() += 1;
+                self.0.seek(pos)
+            }
+        }
+
+        let mut data = vec![];
+        for page_id in 0..4 {
+            data.extend(PageBuilder::new(ptr(page_id), PageType::Data).build());
+        }
+        // truncated last page
+        data.extend(&PageBuilder::new(ptr(4), PageType::Index).build()[..PAGE_SIZE / 2]);
+
+        let seeks = Arc::new(Mutex::new(0));
+        let pp =
+            FilePageProvider::new(Counting(std::io::Cursor::new(data), seeks.clone())).unwrap();
+        *seeks.lock().unwrap() = 0;
+
+        let ptrs = vec![ptr(1), ptr(2), ptr(3), ptr(4), ptr(5), ptr(0)];
+        let pages = pp.get_many(&ptrs);
+        assert_eq!(
+            pages
+                .iter()
+                .map(|page| page.as_ref().map(|page| page.header.ptr))
+                .collect::<Vec<_>>(),
+            vec![
+                Some(ptr(1)),
+                Some(ptr(2)),
+                Some(ptr(3)),
+                Some(ptr(4)),
+                None,
+                Some(ptr(0))
+            ]
+        );
+        assert_eq!(pages[3].as_ref().unwrap().header.ty, PageType::Index);
+        // ptr(1) to ptr(5) and ptr(0)
+        assert_eq!(*seeks.lock().unwrap(), 2);
+    }
+
+    // a record with a single 4 byte fixed column
+    fn int_record(value: u32) -> Vec<u8> {
+        let mut data = vec![0x10, 0, 8, 0];
+        data.extend(value.to_le_bytes());
+        data.extend([1, 0, 0]);
+        data
+    }
+
+    fn fixed_values<T: PageProvider>(pp: &T, ptr: PagePointer) -> Vec<Vec<u8>> {
+        pp.get(ptr)
+            .unwrap()
+            .records()
+            .map(|record| record.fixed_data.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn short_headers_and_boot_pages_are_errors() {
+        let data = PageBuilder::new(ptr(0), PageType::Boot)
+            .record(int_record(1))
+            .build();
+        let short = MemoryPageProvider::with_page_size(48).with_page(ptr(0), data[..48].to_vec());
+        assert!(short.get(ptr(0)).is_none());
+
+        let pp = MemoryPageProvider::new().with_page(ptr(0), data);
+        assert!(matches!(
+            crate::BootPage::parse(pp.get(ptr(0)).unwrap()),
+            Err(crate::MdfError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn checksums_are_only_verified_on_full_pages() {
+        let page = |page_size: usize| {
+            let mut data = PageBuilder::new(ptr(0), PageType::Data)
+                .record(int_record(1))
+                .page_size(page_size)
+                .build();
+            data[4..6].copy_from_slice(&crate::PageFlags::HAS_CHECKSUM.bits().to_le_bytes());
+            data
+        };
+
+        let small = MemoryPageProvider::with_page_size(4096).with_page(ptr(0), page(4096));
+        assert_eq!(small.get(ptr(0)).unwrap().verify_checksum(), None);
+
+        let full = MemoryPageProvider::new().with_page(ptr(0), page(PAGE_SIZE));
+        assert_eq!(full.get(ptr(0)).unwrap().verify_checksum(), Some(false));
+    }
+
+    #[test]
+    fn explicit_page_size_of_8192_behaves_like_the_default() {
+        let build = |page_size: Option<usize>| {
+            let builder = (1..=3).fold(PageBuilder::new(ptr(0), PageType::Data), |b, value| {
+                b.record(int_record(value))
+            });
+            match page_size {
+                Some(page_size) => builder.page_size(page_size).build(),
+                None => builder.build(),
+            }
+        };
+
+        assert_eq!(build(None), build(Some(8192)));
+
+        let default = MemoryPageProvider::new().with_page(ptr(0), build(None));
+        let explicit =
+            MemoryPageProvider::with_page_size(8192).with_page(ptr(0), build(Some(8192)));
+        let values = fixed_values(&default, ptr(0));
+        assert_eq!(
+            values,
+            vec![vec![1, 0, 0, 0], vec![2, 0, 0, 0], vec![3, 0, 0, 0]]
+        );
+        assert_eq!(values, fixed_values(&explicit, ptr(0)));
+
+        let file = FilePageProvider::with_page_size(
+            vec![(1, std::io::Cursor::new(build(Some(8192))))],
+            8192,
+        )
+        .unwrap();
+        assert_eq!(values, fixed_values(&file, ptr(0)));
+    }
+
+    #[test]
+    fn smaller_pages_use_the_page_size_of_the_provider() {
+        let page = |page_id| {
+            PageBuilder::new(ptr(page_id), PageType::Data)
+                .page_size(4096)
+                .record(int_record(page_id + 1))
+                .build()
+        };
+        let mut data = page(0);
+        data.extend(page(1));
+
+        let pp =
+            FilePageProvider::with_page_size(vec![(1, std::io::Cursor::new(data))], 4096).unwrap();
+        assert_eq!(pp.num_pages(1), 2);
+        assert_eq!(fixed_values(&pp, ptr(1)), vec![vec![2, 0, 0, 0]]);
+
+        assert_eq!(ptr(3).byte_offset(4096), 3 * 4096);
+        assert_eq!(
+            PagePointer::from_byte_offset(1, 3 * 4096 + 100, 4096),
+            ptr(3)
+        );
+        assert_eq!(ptr(3).byte_offset(PAGE_SIZE), 3 * 8192);
+    }
+
+    #[test]
+    fn caching_provider_evicts_least_recently_used() {
+        let cache = CachingPageProvider::new(pages(4), 2);
+        // pages outlive their eviction, without keeping the evicted data in the cache
+        let first = cache.get(ptr(0)).unwrap();
+        for page_id in [1, 0, 2, 3, 0] {
+            assert_eq!(cache.get(ptr(page_id)).unwrap().header.ptr, ptr(page_id));
+        }
+        assert_eq!(first.header.ptr, ptr(0));
+        assert_eq!(cache.stats(), (1, 5));
+        assert_eq!(cache.cache().entries.len(), 2);
+    }
+
+    #[test]
+    fn caching_provider_is_shared_between_threads() {
+        let cache = CachingPageProvider::new(pages(4), 2);
+        std::thread::scope(|scope| {
+            for page_id in 0..4 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    assert_eq!(cache.get(ptr(page_id)).unwrap().header.ptr, ptr(page_id));
+                });
+            }
+        });
+        assert_eq!(cache.stats(), (0, 4));
+        assert_eq!(cache.cache().entries.len(), 2);
+    }
+
+    #[test]
+    fn caching_provider_with_capacity_zero_does_not_cache() {
+        let cache = CachingPageProvider::new(pages(1), 0);
+        for _ in 0..3 {
+            assert!(cache.get(ptr(0)).is_some());
+        }
+        assert_eq!(cache.stats(), (0, 3));
+        assert!(cache.cache().entries.is_empty());
+    }
+}
@@ -1,33 +1,107 @@
-use crate::Record;
-use byteorder::{LittleEndian, ReadBytesExt};
+use crate::util::{hex_dump, read_u16_at, read_u32_at, slice};
+use crate::{MdfError, Record};
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use derivative::Derivative;
 use log::{error, trace};
-use pretty_hex::{config_hex, HexConfig};
 use serde::{Deserialize, Serialize};
+use std::ops::{Bound, Deref, RangeBounds};
+use std::sync::Arc;
 
 pub const PAGE_SIZE: usize = 8192;
+// Pages are allocated in extents of 8 consecutive pages
+pub const PAGES_PER_EXTENT: u32 = 8;
+// Each GAM / SGAM page tracks the allocation of 63904 extents
+pub const PAGES_PER_GAM_INTERVAL: u32 = 63904 * PAGES_PER_EXTENT;
+// Each PFS page tracks the free space of 8088 pages
+pub const PAGES_PER_PFS_INTERVAL: u32 = 8088;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PagePointer {
     pub page_id: u32,
     pub file_id: u16,
 }
 
+// Ordered by file first, then by position in the file
+impl Ord for PagePointer {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_tuple().cmp(&other.as_tuple())
+    }
+}
+
+impl PartialOrd for PagePointer {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl PagePointer {
     pub(crate) fn parse(data: &[u8]) -> Option<Self> {
-        let file_id = (&data[4..6]).read_u16::<LittleEndian>().unwrap();
+        let file_id = read_u16_at(data, 4)?;
         if file_id == 0 {
             None
         } else {
             Some(Self {
-                page_id: (&data[0..4]).read_u32::<LittleEndian>().unwrap(),
+                page_id: read_u32_at(data, 0)?,
                 file_id,
             })
         }
     }
+
+    // (file_id, page_id)
+    pub fn as_tuple(&self) -> (u16, u32) {
+        (self.file_id, self.page_id)
+    }
+
+    pub fn from_tuple((file_id, page_id): (u16, u32)) -> Self {
+        Self { page_id, file_id }
+    }
+
+    // offset of the start of this page in its file, see `PageProvider::page_size`
+    pub fn byte_offset(&self, page_size: usize) -> u64 {
+        self.page_id as u64 * page_size as u64
+    }
+
+    // inverse of `byte_offset`, offsets inside of a page are rounded down to the page start
+    pub fn from_byte_offset(file_id: u16, offset: u64, page_size: usize) -> Self {
+        Self {
+            page_id: (offset / page_size as u64) as u32,
+            file_id,
+        }
+    }
+
+    pub fn extent_id(&self) -> u32 {
+        self.page_id / PAGES_PER_EXTENT
+    }
+
+    // index of the GAM interval (and thus of the GAM / SGAM page) this page is tracked in
+    pub fn gam_interval(&self) -> u32 {
+        self.page_id / PAGES_PER_GAM_INTERVAL
+    }
+
+    // the PFS page this page is tracked in, the first one is page 1, all the others are at
+    // the start of their interval
+    pub fn pfs_page(&self) -> PagePointer {
+        let interval = self.page_id / PAGES_PER_PFS_INTERVAL;
+        PagePointer {
+            page_id: if interval == 0 {
+                1
+            } else {
+                interval * PAGES_PER_PFS_INTERVAL
+            },
+            file_id: self.file_id,
+        }
+    }
+}
+
+// `file_id:page_id`, like the page ids SQL Server prints in its messages
+impl std::fmt::Display for PagePointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file_id, self.page_id)
+    }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct RecordPointer {
     pub page_ptr: PagePointer,
     pub slot_id: u16,
@@ -35,19 +109,33 @@ pub struct RecordPointer {
 
 impl RecordPointer {
     pub(crate) fn parse(data: &[u8]) -> Option<Self> {
-        let file_id = (&data[4..6]).read_u16::<LittleEndian>().unwrap();
-        if file_id == 0 {
-            None
-        } else {
-            Some(Self {
-                page_ptr: PagePointer::parse(&data[0..6]).unwrap(),
-                slot_id: (&data[6..8]).read_u16::<LittleEndian>().unwrap(),
-            })
+        Some(Self {
+            page_ptr: PagePointer::parse(slice(data, 0..6).ok()?)?,
+            slot_id: read_u16_at(data, 6)?,
+        })
+    }
+
+    // (file_id, page_id, slot_id)
+    pub fn as_tuple(&self) -> (u16, u32, u16) {
+        (self.page_ptr.file_id, self.page_ptr.page_id, self.slot_id)
+    }
+
+    pub fn from_tuple((file_id, page_id, slot_id): (u16, u32, u16)) -> Self {
+        Self {
+            page_ptr: PagePointer { page_id, file_id },
+            slot_id,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+// `file_id:page_id:slot_id`
+impl std::fmt::Display for RecordPointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.page_ptr, self.slot_id)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum PageType {
     UnAlloc,
     Data,
@@ -93,6 +181,30 @@ impl PageType {
             //            _ => panic!("unknown page type {}", ty)
         }
     }
+
+    // inverse of `parse`
+    pub fn id(&self) -> u8 {
+        match self {
+            PageType::UnAlloc => 0,
+            PageType::Data => 1,
+            PageType::Index => 2,
+            PageType::TextMix => 3,
+            PageType::TextTree => 4,
+            PageType::Sort => 7,
+            PageType::GAM => 8,
+            PageType::SGAM => 9,
+            PageType::IAM => 10,
+            PageType::PFS => 11,
+            PageType::Boot => 13,
+            PageType::FileHeader => 15,
+            PageType::DiffMap => 16,
+            PageType::MLMap => 17,
+            PageType::CheckDBTemp => 18,
+            PageType::AlterIndexTemp => 19,
+            PageType::PreAlloc => 20,
+            PageType::Unknown(unk) => *unk,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -110,21 +222,36 @@ pub struct PageHeader {
     pub index_id: u16,
     prev_page_ptr: Option<PagePointer>,
     next_page_ptr: Option<PagePointer>,
+    pub flags: PageFlags,
+    // the page checksum or the torn page bits, depending on `flags`
+    pub torn_bits: u32,
+}
+
+bitflags! {
+    pub struct PageFlags: u16 {
+        const TORN_PAGE_DETECTION = 0x100;
+        const HAS_CHECKSUM        = 0x200;
+    }
 }
 
 impl PageHeader {
-    fn parse(data: &[u8]) -> Self {
-        let ptr = Self::parse_ptr(data).unwrap();
-        let ty = PageType::parse(data[1]);
-        let level = data[3];
-        let index_id = (&data[6..8]).read_u16::<LittleEndian>().unwrap();
-        let p_min_len = (&data[14..16]).read_u16::<LittleEndian>().unwrap();
-        let slot_count = (&data[22..24]).read_u16::<LittleEndian>().unwrap();
-        let object_id = (&data[24..28]).read_u32::<LittleEndian>().unwrap();
-        let prev_page_ptr = PagePointer::parse(&data[8..14]);
-        let next_page_ptr = PagePointer::parse(&data[16..22]);
+    fn parse(data: &[u8]) -> Result<Self, MdfError> {
+        let ptr = Self::parse_ptr(data)
+            .ok_or_else(|| MdfError::Corrupt("page header without a page pointer".to_string()))?;
+        let u16_at = |offset| read_u16_at(data, offset).ok_or(MdfError::UnexpectedEnd);
+        let u32_at = |offset| read_u32_at(data, offset).ok_or(MdfError::UnexpectedEnd);
+        let ty = PageType::parse(*slice(data, 1)?);
+        let level = *slice(data, 3)?;
+        let index_id = u16_at(6)?;
+        let p_min_len = u16_at(14)?;
+        let slot_count = u16_at(22)?;
+        let object_id = u32_at(24)?;
+        let prev_page_ptr = PagePointer::parse(slice(data, 8..14)?);
+        let next_page_ptr = PagePointer::parse(slice(data, 16..22)?);
+        let flags = PageFlags::from_bits_truncate(u16_at(4)?);
+        let torn_bits = u32_at(60)?;
 
-        Self {
+        Ok(Self {
             ptr,
             ty,
             level,
@@ -134,20 +261,148 @@ impl PageHeader {
             object_id,
             next_page_ptr,
             prev_page_ptr,
-        }
+            flags,
+            torn_bits,
+        })
+    }
+
+    pub fn slot_count(&self) -> u16 {
+        self.slot_count
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn prev_page_ptr(&self) -> Option<PagePointer> {
+        self.prev_page_ptr
+    }
+
+    pub fn next_page_ptr(&self) -> Option<PagePointer> {
+        self.next_page_ptr
     }
 
     pub fn parse_ptr(data: &[u8]) -> Option<PagePointer> {
-        PagePointer::parse(&data[32..])
+        PagePointer::parse(data.get(32..)?)
+    }
+
+    // The object_id and index_id in the page header are not the ids of the table and index,
+    // but are derived from the allocation unit the page belongs to
+    // (see `SysAllocUnit::page_owner_ids`)
+    pub fn belongs_to(&self, object_id: u32, index_id: u16) -> bool {
+        self.object_id == object_id && self.index_id == index_id
+    }
+}
+
+// Bytes of a page, either borrowed from the page provider (for providers that keep their pages
+// in memory, like `MmapPageProvider`) or sharing ownership of the page with it, so providers
+// can drop pages they no longer need (like `CachingPageProvider`)
+#[derive(Clone)]
+pub struct PageBytes<'a> {
+    data: PageData<'a>,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Clone)]
+enum PageData<'a> {
+    Borrowed(&'a [u8]),
+    Shared(Arc<[u8]>),
+}
+
+impl<'a> PageBytes<'a> {
+    pub fn borrowed(data: &'a [u8]) -> Self {
+        Self {
+            start: 0,
+            end: data.len(),
+            data: PageData::Borrowed(data),
+        }
+    }
+
+    pub fn shared(data: Arc<[u8]>) -> Self {
+        Self {
+            start: 0,
+            end: data.len(),
+            data: PageData::Shared(data),
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self::borrowed(&[])
+    }
+
+    // bounds checked slicing, like `util::slice`
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Result<Self, MdfError> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        };
+        if start > end || end > self.len() {
+            return Err(MdfError::UnexpectedEnd);
+        }
+        Ok(Self {
+            data: self.data.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        })
+    }
+}
+
+impl Deref for PageBytes<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.data {
+            PageData::Borrowed(data) => &data[self.start..self.end],
+            PageData::Shared(data) => &data[self.start..self.end],
+        }
+    }
+}
+
+impl AsRef<[u8]> for PageBytes<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<'a> From<&'a [u8]> for PageBytes<'a> {
+    fn from(data: &'a [u8]) -> Self {
+        Self::borrowed(data)
+    }
+}
+
+impl From<Arc<[u8]>> for PageBytes<'_> {
+    fn from(data: Arc<[u8]>) -> Self {
+        Self::shared(data)
+    }
+}
+
+impl std::fmt::Debug for PageBytes<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl PartialEq for PageBytes<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
     }
 }
 
+impl Eq for PageBytes<'_> {}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct RawPage<'a, T: ?Sized> {
     pub header: PageHeader,
     #[derivative(Debug = "ignore")]
-    pub data: &'a [u8],
+    pub data: PageBytes<'a>,
     #[derivative(Debug = "ignore")]
     pub page_provider: &'a T,
 }
@@ -156,20 +411,38 @@ impl<'a, T> Clone for RawPage<'a, T> {
     fn clone(&self) -> Self {
         Self {
             header: self.header.clone(),
-            data: self.data,
+            data: self.data.clone(),
             page_provider: self.page_provider,
         }
     }
 }
 
 impl<'a, T: PageProvider> RawPage<'a, T> {
-    pub fn parse(data: &'a [u8], page_provider: &'a T) -> Self {
-        Self {
-            header: PageHeader::parse(data),
-            // All the offsets include the 96 byte header, so just use the whole data array
-            data: &data[..8192],
+    // Fails with `MdfError::UnexpectedEnd` if `data` is shorter than the page size of the
+    // provider, providers have to zero-pad truncated pages themselves (like `FilePageProvider`)
+    pub fn parse(data: impl Into<PageBytes<'a>>, page_provider: &'a T) -> Result<Self, MdfError> {
+        // All the offsets include the 96 byte header, so just use the whole data array
+        let data = data.into().slice(..page_provider.page_size())?;
+        Ok(Self {
+            header: PageHeader::parse(&data)?,
+            data,
             page_provider,
+        })
+    }
+
+    // Recomputes the checksum of the page and compares it with the one stored in the header,
+    // `None` if the page has no checksum
+    pub fn verify_checksum(&self) -> Option<bool> {
+        // the checksum is only defined for full 8192 byte pages
+        if !self.header.flags.contains(PageFlags::HAS_CHECKSUM) || self.data.len() != PAGE_SIZE {
+            return None;
         }
+        Some(page_checksum(&self.data) == self.header.torn_bits)
+    }
+
+    // The whole page (including the header and slot array) formatted for bug reports
+    pub fn hex_dump(&self) -> String {
+        hex_dump(&self.data)
     }
 
     // number of records on *this* page
@@ -179,51 +452,108 @@ impl<'a, T: PageProvider> RawPage<'a, T> {
 
     // idx is relative to *this* page
     pub fn record(&self, idx: u16) -> Option<Record<'a>> {
-        // assert!(idx < self.record_count());
+        self.try_record(idx)
+            .map_err(|e| error!("could not parse record {} of {:?}: {}", idx, self, e))
+            .ok()
+    }
+
+    // Offset of the record in slot `idx` from the start of the page
+    pub fn slot_offset(&self, idx: u16) -> Result<u16, MdfError> {
         if idx >= self.record_count() {
-            error!(
-                "requested a slot idx bigger than our count: {}, {:?}",
-                idx, self
-            );
-            return None;
+            return Err(MdfError::InvalidSlot(idx));
         }
 
-        let slot_array_position = PAGE_SIZE - 2 * (idx as usize) - 2;
-        let offset = (&self.data[slot_array_position..])
-            .read_u16::<LittleEndian>()
-            .unwrap() as usize;
+        // the slot array grows backwards from the end of the page
+        let slot_array_position = self
+            .data
+            .len()
+            .checked_sub(2 * (idx as usize) + 2)
+            .ok_or(MdfError::InvalidSlot(idx))?;
+        Ok(slice(&self.data, slot_array_position..)?.read_u16::<LittleEndian>()?)
+    }
+
+    // The data of the page starting at the record in slot `idx`
+    pub fn slot_data(&self, idx: u16) -> Result<PageBytes<'a>, MdfError> {
+        let offset = self.slot_offset(idx)? as usize;
         trace!("reading record {} at {:x}", idx, offset);
-        let cfg = HexConfig {
-            width: 32,
-            group: 0,
-            ..HexConfig::default()
-        };
-        trace!("{}", config_hex(&(&self.data[offset..]), cfg));
+        self.data.slice(offset..)
+    }
+
+    pub fn try_record(&self, idx: u16) -> Result<Record<'a>, MdfError> {
+        let data = self.slot_data(idx)?;
+        trace!("{}", hex_dump(&data));
         Record::parse(
-            &self.data[offset..],
+            data,
             self.header.ty == PageType::Index,
             self.header.p_min_len,
         )
     }
 
     pub fn records(&self) -> impl Iterator<Item = Record<'a>> {
-        RecordIterator::new((*self).clone(), false)
+        self.records_with_pointers().map(|(_, record)| record)
     }
 
     pub fn local_records(&self) -> impl Iterator<Item = Record<'a>> {
-        RecordIterator::new((*self).clone(), true)
+        self.local_records_with_pointers().map(|(_, record)| record)
     }
 
     pub fn into_records(self) -> impl Iterator<Item = Record<'a>> {
-        RecordIterator::new(self, false)
+        RecordIterator::new(self, false).map(|(_, record)| record)
+    }
+
+    // Like `records`, but also returns where each record is stored.
+    // For forwarded rows this is the location of the row, not of the forwarding stub
+    pub fn records_with_pointers(&self) -> impl Iterator<Item = (RecordPointer, Record<'a>)> {
+        RecordIterator::new((*self).clone(), false)
+    }
+
+    pub fn local_records_with_pointers(&self) -> impl Iterator<Item = (RecordPointer, Record<'a>)> {
+        RecordIterator::new((*self).clone(), true)
+    }
+
+    // This page followed by all the pages reachable through `next_page_ptr`.
+    // Ends at the last page of the chain or at the first page that can't be read
+    pub fn page_chain(self) -> impl Iterator<Item = RawPage<'a, T>> {
+        std::iter::successors(Some(self), |page| {
+            page.page_provider.get(page.header.next_page_ptr()?)
+        })
+    }
+
+    // All the records of *this* page as they are stored, including ghost records, forwarding
+    // stubs and forwarded records. Useful for forensic scans
+    pub fn raw_records(&self) -> impl Iterator<Item = Record<'a>> {
+        let mut iter = RecordIterator::new((*self).clone(), true);
+        iter.raw = true;
+        iter.map(|(_, record)| record)
     }
 }
 
+// The page is split into 16 sectors of 512 bytes, the words of each sector are xored together
+// and the results rotated by a different amount for each sector before combining them.
+// The checksum field itself is skipped.
+fn page_checksum(data: &[u8]) -> u32 {
+    const SECTOR_SIZE: usize = 512;
+    const CHECKSUM_WORD: usize = 60 / 4;
+
+    data.chunks(SECTOR_SIZE)
+        .enumerate()
+        .fold(0, |checksum, (i, sector)| {
+            let sector_sum = sector
+                .chunks_exact(4)
+                .enumerate()
+                .filter(|(j, _)| i != 0 || *j != CHECKSUM_WORD)
+                .fold(0, |sum, (_, word)| sum ^ LittleEndian::read_u32(word));
+            checksum ^ sector_sum.rotate_left(15 - i as u32)
+        })
+}
+
 struct RecordIterator<'a, T> {
     current_page: RawPage<'a, T>,
     // idx (on this page) of the record we will present next
     idx: u16,
     local: bool,
+    // don't skip ghosts or resolve forwarding stubs
+    raw: bool,
 }
 
 impl<'a, T> RecordIterator<'a, T> {
@@ -232,31 +562,69 @@ impl<'a, T> RecordIterator<'a, T> {
             current_page: start_page,
             idx: 0,
             local,
+            raw: false,
         }
     }
 }
 
 impl<'a, T: PageProvider> Iterator for RecordIterator<'a, T> {
-    type Item = Record<'a>;
+    type Item = (RecordPointer, Record<'a>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.idx >= self.current_page.record_count() {
-            match self.current_page.header.next_page_ptr {
-                Some(ptr) if !self.local => match self.current_page.page_provider.get(ptr) {
-                    Some(next_page) => {
-                        self.current_page = next_page;
-                        self.idx = 0;
+        loop {
+            if self.idx >= self.current_page.record_count() {
+                match self.current_page.header.next_page_ptr() {
+                    Some(ptr) if !self.local => match self.current_page.page_provider.get(ptr) {
+                        Some(next_page) => {
+                            self.current_page = next_page;
+                            self.idx = 0;
+                            continue;
+                        }
+                        None => return None,
+                    },
+                    _ => return None,
+                }
+            }
+
+            trace!("reading record {} from {:#?}", self.idx, self.current_page);
+            let record = self.current_page.record(self.idx);
+            let record_ptr = RecordPointer {
+                page_ptr: self.current_page.header.ptr,
+                slot_id: self.idx,
+            };
+            self.idx += 1;
+            // skip records we can't parse, `record` already logged why
+            let record = match record {
+                Some(record) => record,
+                None => continue,
+            };
+
+            if self.raw {
+                return Some((record_ptr, record));
+            }
+
+            if record.is_ghost() {
+                continue;
+            }
+
+            // Forwarded rows are returned in place of their stub, otherwise heap scans would
+            // see them twice
+            if record.is_forwarded() {
+                continue;
+            }
+
+            if let Some(ptr) = record.forwarding_ptr() {
+                match self.current_page.page_provider.get_record(ptr) {
+                    Some(target) => return Some((ptr, target)),
+                    None => {
+                        error!("could not resolve forwarding stub pointing to {:?}", ptr);
+                        continue;
                     }
-                    None => return None,
-                },
-                _ => return None,
+                }
             }
-        }
 
-        trace!("reading record {} from {:#?}", self.idx, self.current_page);
-        let record = self.current_page.record(self.idx);
-        self.idx += 1;
-        record
+            return Some((record_ptr, record));
+        }
     }
 }
 
@@ -267,8 +635,63 @@ pub trait PageProvider: Sized {
 
     fn get(&self, ptr: PagePointer) -> Option<RawPage<Self>>;
 
+    // SQL Server always uses 8192 byte pages, the offsets inside of a page
+    // (like the slot array at its end) are relative to this size
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    // Fetches multiple pages at once, providers that can batch their I/O should override this
+    fn get_many(&self, ptrs: &[PagePointer]) -> Vec<Option<RawPage<Self>>> {
+        ptrs.iter().map(|ptr| self.get(*ptr)).collect()
+    }
+
+    // The first page of every extent in the database
+    //
+    // This is a `Vec` rather than an iterator as trait methods can not return `impl Iterator`
+    // and `Table::par_scan_db` needs an indexed collection to split the extents between threads
+    fn extents(&self) -> Vec<PagePointer> {
+        self.file_ids()
+            .into_iter()
+            .flat_map(|file_id| {
+                (0..self.num_pages(file_id))
+                    .step_by(PAGES_PER_EXTENT as usize)
+                    .map(move |page_id| PagePointer { page_id, file_id })
+            })
+            .collect()
+    }
+
+    // All (existing) pages of the extent `extent` belongs to, fetched using `get_many`
+    fn get_extent(&self, extent: PagePointer) -> Vec<Option<RawPage<Self>>> {
+        let first = extent.extent_id() * PAGES_PER_EXTENT;
+        let last = (first + PAGES_PER_EXTENT).min(self.num_pages(extent.file_id));
+        let ptrs: Vec<_> = (first..last)
+            .map(|page_id| PagePointer {
+                page_id,
+                file_id: extent.file_id,
+            })
+            .collect();
+        self.get_many(&ptrs)
+    }
+
     fn get_record(&self, ptr: RecordPointer) -> Option<Record> {
         self.get(ptr.page_ptr)
             .and_then(|page| page.record(ptr.slot_id))
     }
+
+    // Like `get_record` for multiple records, each page is only fetched once
+    // and all of them are fetched in a single `get_many` call
+    fn get_records(&self, ptrs: &[RecordPointer]) -> Vec<Option<Record>> {
+        let mut page_ptrs: Vec<_> = ptrs.iter().map(|ptr| ptr.page_ptr).collect();
+        page_ptrs.sort_unstable();
+        page_ptrs.dedup();
+        let pages = self.get_many(&page_ptrs);
+
+        ptrs.iter()
+            .map(|ptr| {
+                let idx = page_ptrs.binary_search(&ptr.page_ptr).ok()?;
+                pages[idx].as_ref()?.record(ptr.slot_id)
+            })
+            .collect()
+    }
 }
@@ -1,11 +1,13 @@
+use crate::util::{hex_dump, slice};
+use crate::{MdfError, PageBytes, RecordPointer};
 use bitflags::bitflags;
 use bitvec::prelude::*;
 use byteorder::{LittleEndian, ReadBytesExt};
 use derivative::Derivative;
 use log::{error, trace};
 
-#[derive(Debug)]
-enum RecordType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
     Primary,
     Forwarded,
     Forwarding,
@@ -40,9 +42,36 @@ pub struct Record<'a> {
     tag_b: RecordTagB,
     pub column_count: u16,
     #[derivative(Debug = "ignore")]
-    pub fixed_data: &'a [u8],
-    null_bitmap: Option<&'a BitSlice<Lsb0, u8>>,
+    pub fixed_data: PageBytes<'a>,
+    #[derivative(Debug = "ignore")]
+    null_bitmap: Option<PageBytes<'a>>,
     pub var_length_columns: Option<VarLengthColumns<'a>>,
+    versioning_tag: Option<VersioningTag>,
+    // only set for forwarding stubs, points to where the row actually lives
+    forwarding_ptr: Option<RecordPointer>,
+    // all bytes of the record, from the status byte to the end of the versioning tag
+    #[derivative(Debug = "ignore")]
+    data: PageBytes<'a>,
+}
+
+// Appended to records of databases using snapshot isolation / read committed snapshot
+#[derive(Debug, Clone, Copy)]
+pub struct VersioningTag {
+    // pointer to the previous version of this record in the version store (in tempdb)
+    pub version_ptr: Option<RecordPointer>,
+    // transaction sequence number of the transaction that created this version
+    pub timestamp: u64,
+}
+
+impl VersioningTag {
+    const SIZE: usize = 14;
+
+    fn parse(data: &[u8]) -> Result<Self, MdfError> {
+        Ok(Self {
+            version_ptr: RecordPointer::parse(slice(data, 0..8)?),
+            timestamp: slice(data, 8..14)?.read_u48::<LittleEndian>()?,
+        })
+    }
 }
 
 #[derive(Derivative)]
@@ -50,7 +79,7 @@ pub struct Record<'a> {
 pub struct VarLengthColumns<'a> {
     // Starts at the `variable length column offset array`
     #[derivative(Debug = "ignore")]
-    data: &'a [u8],
+    data: PageBytes<'a>,
     pub count: u16,
     base_offset: usize,
 }
@@ -61,22 +90,21 @@ pub struct VarLengthColumnOffset {
 }
 
 impl VarLengthColumnOffset {
-    fn parse(bytes: &[u8]) -> Self {
-        let value = (&bytes[0..2]).read_u16::<LittleEndian>().unwrap();
+    fn parse(bytes: &[u8]) -> Result<Self, MdfError> {
+        let value = slice(bytes, 0..2)?.read_u16::<LittleEndian>()?;
         let end = value & 0x7fff;
         let complex = (value & 0x8000) != 0;
-        Self { end, complex }
+        Ok(Self { end, complex })
     }
 }
 
 impl<'a> VarLengthColumns<'a> {
-    // Get data of the `idx`th column
-    pub fn get(&self, idx: u16) -> (bool, &'a [u8]) {
+    // Get data of the `idx`th column, `None` if its offsets point outside of the record
+    pub fn get(&self, idx: u16) -> Option<(bool, PageBytes<'a>)> {
         // If we want a bigger index than we support the value is null by definition
-        // assert!(idx < self.count);
         if idx >= self.count {
             // We don't really know if its complex or not, lets hope this works
-            (false, &[])
+            Some((false, PageBytes::empty()))
         } else {
             let start = if idx == 0 {
                 // There are two bytes for each var length column in offsets,
@@ -84,17 +112,21 @@ impl<'a> VarLengthColumns<'a> {
                 2 * self.count as usize
             } else {
                 let prev_idx = idx as usize - 1;
-                VarLengthColumnOffset::parse(&self.data[2 * prev_idx..2 * (prev_idx + 1)]).end
-                    as usize
-                    - self.base_offset
+                self.offset(prev_idx)?.end as usize
             };
-            let idx = idx as usize;
-            let end = VarLengthColumnOffset::parse(&self.data[2 * idx..2 * (idx + 1)]);
-            let end_offs = end.end as usize - self.base_offset;
+            let end = self.offset(idx as usize)?;
 
-            (end.complex, &self.data[start..end_offs])
+            // `slice` checks `start <= end` and both being in bounds
+            Some((end.complex, self.data.slice(start..end.end as usize).ok()?))
         }
     }
+
+    // The end of the `idx`th column relative to `data`
+    fn offset(&self, idx: usize) -> Option<VarLengthColumnOffset> {
+        let mut offset = VarLengthColumnOffset::parse(self.data.get(2 * idx..)?).ok()?;
+        offset.end = (offset.end as usize).checked_sub(self.base_offset)? as u16;
+        Some(offset)
+    }
 }
 
 bitflags! {
@@ -121,35 +153,122 @@ impl<'a> Record<'a> {
         self.tag_a.contains(RecordTagA::HAS_VAR_LENGTH_COLUMNS)
     }
 
+    pub fn record_type(&self) -> RecordType {
+        self.ty
+    }
+
+    // Deleted records that have not been cleaned up yet
+    pub fn is_ghost(&self) -> bool {
+        matches!(
+            self.ty,
+            RecordType::GhostData | RecordType::GhostIndex | RecordType::GhostVersion
+        )
+    }
+
+    // The target of a forwarding stub, also reachable by scanning its page directly
+    pub fn is_forwarded(&self) -> bool {
+        self.ty == RecordType::Forwarded
+    }
+
+    // Whether the record stores the columns of a row. Forwarding stubs, LOB fragments and
+    // row versions don't
+    pub fn stores_row(&self) -> bool {
+        matches!(
+            self.ty,
+            RecordType::Primary
+                | RecordType::Forwarded
+                | RecordType::Index
+                | RecordType::GhostIndex
+                | RecordType::GhostData
+        )
+    }
+
+    // If this is a forwarding stub, the location of the real row
+    pub fn forwarding_ptr(&self) -> Option<RecordPointer> {
+        self.forwarding_ptr
+    }
+
+    // The version of this record, only present if snapshot isolation was in use when it was
+    // written. `version_ptr` leads to the previous version in the version store of tempdb
+    pub fn version_info(&self) -> Option<VersioningTag> {
+        self.versioning_tag
+    }
+
+    // One bit per column, set for NULL values. Only present if the record has a null bitmap
+    pub fn null_bitmap_bits(&self) -> Option<&BitSlice<Lsb0, u8>> {
+        self.null_bitmap
+            .as_ref()
+            .map(|bytes| BitSlice::from_slice(bytes).unwrap())
+    }
+
+    // The raw bytes of the record formatted for bug reports, like `RawPage::hex_dump`
+    pub fn hex_dump(&self) -> String {
+        hex_dump(&self.data)
+    }
+
+    // The raw data of the `idx`th variable length column and whether it is complex
+    // (a LOB or row overflow pointer), see `VarLengthColumns::get`
+    pub fn raw_var_column(&self, idx: u16) -> Option<(bool, PageBytes<'a>)> {
+        self.var_length_columns.as_ref()?.get(idx)
+    }
+
     pub fn is_column_null(&self, idx: u16) -> bool {
-        self.null_bitmap.map(|v| v[idx as usize]).unwrap_or(false)
+        self.null_bitmap_bits()
+            .map(|v| v[idx as usize])
+            .unwrap_or(false)
     }
 
-    pub fn parse(data: &'a [u8], is_index: bool, p_min_len: u16) -> Option<Self> {
-        let tag_a = RecordTagA::from_bits(data[0] >> 4).unwrap();
+    pub fn parse(data: PageBytes<'a>, is_index: bool, p_min_len: u16) -> Result<Self, MdfError> {
+        let status = *data.first().ok_or(MdfError::UnexpectedEnd)?;
+        let tag_a = RecordTagA::from_bits_truncate(status >> 4);
 
         let tag_b = if is_index {
             RecordTagB::empty()
         } else {
             // Seems there are some unknown bits
-            RecordTagB::from_bits_truncate(data[1])
+            RecordTagB::from_bits_truncate(*data.get(1).ok_or(MdfError::UnexpectedEnd)?)
         };
 
-        let ty = RecordType::parse((data[0] & 0xf) >> 1);
+        let ty = RecordType::parse((status & 0xf) >> 1);
 
-        // Other record types are currently not supported
-        assert!(matches!(
-            ty,
-            RecordType::Primary | RecordType::Index | RecordType::Blob
-        ));
+        // Forwarding stubs only contain the pointer to the real row and the layout of ghost
+        // version records is unknown, so neither of them has any columns
+        if matches!(ty, RecordType::Forwarding | RecordType::GhostVersion) {
+            // the length of ghost version records is unknown, so they keep the rest of the page
+            let (forwarding_ptr, data) = if ty == RecordType::Forwarding {
+                let ptr = RecordPointer::parse(slice(&data, 1..9)?).ok_or_else(|| {
+                    MdfError::Corrupt("forwarding stub without a target".to_string())
+                })?;
+                (Some(ptr), data.slice(..9)?)
+            } else {
+                (None, data)
+            };
+            return Ok(Record {
+                ty,
+                tag_a,
+                tag_b,
+                fixed_data: PageBytes::empty(),
+                column_count: 0,
+                null_bitmap: None,
+                var_length_columns: None,
+                versioning_tag: None,
+                forwarding_ptr,
+                data,
+            });
+        }
 
         let fixed_data_length = if is_index {
-            p_min_len - 1
+            p_min_len.checked_sub(1).ok_or_else(|| {
+                MdfError::Corrupt("index record with a p_min_len of zero".to_string())
+            })?
         } else {
-            let offs = (&data[2..4]).read_u16::<LittleEndian>().unwrap();
+            let offs = slice(&data, 2..4)?.read_u16::<LittleEndian>()?;
             if offs < 4 {
                 error!("something is fucked, the fixed data len is smaller than < 4: {}, {:?}, {:?}, {:?}", offs, ty, tag_a, tag_b);
-                return None;
+                return Err(MdfError::Corrupt(format!(
+                    "fixed data offset {} smaller than 4",
+                    offs
+                )));
             }
             offs - 4
         };
@@ -165,42 +284,157 @@ impl<'a> Record<'a> {
                 offset,
                 data.len()
             );
-            return None;
+            return Err(MdfError::UnexpectedEnd);
         }
 
-        let column_count = (&data[offset..]).read_u16::<LittleEndian>().unwrap();
-        offset += 2;
+        // index records only store the column count if they have a null bitmap,
+        // without one all columns are present
+        let column_count = if is_index && !tag_a.contains(RecordTagA::HAS_NULL_BITMAP) {
+            u16::MAX
+        } else {
+            let column_count = slice(&data, offset..)?.read_u16::<LittleEndian>()?;
+            offset += 2;
+            column_count
+        };
 
         let null_bitmap = if tag_a.contains(RecordTagA::HAS_NULL_BITMAP) {
             let null_bitmap_bytes = (column_count as usize + 7) / 8;
-            let bitslice = BitSlice::from_slice(&data[offset..offset + null_bitmap_bytes]).unwrap();
+            let bitmap = data.slice(offset..offset + null_bitmap_bytes)?;
             offset += null_bitmap_bytes;
-            Some(bitslice)
+            Some(bitmap)
         } else {
             None
         };
 
         let var_length_columns_count = if tag_a.contains(RecordTagA::HAS_VAR_LENGTH_COLUMNS) {
-            Some((&data[offset..]).read_u16::<LittleEndian>().unwrap())
+            Some(slice(&data, offset..)?.read_u16::<LittleEndian>()?)
         } else {
             None
         };
 
-        let fixed_data = &data[4..fixed_data_length as usize + 4];
+        // index records only have a single status byte in front of the fixed data
+        let fixed_data_start = if is_index { 1 } else { 4 };
+        let fixed_data =
+            data.slice(fixed_data_start..fixed_data_start + fixed_data_length as usize)?;
         trace!("record has {} bytes of fixed_data", fixed_data_length);
 
-        Some(Record {
+        // the end of the last var length column (or of the null bitmap without them)
+        let end = match var_length_columns_count {
+            Some(0) => offset + 2,
+            Some(count) => {
+                let last = 2 * (count as usize - 1) + offset + 2;
+                VarLengthColumnOffset::parse(slice(&data, last..last + 2)?)?.end as usize
+            }
+            None => offset,
+        };
+
+        // the versioning tag is stored after the end of the last var length column
+        let versioning_tag = if tag_a.contains(RecordTagA::HAS_VERSIONING_TAG) {
+            if end + VersioningTag::SIZE > data.len() {
+                error!(
+                    "versioning tag at {} does not fit into the record of length {}",
+                    end,
+                    data.len()
+                );
+                None
+            } else {
+                Some(VersioningTag::parse(slice(
+                    &data,
+                    end..end + VersioningTag::SIZE,
+                )?)?)
+            }
+        } else {
+            None
+        };
+
+        let var_length_columns = match var_length_columns_count {
+            Some(count) => Some(VarLengthColumns {
+                count,
+                data: data.slice(offset + 2..)?,
+                base_offset: offset + 2,
+            }),
+            None => None,
+        };
+
+        Ok(Record {
             ty,
             tag_a,
             tag_b,
             fixed_data,
             column_count,
             null_bitmap,
-            var_length_columns: var_length_columns_count.map(|count| VarLengthColumns {
-                count,
-                data: &data[offset + 2..],
-                base_offset: offset + 2,
-            }),
+            var_length_columns,
+            versioning_tag,
+            forwarding_ptr: None,
+            // invalid offsets of the var length columns are clamped to the available data
+            data: data.slice(
+                ..(end + versioning_tag.map_or(0, |_| VersioningTag::SIZE)).min(data.len()),
+            )?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::fixtures::{page, PTR};
+    use crate::PageProvider;
+
+    #[test]
+    fn null_bitmap_and_var_length_columns() {
+        let rec = vec![
+            0x30, 0, // status bits
+            6, 0, // end of the fixed data
+            0xaa, 0xbb, // fixed data
+            3, 0,     // column count
+            0b010, // null bitmap, the second column is NULL
+            2, 0, // var length column count
+            18, 0, // end of the first var length column
+            20, 0x80, // end of the second one, which is complex
+            b'a', b'b', b'c', 1, 2,
+        ];
+        let pp = page(vec![rec]);
+        let page = pp.get(PTR).unwrap();
+        let record = page.record(0).unwrap();
+
+        assert_eq!(record.record_type(), RecordType::Primary);
+        assert!(record.stores_row());
+        assert_eq!(&*record.fixed_data, &[0xaa, 0xbb]);
+        assert_eq!(record.column_count, 3);
+        assert!(!record.is_column_null(0));
+        assert!(record.is_column_null(1));
+        assert!(!record.is_column_null(2));
+
+        let (complex, data) = record.raw_var_column(0).unwrap();
+        assert!(!complex);
+        assert_eq!(&*data, b"abc");
+        let (complex, data) = record.raw_var_column(1).unwrap();
+        assert!(complex);
+        assert_eq!(&*data, &[1, 2]);
+        // columns past the stored ones are empty
+        assert_eq!(&*record.raw_var_column(2).unwrap().1, &[] as &[u8]);
+    }
+
+    #[test]
+    fn forwarding_stub() {
+        let mut rec = vec![0x04];
+        rec.extend_from_slice(&7u32.to_le_bytes());
+        rec.extend_from_slice(&1u16.to_le_bytes());
+        rec.extend_from_slice(&3u16.to_le_bytes());
+        let pp = page(vec![rec]);
+        let page = pp.get(PTR).unwrap();
+        let record = page.record(0).unwrap();
+
+        assert_eq!(record.record_type(), RecordType::Forwarding);
+        assert!(!record.stores_row());
+        let target = record.forwarding_ptr().unwrap();
+        assert_eq!(target.as_tuple(), (1, 7, 3));
+    }
+
+    #[test]
+    fn truncated_record() {
+        // the fixed data ends past the end of the record
+        let data = [0x10, 0, 40, 0, 1, 2];
+        assert!(Record::parse(PageBytes::borrowed(&data), false, 0).is_err());
+    }
+}
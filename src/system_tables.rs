@@ -1,19 +1,24 @@
-use crate::{create_row_parser, PagePointer, ValueOrLob};
+use crate::{create_row_parser, PagePointer, PageProvider, ValueOrLob};
 use bitflags::bitflags;
+use log::warn;
 
 // All the system tables are made to copy data, as they are stored in the database, which
 // is very hard to do because it requires a self-referential struct
 // They should be very small anyways
 
+pub const SYS_ALLOC_UNITS_AUID: i64 = 458752;
 pub const SYS_ROW_SET_AUID: i64 = 327680;
 pub const SYS_SCH_OBJS_IDMAJOR: i32 = 34;
 pub const SYS_COL_PARS_IDMAJOR: i32 = 41;
 pub const SYS_SCALAR_TYPES_IDMAJOR: i32 = 50;
-// OrcaMDF uses this
-// pub const SYS_RS_COLS_IDMAJOR: i32 = 3;
-// but we only have a sysrowsetcolumns with a IDMAJOR = 4
-pub const SYS_RS_COLS_IDMAJOR: i32 = 4;
+// `sysrscols`, SQL Server 2005 instead has a `sysrowsetcolumns` (with IDMAJOR = 4)
+// without the physical layout of the columns, which we don't read
+pub const SYS_RS_COLS_IDMAJOR: i32 = 3;
 pub const SYS_SINGLE_OBJECT_REFS_IDMAJOR: i32 = 74;
+pub const SYS_MULTI_OBJECT_REFS_IDMAJOR: i32 = 75;
+pub const SYS_OBJ_VALUES_IDMAJOR: i32 = 60;
+pub const SYS_IDX_STATS_IDMAJOR: i32 = 54;
+pub const SYS_IS_COLS_IDMAJOR: i32 = 55;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum AllocUnitType {
@@ -21,6 +26,7 @@ pub enum AllocUnitType {
     InRowData,
     LobData,
     RowOverflowData,
+    Unknown(i8),
 }
 
 impl AllocUnitType {
@@ -30,7 +36,10 @@ impl AllocUnitType {
             1 => Self::InRowData,
             2 => Self::LobData,
             3 => Self::RowOverflowData,
-            _ => panic!("unknown SysAllocUnitType {}", value),
+            _ => {
+                warn!("unknown allocation unit type {}", value);
+                Self::Unknown(value)
+            }
         }
     }
 }
@@ -42,9 +51,9 @@ create_row_parser!(
         owner_id: i64,
         status: i32,
         fgid: i16,
-        pg_first: Option<PagePointer> = [Binary(6)] Binary(v) => PagePointer::parse(v),
-        pg_root: Option<PagePointer> = [Binary(6)] Binary(v) => PagePointer::parse(v),
-        pg_firstiam: Option<PagePointer> = [Binary(6)] Binary(v) => PagePointer::parse(v),
+        pg_first: Option<PagePointer> = [Binary(6)] Binary(v) => PagePointer::parse(&v),
+        pg_root: Option<PagePointer> = [Binary(6)] Binary(v) => PagePointer::parse(&v),
+        pg_firstiam: Option<PagePointer> = [Binary(6)] Binary(v) => PagePointer::parse(&v),
         pc_used: i64,
         pc_data: i64,
         pc_reserved: i64,
@@ -52,6 +61,18 @@ create_row_parser!(
     }
 );
 
+impl SysAllocUnit {
+    // The page header stores the allocation unit id split into the `object_id` and `index_id`
+    // fields: au_id = (index_id << 48) | (object_id << 16)
+    pub fn page_owner_ids(&self) -> (u32, u16) {
+        Self::owner_ids_of(self.au_id)
+    }
+
+    pub fn owner_ids_of(au_id: i64) -> (u32, u16) {
+        ((au_id >> 16) as u32, (au_id >> 48) as u16)
+    }
+}
+
 create_row_parser!(
     struct SysRowSet {
         row_set_id: i64,
@@ -74,7 +95,8 @@ create_row_parser!(
     }
 );
 
-#[derive(Debug, Eq, PartialEq)]
+// The `type` of `sys.objects`
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum SchType {
     SystemTable,
     SqlScalarFunction,
@@ -82,13 +104,31 @@ pub enum SchType {
     ServiceQueue,
     InternalTable,
     DefaultConstraint,
-    // Just guessing with these two
     PrimaryKey,
     StoredProcedure,
     Unique,
     SqlTableFunction,
     View,
     Trigger,
+    ForeignKey,
+    CheckConstraint,
+    SqlInlineTableFunction,
+    Synonym,
+    Sequence,
+    TableType,
+    Rule,
+    PlanGuide,
+    ExtendedStoredProcedure,
+    ReplicationFilterProcedure,
+    ClrAggregateFunction,
+    ClrScalarFunction,
+    ClrTableFunction,
+    ClrStoredProcedure,
+    ClrTrigger,
+    EdgeConstraint,
+    ExternalTable,
+    // the two character code of types not known to this crate
+    Unknown(String),
 }
 
 impl SchType {
@@ -103,10 +143,30 @@ impl SchType {
             "PK" => Self::PrimaryKey,
             "P " => Self::StoredProcedure,
             "UQ" => Self::Unique,
-            "IF" => Self::SqlTableFunction,
+            "TF" => Self::SqlTableFunction,
             "V " => Self::View,
             "TR" => Self::Trigger,
-            _ => panic!("unknown SchType {}", ty),
+            "F " => Self::ForeignKey,
+            "C " => Self::CheckConstraint,
+            "IF" => Self::SqlInlineTableFunction,
+            "SN" => Self::Synonym,
+            "SO" => Self::Sequence,
+            "TT" => Self::TableType,
+            "R " => Self::Rule,
+            "PG" => Self::PlanGuide,
+            "X " => Self::ExtendedStoredProcedure,
+            "RF" => Self::ReplicationFilterProcedure,
+            "AF" => Self::ClrAggregateFunction,
+            "FS" => Self::ClrScalarFunction,
+            "FT" => Self::ClrTableFunction,
+            "PC" => Self::ClrStoredProcedure,
+            "TA" => Self::ClrTrigger,
+            "EC" => Self::EdgeConstraint,
+            "ET" => Self::ExternalTable,
+            _ => {
+                warn!("unknown object type {:?}", ty);
+                Self::Unknown(ty.to_string())
+            }
         }
     }
 }
@@ -118,7 +178,7 @@ create_row_parser!(
         ns_id: i32,
         ns_class: i8,
         status: i32,
-        ty: SchType = [Char(2)] Char(v) => SchType::parse(v),
+        ty: SchType = [Char(2, crate::CodePage::default())] Char(v) => SchType::parse(&v),
         pid: i32,
         pcall: i8,
         int_prop: i32,
@@ -135,6 +195,8 @@ bitflags! {
         const ROW_GUID_COL       = 1 << 3;
         const COMPUTED           = 1 << 4;
         const FILESTREAM         = 1 << 5;
+        // computed column whose value is stored in the record
+        const PERSISTED          = 1 << 9;
         const XML_DOCUMENT       = 1 << 11;
         const REPLICATED         = 1 << 17;
         const NON_SQL_SUBSCRIBED = 1 << 18;
@@ -184,29 +246,38 @@ create_row_parser!(
     }
 );
 
+// The physical layout of the columns of each row set (partition)
 create_row_parser!(
     struct SysRsCol {
         row_set_id: i64,
+        // the `col_id` of the `SysColPar` for the heap / clustered index of a table
         row_set_col_id: i32,
         hobt_col_id: i32,
-        status: i32,
         rc_modified: i64,
+        // the type (`xtype`) in the low byte and the length above it
+        ti: i32,
+        // the collation of string columns, not a column id
+        collation_id: i32,
+        ord_key: i16,
         max_in_row_len: i16,
+        status: i32,
+        offset: i32,
+        // 1 based index into the null bitmap
+        null_bit: i32,
+        bit_pos: i16,
+        col_guid: Vec<u8>[?] = [VarBinary(Some(16))] VarBinary(ValueOrLob::Value(v)) => v.to_vec(),
+        // not present before SQL Server 2012
+        db_frag_id: i32[?],
     }
 );
-/*
-
-       ti: i32,
-       c_id: i32,
-       ord_key: i16,
-       max_in_row_len: i16,
-       status: i32,
-       offset: i32,
-       null_bit: i32,
-       bit_pos: i16,
-       col_guid: Vec<u8>[?] = [VarBinary(Some(16))] VarBinary(v) => v.to_vec(),
-       db_frag_id: i32[?]
-*/
+
+impl SysRsCol {
+    // Offset of the column in the fixed data of the record (including the 4 byte record
+    // header), for variable length columns the negated index into the variable length columns
+    pub fn leaf_offset(&self) -> i16 {
+        self.offset as i16
+    }
+}
 
 create_row_parser!(
     struct SysSingleObjRef {
@@ -218,3 +289,132 @@ create_row_parser!(
         status: i32,
     }
 );
+
+impl SysSingleObjRef {
+    // `class` of the rows linking a foreign key (`dep_id`) to the table it references (`in_dep_id`)
+    // and the unique index of that table it references (`in_dep_sub_id`), `dep_sub_id` is 0.
+    // These are the `referenced_object_id` and `key_index_id` of `sys.foreign_keys`
+    pub const FOREIGN_KEY_CLASS: i8 = 11;
+}
+
+create_row_parser!(
+    struct SysMultiObjRef {
+        class: i8,
+        dep_id: i32,
+        dep_sub_id: i32,
+        in_dep_id: i32,
+        in_dep_sub_id: i32,
+        status: i32,
+    }
+);
+
+impl SysMultiObjRef {
+    // `class` of the rows storing the columns of foreign keys, one row for each column:
+    // `dep_id` is the foreign key, `dep_sub_id` the referencing column (`col_id`),
+    // `in_dep_id` the referenced table and `in_dep_sub_id` the referenced column.
+    // The rows are not ordered by the position of the column in the foreign key, so
+    // `SystemTables::foreign_keys` orders them by the key of the referenced index. It only uses
+    // rows whose `in_dep_id` is the table referenced according to `SysSingleObjRef::FOREIGN_KEY_CLASS`
+    pub const FOREIGN_KEY_COLUMN_CLASS: i8 = 3;
+}
+
+// Stores (among other things) the T-SQL source of views, procedures, functions and triggers
+create_row_parser!(
+    struct SysObjValue {
+        val_class: i8,
+        obj_id: i32,
+        sub_obj_id: i32,
+        val_num: i32,
+        value: crate::OwnedSqlValue[?] = [SqlVariant] v => v.to_owned(),
+        image_val: ValueOrLob<Vec<u8>>[?] = [VarBinary(None)] VarBinary(data) => data.map(|bytes| bytes.to_vec()),
+    }
+);
+
+impl SysObjValue {
+    // `val_class` of the rows storing module definitions
+    pub const DEFINITION_CLASS: i8 = 1;
+
+    // The complete `image_val`, reading it from the LOB pages if it is not stored in the row
+    pub fn read_image_val<T: PageProvider>(&self, page_provider: &T) -> Option<Vec<u8>> {
+        match self.image_val.as_ref()? {
+            ValueOrLob::Value(bytes) => Some(bytes.clone()),
+            ValueOrLob::Lob(ptr) => Some(ptr.read(page_provider)?.to_vec()),
+        }
+    }
+}
+
+bitflags! {
+    pub struct IdxStatStatus: i32 {
+        const UNIQUE            = 0x8;
+        const PRIMARY_KEY       = 0x20;
+        const UNIQUE_CONSTRAINT = 0x40;
+    }
+}
+
+// One row for each index (and statistic) of each table, backs `sys.indexes`
+create_row_parser!(
+    struct SysIdxStat {
+        id: i32,
+        ind_id: i32,
+        name: String[?] = [SysName] SysName(v) => v,
+        status: IdxStatStatus = [Int] Int(i) => IdxStatStatus::from_bits_truncate(i),
+        int_prop: i32,
+        fill_fact: i8,
+        // 0 for heaps, 1 for clustered and 2 for nonclustered indexes
+        ty: i8,
+        tiny_prop: i8,
+        data_space: i32,
+        lob_ds: i32,
+        row_set: i64,
+    }
+);
+
+bitflags! {
+    pub struct IsColStatus: i32 {
+        const DESCENDING = 0x4;
+        const INCLUDED   = 0x10;
+    }
+}
+
+// The columns of each index, backs `sys.index_columns`
+create_row_parser!(
+    struct SysIsCol {
+        // the `id` and `ind_id` of the `SysIdxStat`
+        id_major: i32,
+        id_minor: i32,
+        sub_id: i32,
+        status: IsColStatus = [Int] Int(i) => IsColStatus::from_bits_truncate(i),
+        // the `col_id` of the `SysColPar`
+        int_prop: i32,
+        // position in the index key starting at 1, 0 for included columns
+        tiny_prop1: i8,
+        tiny_prop2: i8,
+    }
+);
+
+impl SysIsCol {
+    pub fn col_id(&self) -> i32 {
+        self.int_prop
+    }
+
+    pub fn key_ordinal(&self) -> u8 {
+        self.tiny_prop1 as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_types_do_not_panic() {
+        assert_eq!(SchType::parse("U "), SchType::UserTable);
+        assert_eq!(SchType::parse("TF"), SchType::SqlTableFunction);
+        assert_eq!(SchType::parse("SN"), SchType::Synonym);
+        assert_eq!(SchType::parse("TT"), SchType::TableType);
+        assert_eq!(SchType::parse("ZZ"), SchType::Unknown("ZZ".to_string()));
+
+        assert_eq!(AllocUnitType::parse(3), AllocUnitType::RowOverflowData);
+        assert_eq!(AllocUnitType::parse(9), AllocUnitType::Unknown(9));
+    }
+}
@@ -1,67 +1,910 @@
-use crate::{PagePointer, PageProvider, PageType, Row, Schema};
+use crate::util::to_hex;
+use crate::{
+    value_for_display, value_for_export, ColumnType, IamPage, LobDataBlocks, MdfError, PageHeader,
+    PagePointer, PageProvider, PageType, RawPage, Record, RecordPointer, Row, Schema,
+    SchemaMismatch, SqlType, SqlValue,
+};
 use derivative::Derivative;
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    // one JSON object per line
+    JsonLines,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        }
+    }
+}
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct Table<'a, T> {
     pub name: String,
+    // `id` of the `SysSchObj` of this table
+    pub object_id: i32,
     #[derivative(Debug = "ignore")]
     pub page_provider: &'a T,
     pub schema: Schema,
+    // 0 for heaps, 1 for tables with a clustered index
+    pub index_id: i32,
+    // (object_id, index_id) pairs as found in the headers of the pages of this table
+    pub page_owner_ids: Vec<(u32, u16)>,
     pub partition_pointer: Vec<PagePointer>,
+    // first IAM page of each partition
+    pub iam_pointer: Vec<PagePointer>,
+    // root page of the clustered index of each partition, empty for heaps
+    pub index_root: Vec<PagePointer>,
+    // indices into `schema.columns` of the clustered index key, in key order.
+    // Read from `sysiscols`, if it is missing it can be set using `with_key_columns`
+    pub key_columns: Vec<usize>,
+    // indices into `schema.columns` of the primary key, in key order
+    pub primary_key: Vec<usize>,
+    // sum of `SysRowSet::rcrows` of all partitions
+    pub estimated_rows: i64,
+}
+
+// The differences between two versions of the same table, see `Table::diff`.
+// Each row is given as the display representation of its columns, all lists are sorted
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TableDiff {
+    // rows of `self` missing in `other`
+    pub removed: Vec<Vec<String>>,
+    // rows of `other` missing in `self`
+    pub added: Vec<Vec<String>>,
+    // (row of `self`, row of `other`) with the same key but different values
+    pub changed: Vec<(Vec<String>, Vec<String>)>,
+}
+
+impl TableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Passed to the callback of `Table::scan_db_with_progress` after each extent
+#[derive(Debug, Clone, Copy)]
+pub struct ScanProgress {
+    // the first page of the extent that was just scanned
+    pub file_id: u16,
+    pub page_id: u32,
+    // pages scanned so far and in total, across all files
+    pub pages_scanned: u64,
+    pub total_pages: u64,
+    pub rows: usize,
+}
+
+// The location of a row returned by `Table::scan_db_with_cursor`, can be stored to continue
+// the scan later using `Table::resume_scan`. Ordered in the order rows are scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ScanCursor {
+    pub file_id: u16,
+    pub page_id: u32,
+    pub slot: u16,
+}
+
+impl From<RecordPointer> for ScanCursor {
+    fn from(ptr: RecordPointer) -> Self {
+        Self {
+            file_id: ptr.page_ptr.file_id,
+            page_id: ptr.page_ptr.page_id,
+            slot: ptr.slot_id,
+        }
+    }
+}
+
+// How `Table::read` finds the pages of a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadStrategy {
+    // `PageChain` if `check_chain` finds no problems, otherwise `IamChain` if the table has
+    // IAM pages and `BruteForceScan` if it has none
+    #[default]
+    Auto,
+    // follows the page chain of each partition, see `rows`
+    PageChain,
+    // reads the pages allocated to the table according to its IAM pages, see `scan_allocated`
+    IamChain,
+    // scans every page of the database, see `scan_db`
+    BruteForceScan,
+}
+
+// Problems found while following the page chains of a table, see `Table::check_chain`
+#[derive(Debug, Default)]
+pub struct ChainReport {
+    // number of pages visited
+    pub pages: usize,
+    // pages that are part of a chain but could not be read
+    pub unreadable: Vec<PagePointer>,
+    // pages that are part of a chain but belong to another table
+    pub wrong_owner: Vec<PagePointer>,
+    // (page, next page) where the `prev_page_ptr` of the next page does not point back
+    pub broken_back_links: Vec<(PagePointer, PagePointer)>,
+    // pages the chain loops back to
+    pub cycles: Vec<PagePointer>,
+}
+
+impl ChainReport {
+    pub fn is_ok(&self) -> bool {
+        self.unreadable.is_empty()
+            && self.wrong_owner.is_empty()
+            && self.broken_back_links.is_empty()
+            && self.cycles.is_empty()
+    }
+}
+
+// A single partition of a table, see `Table::partitions`
+pub struct Partition<'t, 'a, T> {
+    pub table: &'t Table<'a, T>,
+    // 1 based, in the order of `Table::partition_pointer`
+    pub number: usize,
+    pub first_page: PagePointer,
+}
+
+impl<'t, 'a, T: PageProvider> Partition<'t, 'a, T> {
+    // The data pages of this partition, following the page chain from its first page
+    pub fn pages(&self) -> impl Iterator<Item = RawPage<'a, T>> {
+        self.table
+            .page_provider
+            .get(self.first_page)
+            .into_iter()
+            .flat_map(RawPage::page_chain)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = Record<'a>> {
+        self.table
+            .page_provider
+            .get(self.first_page)
+            .into_iter()
+            .flat_map(RawPage::into_records)
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = Row<'a>> + 't {
+        let table = self.table;
+        self.records()
+            .filter_map(move |record| table.parse_row(record))
+    }
+
+    // See `Table::row_count`
+    pub fn row_count(&self) -> usize {
+        self.pages()
+            .map(|page| {
+                page.raw_records()
+                    .filter(|record| !record.is_ghost() && !record.is_forwarded())
+                    .count()
+            })
+            .sum()
+    }
+}
+
+// Compares the first `key.len()` columns of an index key with `key`, NULL sorts first
+fn compare_key_values(
+    values: &[Option<&SqlValue>],
+    key: &[SqlValue],
+) -> Result<Ordering, MdfError> {
+    for (value, key) in values.iter().zip(key) {
+        let ord = match value {
+            Some(value) => value.compare(key).ok_or_else(|| {
+                MdfError::InvalidKey(format!("cannot compare {:?} with {:?}", value, key))
+            })?,
+            None => Ordering::Less,
+        };
+        if ord != Ordering::Equal {
+            return Ok(ord);
+        }
+    }
+    Ok(Ordering::Equal)
+}
+
+// Whether `SqlValue::compare` can compare values of a column of type `data_type` with `value`
+fn is_comparable(data_type: &SqlType, value: &SqlValue) -> bool {
+    match data_type {
+        SqlType::TinyInt
+        | SqlType::SmallInt
+        | SqlType::Int
+        | SqlType::BigInt
+        | SqlType::Bit
+        | SqlType::Decimal { .. }
+        | SqlType::Money
+        | SqlType::SmallMoney
+        | SqlType::Real
+        | SqlType::Float => value.as_i64().is_some() || value.as_f64().is_some(),
+        SqlType::Char(..)
+        | SqlType::VarChar(..)
+        | SqlType::NChar(_)
+        | SqlType::NVarChar(_)
+        | SqlType::SysName => value.as_str().is_some(),
+        SqlType::Binary(_) | SqlType::VarBinary(_) | SqlType::SqlVariant => {
+            value.as_bytes().is_some()
+        }
+        SqlType::Date | SqlType::DateTime | SqlType::SmallDateTime | SqlType::DateTime2(_) => {
+            value.as_datetime().is_some()
+        }
+        SqlType::Time(_) => matches!(value, SqlValue::Time(_)),
+        SqlType::DateTimeOffset(_) => matches!(value, SqlValue::DateTimeOffset(_)),
+        SqlType::UniqueIdentifier => value.as_unique_identifier().is_some(),
+        // can't be part of an index key
+        SqlType::NText | SqlType::Image => false,
+    }
+}
+
+fn compare_values(values: &[Option<SqlValue>], key: &[SqlValue]) -> Result<Ordering, MdfError> {
+    let values: Vec<_> = values.iter().map(|value| value.as_ref()).collect();
+    compare_key_values(&values, key)
+}
+
+// Like `value_for_display`, but values stored out of row are represented by their content
+fn value_for_diff<T: PageProvider>(
+    value: &Option<SqlValue>,
+    column: &ColumnType,
+    page_provider: &T,
+) -> String {
+    let value = match value {
+        Some(value) if value.as_lob_pointer().is_some() => value,
+        _ => return value_for_display(value),
+    };
+    let content = match &column.data_type {
+        SqlType::VarChar(_, code_page) => value
+            .read_lob(page_provider)
+            .map(|data| code_page.decode(&data.to_vec())),
+        _ => value
+            .read_string(page_provider)
+            .or_else(|| value.read_bytes(page_provider).map(|data| to_hex(&data))),
+    };
+    content.unwrap_or_else(|| "<unreadable LOB>".to_string())
 }
 
 impl<'a, T: PageProvider> Table<'a, T> {
+    // Compares the rows of two versions of the same table (for example from two backups).
+    // Rows are matched by their primary key (or clustered index key), rows with the same key
+    // but different values are reported as changed. Tables without a key are matched by their
+    // full content, duplicates are counted, so a row occuring twice in `self` but only once in
+    // `other` is reported as removed once.
+    pub fn diff<U: PageProvider>(&self, other: &Table<U>) -> Result<TableDiff, SchemaMismatch> {
+        other.schema.validate_against(&self.schema)?;
+
+        let key = if self.primary_key.is_empty() {
+            &self.key_columns
+        } else {
+            &self.primary_key
+        };
+        // key -> rows with that key, the whole row is the key without a key
+        type Rows = HashMap<Vec<String>, Vec<Vec<String>>>;
+        fn collect_rows<T: PageProvider>(table: &Table<T>, key: &[usize]) -> Rows {
+            let mut rows = Rows::new();
+            table.for_each_row(|values| {
+                let row: Vec<String> = values
+                    .iter()
+                    .zip(&table.schema.columns)
+                    .map(|(value, col)| value_for_diff(value, col, table.page_provider))
+                    .collect();
+                let key = if key.is_empty() {
+                    row.clone()
+                } else {
+                    key.iter().map(|&idx| row[idx].clone()).collect()
+                };
+                rows.entry(key).or_default().push(row);
+            });
+            rows
+        }
+        let ours = collect_rows(self, key);
+        let mut theirs = collect_rows(other, key);
+
+        let mut diff = TableDiff::default();
+        for (key, mut removed) in ours {
+            let mut added = theirs.remove(&key).unwrap_or_default();
+            // rows present in both don't count, each match only once
+            removed.retain(|row| match added.iter().position(|other| other == row) {
+                Some(idx) => {
+                    added.swap_remove(idx);
+                    false
+                }
+                None => true,
+            });
+            removed.sort();
+            added.sort();
+
+            let changed = removed.len().min(added.len());
+            diff.changed
+                .extend(removed.drain(..changed).zip(added.drain(..changed)));
+            diff.removed.extend(removed);
+            diff.added.extend(added);
+        }
+        diff.added.extend(theirs.into_values().flatten());
+
+        diff.removed.sort();
+        diff.added.sort();
+        diff.changed.sort();
+        Ok(diff)
+    }
+
+    // Sets the columns of the clustered index key, needed for `seek`
+    pub fn with_key_columns(mut self, names: &[&str]) -> Result<Self, MdfError> {
+        self.key_columns = names
+            .iter()
+            .map(|name| {
+                self.schema
+                    .columns
+                    .iter()
+                    .position(|col| col.name == *name)
+                    .ok_or_else(|| {
+                        MdfError::InvalidKey(format!("{} has no column {}", self.name, name))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    // The columns of the primary key in key order, or of the clustered index key if the
+    // table has no primary key
+    pub fn primary_key(&self) -> Vec<&ColumnType> {
+        let key = if self.primary_key.is_empty() {
+            &self.key_columns
+        } else {
+            &self.primary_key
+        };
+        key.iter().map(|&idx| &self.schema.columns[idx]).collect()
+    }
+
+    // A `CREATE TABLE` statement for this table, including the primary key
+    pub fn to_create_table_sql(&self) -> String {
+        self.schema.create_table_sql(&self.name, &self.primary_key)
+    }
+
+    // Finds the rows whose clustered index key starts with `key`, by descending the index
+    // instead of scanning the whole table. Fails if `key` has more columns than the index key
+    // or values that can't be compared with the key columns
+    pub fn seek<'k>(
+        &'a self,
+        key: &'k [SqlValue<'k>],
+    ) -> Result<impl Iterator<Item = Row<'a>> + 'k, MdfError>
+    where
+        'a: 'k,
+    {
+        if key.len() > self.key_columns.len() {
+            return Err(MdfError::InvalidKey(format!(
+                "seek key has {} columns, but the index key of {} only {}",
+                key.len(),
+                self.name,
+                self.key_columns.len()
+            )));
+        }
+        for (value, &idx) in key.iter().zip(&self.key_columns) {
+            let col = &self.schema.columns[idx];
+            if !is_comparable(&col.data_type, value) {
+                return Err(MdfError::InvalidKey(format!(
+                    "cannot compare {:?} with column {} of type {}",
+                    value,
+                    col.name,
+                    col.data_type.to_tsql_name()
+                )));
+            }
+        }
+
+        let key_schema = Schema::new(
+            self.key_columns
+                .iter()
+                .map(|&idx| self.schema.columns[idx].clone())
+                .collect(),
+        );
+
+        Ok(self
+            .index_root
+            .iter()
+            .filter_map(move |root| self.seek_leaf(&key_schema, *root, key))
+            .flat_map(move |leaf| {
+                leaf.into_records()
+                    .filter_map(move |record| self.parse_row(record))
+                    .map_while(move |row| match self.compare_key(&row, key) {
+                        Ok(ord) => Some((ord, row)),
+                        Err(e) => {
+                            error!("stopping seek in {}: {}", self.name, e);
+                            None
+                        }
+                    })
+                    .skip_while(|(ord, _)| *ord == Ordering::Less)
+                    .take_while(|(ord, _)| *ord == Ordering::Equal)
+                    .map(|(_, row)| row)
+            }))
+    }
+
+    // Descends from the `root` index page to the leftmost leaf page that can contain `key`
+    fn seek_leaf(
+        &'a self,
+        key_schema: &Schema,
+        root: PagePointer,
+        key: &[SqlValue],
+    ) -> Option<RawPage<'a, T>> {
+        let mut page = self.page_provider.get(root)?;
+        while page.header.ty == PageType::Index {
+            let mut child = None;
+            for record in page.local_records() {
+                // the child page pointer follows the key columns
+                let fixed_data = record.fixed_data.clone();
+                let ptr = PagePointer::parse(&fixed_data[fixed_data.len().checked_sub(6)?..])?;
+                let entry = match key_schema.parse(record) {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!("could not parse index record of {}: {}", self.name, e);
+                        return None;
+                    }
+                };
+                // the first record of a page is the lower bound of the page and has no key,
+                // after that we want the last record with a smaller key, as duplicates of
+                // `key` can begin at the end of its child page
+                if child.is_some() {
+                    match compare_values(&entry.values, key) {
+                        Ok(Ordering::Less) => {}
+                        Ok(_) => break,
+                        Err(e) => {
+                            error!("could not compare index record of {}: {}", self.name, e);
+                            return None;
+                        }
+                    }
+                }
+                child = Some(ptr);
+            }
+            page = self.page_provider.get(child?)?;
+        }
+        Some(page)
+    }
+
+    fn compare_key(&self, row: &Row, key: &[SqlValue]) -> Result<Ordering, MdfError> {
+        let values: Vec<_> = self
+            .key_columns
+            .iter()
+            .map(|&idx| row.values[idx].as_ref())
+            .collect();
+        compare_key_values(&values, key)
+    }
+
+    // Bad records are skipped (and logged) instead of aborting the whole scan,
+    // records without a row are skipped silently
+    fn parse_row<'r>(&self, record: Record<'r>) -> Option<Row<'r>> {
+        self.schema
+            .parse(record)
+            .map_err(|e| error!("skipping row of {}: {}", self.name, e))
+            .ok()
+            .flatten()
+    }
+
+    // Whether the page stores data of this table according to the object and index id in its
+    // header. Without `page_owner_ids` (the allocation units of the table are unknown) this
+    // falls back to comparing the `p_min_len` of the page with the one of the first page of the
+    // table, or the one expected for the schema if that page can not be read. This also matches
+    // pages of other tables with the same fixed length size
+    pub fn owns_page(&self, header: &PageHeader) -> bool {
+        if self.page_owner_ids.is_empty() {
+            let p_min_len = self
+                .partition_pointer
+                .first()
+                .and_then(|ptr| self.page_provider.get(*ptr))
+                .map(|page| page.header.p_min_len)
+                .or_else(|| self.schema.expected_p_min_len());
+            return Some(header.p_min_len) == p_min_len;
+        }
+        self.page_owner_ids
+            .iter()
+            .any(|&(object_id, index_id)| header.belongs_to(object_id, index_id))
+    }
+
+    // Whether the page is a data page of this table, see `owns_page`
+    fn owns_data_page(&self, header: &PageHeader) -> bool {
+        header.ty == PageType::Data && self.owns_page(header)
+    }
+
+    // Visits the values of every row, reusing the same buffer for all of them.
+    // `rows` has to allocate for each row, as the rows are handed out by value
+    pub fn for_each_row<F: FnMut(&[Option<SqlValue>])>(&self, mut f: F) {
+        let mut values = Vec::with_capacity(self.schema.columns.len());
+        for partition in self.partitions() {
+            for rec in partition.records() {
+                match self.schema.parse_into(rec, &mut values) {
+                    Ok(()) => f(&values),
+                    Err(e) => error!("skipping row of {}: {}", self.name, e),
+                }
+            }
+        }
+    }
+
+    // Writes all rows as a table with a header, with the columns as wide as their widest value.
+    // Numbers are aligned to the right
+    pub fn print_table<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let columns = &self.schema.columns;
+        let mut rows = vec![];
+        self.for_each_row(|values| rows.push(values.iter().map(value_for_display).collect()));
+
+        let header: Vec<String> = columns.iter().map(|col| col.name.clone()).collect();
+        let widths: Vec<usize> = (0..columns.len())
+            .map(|i| {
+                std::iter::once(&header)
+                    .chain(&rows)
+                    .map(|row| row[i].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let format_row = |row: &[String], align_numbers: bool| {
+            let cells: Vec<_> = row
+                .iter()
+                .zip(columns)
+                .zip(&widths)
+                .map(|((value, col), &width)| {
+                    if align_numbers && col.data_type.is_numeric() {
+                        format!("{:>width$}", value, width = width)
+                    } else {
+                        format!("{:<width$}", value, width = width)
+                    }
+                })
+                .collect();
+            cells.join(" | ").trim_end().to_string()
+        };
+
+        writeln!(writer, "{}", format_row(&header, false))?;
+        let separator: Vec<_> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        writeln!(writer, "{}", separator.join("-+-"))?;
+        for row in &rows {
+            writeln!(writer, "{}", format_row(row, true))?;
+        }
+        Ok(())
+    }
+
+    // Writes all rows in the given format, returns the number of rows written
+    pub fn export<W: Write>(&self, writer: W, format: ExportFormat) -> std::io::Result<usize> {
+        match format {
+            ExportFormat::Csv => self.write_csv_rows(writer, ""),
+            ExportFormat::JsonLines => self.write_json_lines(writer),
+        }
+    }
+
+    // Writes each row as a JSON object on its own line
+    pub fn to_json_lines<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        self.write_json_lines(writer).map(|_| ())
+    }
+
+    // Writes a header with the column names followed by one record per row,
+    // NULL values are written as empty fields
+    pub fn write_csv<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        self.write_csv_with_null(writer, "")
+    }
+
+    pub fn write_csv_with_null<W: Write>(&self, writer: W, null: &str) -> std::io::Result<()> {
+        self.write_csv_rows(writer, null).map(|_| ())
+    }
+
+    fn write_json_lines<W: Write>(&self, mut writer: W) -> std::io::Result<usize> {
+        let mut count = 0;
+        for row in self.rows() {
+            serde_json::to_writer(&mut writer, &row)?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    fn write_csv_rows<W: Write>(&self, writer: W, null: &str) -> std::io::Result<usize> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(self.schema.columns.iter().map(|col| &col.name))?;
+        let mut count = 0;
+        for row in self.rows() {
+            writer.write_record(row.values.iter().map(|value| match value {
+                Some(value) => value_for_export(value),
+                None => null.to_string(),
+            }))?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    // The partitions of this table, in the order of `partition_pointer`
+    pub fn partitions(&self) -> impl Iterator<Item = Partition<'_, 'a, T>> {
+        self.partition_pointer
+            .iter()
+            .enumerate()
+            .map(move |(i, first_page)| Partition {
+                table: self,
+                number: i + 1,
+                first_page: *first_page,
+            })
+    }
+
     pub fn rows(&self) -> impl Iterator<Item = Row> {
-        self.partition_pointer.iter().flat_map(move |part| {
-            let start_page = self.page_provider.get(*part).unwrap();
-            start_page
-                .into_records()
-                .map(move |rec| self.schema.parse(rec))
-        })
+        self.partitions().flat_map(|partition| partition.rows())
+    }
+
+    // Reads all rows using `strategy`. With `ReadStrategy::Auto` the page chains are checked
+    // up front, so a broken chain falls back to the IAM pages (or a scan of the whole
+    // database) before any row was returned, instead of returning a partial result
+    pub fn read(&'a self, strategy: ReadStrategy) -> Box<dyn Iterator<Item = Row<'a>> + 'a> {
+        match self.resolve_strategy(strategy) {
+            ReadStrategy::PageChain => Box::new(self.rows()),
+            ReadStrategy::IamChain => Box::new(self.scan_allocated()),
+            _ => Box::new(self.scan_db()),
+        }
+    }
+
+    // The strategy `ReadStrategy::Auto` stands for, all other strategies are returned as is
+    pub fn resolve_strategy(&self, strategy: ReadStrategy) -> ReadStrategy {
+        if strategy != ReadStrategy::Auto {
+            return strategy;
+        }
+
+        let report = self.check_chain();
+        if report.is_ok() {
+            ReadStrategy::PageChain
+        } else if !self.iam_pointer.is_empty() {
+            warn!(
+                "page chain of {} is broken, reading the pages from its IAM pages: {:?}",
+                self.name, report
+            );
+            ReadStrategy::IamChain
+        } else {
+            warn!(
+                "page chain of {} is broken, scanning the whole database: {:?}",
+                self.name, report
+            );
+            ReadStrategy::BruteForceScan
+        }
+    }
+
+    // The number of rows as tracked by SQL Server in `sysrowsets`, without reading any page.
+    // This counter is not always kept up to date, so it can differ from `row_count`
+    pub fn estimated_row_count(&self) -> i64 {
+        self.estimated_rows
+    }
+
+    // Counts the rows by walking the page chain of each partition, without parsing them.
+    // Ghost records are skipped, forwarded rows are counted at their forwarding stub
+    pub fn row_count(&self) -> usize {
+        self.partitions()
+            .map(|partition| partition.row_count())
+            .sum()
+    }
+
+    // Follows the page chain of each partition and checks that every page belongs to this
+    // table and links back to its predecessor. The chain is not followed past pages
+    // that are unreadable, belong to another table or were already visited.
+    pub fn check_chain(&self) -> ChainReport {
+        let mut report = ChainReport::default();
+        let mut visited = HashSet::new();
+
+        for part in &self.partition_pointer {
+            let mut prev: Option<PagePointer> = None;
+            let mut next = Some(*part);
+
+            while let Some(ptr) = next {
+                if !visited.insert(ptr) {
+                    report.cycles.push(ptr);
+                    break;
+                }
+                let page = match self.page_provider.get(ptr) {
+                    Some(page) => page,
+                    None => {
+                        report.unreadable.push(ptr);
+                        break;
+                    }
+                };
+                report.pages += 1;
+
+                if !self.owns_page(&page.header) {
+                    report.wrong_owner.push(ptr);
+                    break;
+                }
+                if let Some(prev) = prev {
+                    if page.header.prev_page_ptr() != Some(prev) {
+                        report.broken_back_links.push((prev, ptr));
+                    }
+                }
+
+                prev = Some(ptr);
+                next = page.header.next_page_ptr();
+            }
+        }
+
+        report
+    }
+
+    // Like `rows`, but only parses the records for which `pred` returns true.
+    // Use `Schema::parse_fixed_column` to cheaply look at single columns of the record
+    pub fn scan_filter<F: Fn(&Record) -> bool>(&self, pred: F) -> impl Iterator<Item = Row> {
+        self.partitions()
+            .flat_map(|partition| partition.records())
+            .filter(move |rec| pred(rec))
+            .filter_map(move |rec| self.parse_row(rec))
     }
 
     // This is used to recover data from broken db's
-    // instead of following the page links, this looks up the p_min_len from the
-    // first page linked to from the allocation units and then scans the whole database
-    // for tables with this p_min_len
-    // For this to work the p_min_len has to be unique enough and the first page must be accessible
+    // instead of following the page links, this scans the whole database for data pages
+    // whose header says they belong to the allocation units of this table (see `owns_page`).
+    // Forwarded rows are only returned in place of their forwarding stub, so each row is
+    // returned once (and not at all if the page of its stub is missing).
+    // The database is read one extent at a time to allow the page provider to batch the reads
     pub fn scan_db(&'a self) -> impl Iterator<Item = Row> {
-        let first_page = self.partition_pointer[0];
-        let first_page = self.page_provider.get(first_page).unwrap();
-        let p_min_len = first_page.header.p_min_len;
+        self.scan_db_with_progress(|_| {})
+    }
 
-        self.page_provider
+    // Like `scan_db`, but calls `progress` each time an extent was scanned
+    pub fn scan_db_with_progress<F: FnMut(ScanProgress)>(
+        &'a self,
+        mut progress: F,
+    ) -> impl Iterator<Item = Row> {
+        let extents = self.page_provider.extents();
+        let total_pages = self
+            .page_provider
             .file_ids()
             .into_iter()
-            .flat_map(move |j| {
-                (0..self.page_provider.num_pages(j))
-                    .filter_map(move |i| {
-                        if let Some(page) = self.page_provider.get(PagePointer {
-                            page_id: i,
-                            file_id: j,
-                        }) {
-                            println!("{:?}", page.header);
-                            if (page.header.p_min_len == p_min_len)
-                                && (page.header.ty == PageType::Data)
-                            {
-                                println!("{} {}", j, i);
-                                return Some(page);
-                            }
-                        }
-                        None
-                    })
-                    .flat_map(move |page| {
-                        page.local_records()
-                            .map(move |record| self.schema.parse(record))
-                    })
+            .map(|file_id| self.page_provider.num_pages(file_id) as u64)
+            .sum();
+        let mut pages_scanned = 0;
+        let mut rows = 0;
+
+        extents.into_iter().flat_map(move |extent| {
+            // missing pages count as scanned as well
+            let pages = self.page_provider.get_extent(extent);
+            pages_scanned += pages.len() as u64;
+
+            let extent_rows: Vec<_> = pages
+                .into_iter()
+                .flatten()
+                .filter(|page| {
+                    trace!("{:?}", page.header);
+                    self.owns_data_page(&page.header)
+                })
+                .flat_map(|page| {
+                    page.local_records()
+                        .filter_map(move |record| self.parse_row(record))
+                })
+                .collect();
+            rows += extent_rows.len();
+
+            progress(ScanProgress {
+                file_id: extent.file_id,
+                page_id: extent.page_id,
+                pages_scanned,
+                total_pages,
+                rows,
+            });
+            extent_rows
+        })
+    }
+
+    // Like `scan_db`, but also returns deleted (ghost) rows and does not resolve forwarded
+    // rows, so each row is returned from the page it is physically stored on
+    pub fn scan_db_raw(&'a self) -> impl Iterator<Item = Row> {
+        self.owned_data_pages().flat_map(move |page| {
+            page.raw_records()
+                .filter_map(move |record| self.parse_row(record))
+        })
+    }
+
+    fn owned_data_pages(&'a self) -> impl Iterator<Item = RawPage<'a, T>> {
+        self.owned_data_pages_in(self.page_provider.extents())
+    }
+
+    fn owned_data_pages_in(
+        &'a self,
+        extents: Vec<PagePointer>,
+    ) -> impl Iterator<Item = RawPage<'a, T>> {
+        extents.into_iter().flat_map(move |extent| {
+            self.page_provider
+                .get_extent(extent)
+                .into_iter()
+                .flatten()
+                .filter(move |page| {
+                    trace!("{:?}", page.header);
+                    self.owns_data_page(&page.header)
+                })
+        })
+    }
+
+    // All the pages allocated to this table, according to the IAM pages of its partitions
+    pub fn allocated_pages(&self) -> Vec<PagePointer> {
+        self.iam_pointer
+            .iter()
+            .flat_map(|first_iam| IamPage::chain(self.page_provider, *first_iam))
+            .flat_map(|iam| iam.pages().collect::<Vec<_>>())
+            .collect()
+    }
+
+    // Like `scan_db`, but only reads the data pages allocated to this table instead of the
+    // whole database. Needs intact IAM pages.
+    pub fn scan_allocated(&'a self) -> impl Iterator<Item = Row> {
+        self.allocated_pages()
+            .into_iter()
+            .filter_map(move |ptr| self.page_provider.get(ptr))
+            .filter(|page| page.header.ty == PageType::Data)
+            .flat_map(move |page| {
+                page.local_records()
+                    .filter_map(move |record| self.parse_row(record))
+            })
+    }
+
+    // Reads the LOB data of all the LOB columns of all rows, together with the pointer to the
+    // first record of the LOB data. LOBs longer than `max_length` bytes are skipped.
+    pub fn lob_blobs(
+        &'a self,
+        max_length: Option<u32>,
+    ) -> impl Iterator<Item = (RecordPointer, LobDataBlocks<'a>)> {
+        self.rows()
+            .flat_map(|row| {
+                row.values
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|value| value.lob_pointer())
+            })
+            .filter_map(move |ptr| Some((ptr.record_pointer(), ptr.read(self.page_provider)?)))
+            .filter(move |(_, blocks)| max_length.is_none_or(|max| blocks.length() <= max))
+    }
+
+    // Like `scan_db`, but reads the files page by page in order and returns where each row
+    // was found
+    pub fn scan_db_with_cursor(&'a self) -> impl Iterator<Item = (ScanCursor, Row)> {
+        self.scan_db_after(None)
+    }
+
+    // Continues a `scan_db_with_cursor` with the row following `cursor`
+    pub fn resume_scan(&'a self, cursor: ScanCursor) -> impl Iterator<Item = (ScanCursor, Row)> {
+        self.scan_db_after(Some(cursor))
+    }
+
+    fn scan_db_after(
+        &'a self,
+        after: Option<ScanCursor>,
+    ) -> impl Iterator<Item = (ScanCursor, Row)> {
+        let mut file_ids = self.page_provider.file_ids();
+        file_ids.sort_unstable();
+
+        file_ids
+            .into_iter()
+            .flat_map(move |file_id| {
+                let num_pages = self.page_provider.num_pages(file_id);
+                let first_page = match after {
+                    Some(after) if after.file_id == file_id => after.page_id,
+                    Some(after) if after.file_id > file_id => num_pages,
+                    _ => 0,
+                };
+                (first_page..num_pages).map(move |page_id| PagePointer { page_id, file_id })
+            })
+            .filter_map(move |ptr| self.page_provider.get(ptr))
+            .filter(move |page| self.owns_data_page(&page.header))
+            .flat_map(move |page| {
+                // the cursor of a forwarded row is the location of its forwarding stub,
+                // as that is where it is found by the scan
+                (0..page.record_count()).filter_map(move |slot| {
+                    let cursor = ScanCursor::from(RecordPointer {
+                        page_ptr: page.header.ptr,
+                        slot_id: slot,
+                    });
+                    if after.is_some_and(|after| cursor <= after) {
+                        return None;
+                    }
+                    let record = page.record(slot)?;
+                    if record.is_ghost() || record.is_forwarded() {
+                        return None;
+                    }
+                    let record = match record.forwarding_ptr() {
+                        Some(ptr) => self.page_provider.get_record(ptr)?,
+                        None => record,
+                    };
+                    Some((cursor, self.parse_row(record)?))
+                })
             })
     }
 
     pub fn scan_db_from(&'a self, start: PagePointer) -> impl Iterator<Item = Row> {
-        let first_page = self.partition_pointer[0];
-        let first_page = self.page_provider.get(first_page).unwrap();
-        let p_min_len = first_page.header.p_min_len;
         let j = start.file_id;
 
         (start.page_id..self.page_provider.num_pages(j))
@@ -70,7 +913,7 @@ impl<'a, T: PageProvider> Table<'a, T> {
                     page_id: i,
                     file_id: j,
                 }) {
-                    if (page.header.p_min_len == p_min_len) && (page.header.ty == PageType::Data) {
+                    if self.owns_data_page(&page.header) {
                         return Some(page);
                     }
                 }
@@ -78,7 +921,234 @@ impl<'a, T: PageProvider> Table<'a, T> {
             })
             .flat_map(move |page| {
                 page.local_records()
-                    .map(move |record| self.schema.parse(record))
+                    .filter_map(move |record| self.parse_row(record))
+            })
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<'a, T: PageProvider + Sync> Table<'a, T> {
+    // Like `scan_db`, but the extents of the database are read by multiple threads sharing the
+    // page provider. The rows are returned in no particular order and have to be owned, as the
+    // pages they were read from are dropped once they were parsed.
+    pub fn par_scan_db(&self) -> impl rayon::iter::ParallelIterator<Item = crate::OwnedRow> + '_ {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        self.page_provider
+            .extents()
+            .into_par_iter()
+            .flat_map_iter(move |extent| {
+                self.owned_data_pages_in(vec![extent])
+                    .flat_map(move |page| {
+                        page.local_records()
+                            .filter_map(|record| self.parse_row(record))
+                            .map(|row| row.to_owned())
+                            .collect::<Vec<_>>()
+                    })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::fixtures::{page, record, PTR};
+    use crate::{MemoryPageProvider, PageBuilder, ValueOrLob};
+
+    // (id int, name nvarchar(max))
+    fn schema() -> Schema {
+        let column = |idx, name: &str, data_type| ColumnType {
+            idx,
+            data_type,
+            name: name.to_string(),
+            nullable: true,
+            computed: false,
+            persisted: false,
+            type_alias: None,
+        };
+        Schema::new(vec![
+            column(1, "id", SqlType::Int),
+            column(2, "name", SqlType::NVarChar(None)),
+        ])
+    }
+
+    fn row(id: i32, name: &str) -> Vec<u8> {
+        let name: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        record(&id.to_le_bytes(), 2, &[&name])
+    }
+
+    fn rows(rows: &[(i32, &str)]) -> MemoryPageProvider {
+        page(rows.iter().map(|(id, name)| row(*id, name)).collect())
+    }
+
+    fn table<T>(page_provider: &T, primary_key: Vec<usize>) -> Table<T> {
+        Table {
+            name: "test".to_string(),
+            object_id: 1,
+            page_provider,
+            schema: schema(),
+            index_id: 0,
+            page_owner_ids: vec![],
+            partition_pointer: vec![PTR],
+            iam_pointer: vec![],
+            index_root: vec![],
+            key_columns: vec![],
+            primary_key,
+            estimated_rows: 0,
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_by_primary_key() {
+        let old = rows(&[(3, "c"), (1, "a"), (2, "b")]);
+        let new = rows(&[(4, "d"), (2, "B"), (3, "c"), (0, "z")]);
+        let diff = table(&old, vec![0]).diff(&table(&new, vec![0])).unwrap();
+
+        assert_eq!(
+            diff,
+            TableDiff {
+                removed: vec![strings(&["1", "a"])],
+                added: vec![strings(&["0", "z"]), strings(&["4", "d"])],
+                changed: vec![(strings(&["2", "b"]), strings(&["2", "B"]))],
+            }
+        );
+    }
+
+    #[test]
+    fn diff_without_key_counts_duplicates() {
+        let old = rows(&[(1, "a"), (1, "a"), (2, "b")]);
+        let new = rows(&[(2, "B"), (1, "a")]);
+        let diff = table(&old, vec![]).diff(&table(&new, vec![])).unwrap();
+
+        assert_eq!(
+            diff,
+            TableDiff {
+                removed: vec![strings(&["1", "a"]), strings(&["2", "b"])],
+                added: vec![strings(&["2", "B"])],
+                changed: vec![],
+            }
+        );
+        assert!(table(&old, vec![])
+            .diff(&table(&old, vec![]))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn diff_of_different_schemas() {
+        let pp = rows(&[]);
+        let mut other = table(&pp, vec![]);
+        other.schema.columns[1].data_type = SqlType::Int;
+        assert!(matches!(
+            table(&pp, vec![]).diff(&other),
+            Err(SchemaMismatch::Type { .. })
+        ));
+    }
+
+    #[test]
+    fn owns_page_falls_back_to_the_p_min_len_of_the_first_page() {
+        let header = |p_min_len| {
+            let data = PageBuilder::new(PTR, PageType::Data)
+                .p_min_len(p_min_len)
+                .build();
+            MemoryPageProvider::new()
+                .with_page(PTR, data)
+                .get(PTR)
+                .unwrap()
+                .header
+        };
+        let expected = schema().expected_p_min_len().unwrap();
+
+        let pp = MemoryPageProvider::new().with_page(
+            PTR,
+            PageBuilder::new(PTR, PageType::Data)
+                .p_min_len(expected + 4)
+                .build(),
+        );
+        let first_page = table(&pp, vec![]);
+        assert!(first_page.owns_page(&header(expected + 4)));
+        assert!(!first_page.owns_page(&header(expected)));
+
+        // the first page can not be read
+        let empty = MemoryPageProvider::new();
+        let schema_only = table(&empty, vec![]);
+        assert!(schema_only.owns_page(&header(expected)));
+        assert!(!schema_only.owns_page(&header(expected + 4)));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_scan_db() {
+        use rayon::iter::ParallelIterator;
+
+        // pages of the table in two extents, and a page of another table
+        let page = |page_id, object_id, rows: &[(i32, &str)]| {
+            let ptr = PagePointer {
+                page_id,
+                file_id: 1,
+            };
+            let page = rows
+                .iter()
+                .fold(PageBuilder::new(ptr, PageType::Data), |page, (id, name)| {
+                    page.record(row(*id, name))
+                });
+            (ptr, page.owner(object_id, 0).build())
+        };
+        let pp = vec![
+            page(1, 7, &[(1, "a"), (2, "b")]),
+            page(9, 7, &[(3, "c")]),
+            page(10, 8, &[(4, "d")]),
+        ]
+        .into_iter()
+        .fold(MemoryPageProvider::new(), |pp, (ptr, data)| {
+            pp.with_page(ptr, data)
+        });
+        let mut table = table(&pp, vec![]);
+        table.page_owner_ids = vec![(7, 0)];
+
+        let mut ids: Vec<_> = table
+            .par_scan_db()
+            .map(|row| match row.get("id") {
+                Some(crate::OwnedSqlValue::Int(id)) => *id,
+                other => panic!("unexpected id {:?}", other),
+            })
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn seek_and_invalid_keys() {
+        let pp = rows(&[(1, "a"), (2, "b"), (2, "c"), (3, "d")]);
+        let mut table = table(&pp, vec![]);
+        table.index_root = vec![PTR];
+        let table = table.with_key_columns(&["id"]).unwrap();
+
+        let names: Vec<_> = table
+            .seek(&[SqlValue::Int(2)])
+            .unwrap()
+            .map(|row| {
+                row.values[1]
+                    .as_ref()
+                    .and_then(SqlValue::as_str)
+                    .map(str::to_string)
             })
+            .collect();
+        assert_eq!(names, vec![Some("b".to_string()), Some("c".to_string())]);
+
+        let name = SqlValue::NVarChar(ValueOrLob::Value("b".to_string()));
+        assert!(matches!(table.seek(&[name]), Err(MdfError::InvalidKey(_))));
+        assert!(matches!(
+            table.seek(&[SqlValue::Int(2), SqlValue::Int(2)]),
+            Err(MdfError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            table.with_key_columns(&["nope"]),
+            Err(MdfError::InvalidKey(_))
+        ));
     }
 }
@@ -1,23 +1,32 @@
-use crate::util::parse_utf16_string;
-use crate::{ColParStatus, LobPointer, Record, SysColPar, SysScalarType};
+use crate::util::{decode_utf16, parse_utf16_string, slice, to_hex};
+use crate::{
+    ColParStatus, LobDataBlocks, LobPointer, MdfError, PageBytes, PageProvider, Record,
+    SchemaMismatch, SysColPar, SysRsCol, SysScalarType,
+};
 use byteorder::{LittleEndian, ReadBytesExt};
-use log::trace;
-use std::io::Cursor;
+use encoding_rs::Encoding;
+use log::{trace, warn};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SqlType {
     TinyInt,
     SmallInt,
     Int,
     BigInt,
     Binary(usize),
-    Char(usize),
+    Char(usize, CodePage),
     NChar(usize),
     VarBinary(Option<usize>),
-    VarChar(Option<usize>),
+    VarChar(Option<usize>, CodePage),
     Bit,
     SqlVariant,
-    NVarChar,
+    // size in bytes, like `NChar`
+    NVarChar(Option<usize>),
     SysName,
     DateTime,
     SmallDateTime,
@@ -25,175 +34,785 @@ pub enum SqlType {
     Image,
     NText,
     Float,
+    Decimal { precision: u8, scale: u8 },
+    Money,
+    SmallMoney,
+    Real,
+    Date,
+    Time(u8),
+    DateTime2(u8),
+    DateTimeOffset(u8),
+}
+
+// -1 is used for `(max)`
+fn max_length(length: i16) -> Result<Option<usize>, MdfError> {
+    if length == -1 {
+        Ok(None)
+    } else {
+        fixed_length(length).map(Some)
+    }
+}
+
+// The `length`, `prec` and `scale` of `SqlType::from_tsql_name` given by the arguments of the
+// type declaration `name(args)`, `None` if they don't fit the type
+fn parse_tsql_args(name: &str, args: &str) -> Option<(i16, u8, u8)> {
+    let args: Vec<_> = args.split(',').map(str::trim).collect();
+    let length = |arg: &str, char_size: i16| match arg {
+        "max" => Some(-1),
+        n => n.parse::<i16>().ok()?.checked_mul(char_size),
+    };
+    Some(match (name, &args[..]) {
+        ("binary" | "char" | "varbinary" | "varchar", [n]) => (length(n, 1)?, 0, 0),
+        ("nchar" | "nvarchar", [n]) => (length(n, 2)?, 0, 0),
+        ("decimal" | "numeric", [precision]) => (0, precision.parse().ok()?, 0),
+        ("decimal" | "numeric", [precision, scale]) => {
+            (0, precision.parse().ok()?, scale.parse().ok()?)
+        }
+        ("time" | "datetime2" | "datetimeoffset", [scale]) => (0, 0, scale.parse().ok()?),
+        // float(n) with n <= 24 is stored like a real
+        ("float", [n]) => (if n.parse::<u8>().ok()? <= 24 { 4 } else { 8 }, 0, 0),
+        _ => return None,
+    })
+}
+
+// types without a `(max)` variant never have a negative length
+fn fixed_length(length: i16) -> Result<usize, MdfError> {
+    usize::try_from(length)
+        .map_err(|_| MdfError::Corrupt(format!("invalid column length {}", length)))
+}
+
+// The encoding of `char` and `varchar` values, determined by the collation of the column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodePage(pub &'static Encoding);
+
+impl Default for CodePage {
+    fn default() -> Self {
+        CodePage(encoding_rs::WINDOWS_1252)
+    }
+}
+
+impl CodePage {
+    // SQL collations (`SQL_*`) store their sort order in the high byte of the collation id,
+    // for Windows collations it is 0 and the low 20 bits are the LCID of the collation.
+    // Unknown collations fall back to windows-1252.
+    // The OEM code pages 437 and 850 of some SQL sort orders are not supported by
+    // `encoding_rs`, but agree with windows-1252 on ASCII.
+    pub fn from_collation_id(collation_id: i32) -> Self {
+        let sort_id = (collation_id as u32 >> 24) as u8;
+        let encoding = match sort_id {
+            0 => Self::encoding_for_lcid(collation_id as u32 & 0xf_ffff),
+            1..=79 => Some(encoding_rs::WINDOWS_1252),
+            80..=99 => Some(encoding_rs::WINDOWS_1250),
+            104..=111 => Some(encoding_rs::WINDOWS_1251),
+            112..=127 => Some(encoding_rs::WINDOWS_1253),
+            128..=143 => Some(encoding_rs::WINDOWS_1254),
+            144..=159 => Some(encoding_rs::WINDOWS_1255),
+            160..=175 => Some(encoding_rs::WINDOWS_1256),
+            176..=191 => Some(encoding_rs::WINDOWS_1257),
+            _ => None,
+        };
+        CodePage(encoding.unwrap_or_else(|| {
+            warn!(
+                "unknown collation {:#x}, decoding as windows-1252",
+                collation_id
+            );
+            encoding_rs::WINDOWS_1252
+        }))
+    }
+
+    // The ANSI code page of the locale, by primary language and sublanguage of the LCID
+    fn encoding_for_lcid(lcid: u32) -> Option<&'static Encoding> {
+        let (language, sublanguage) = (lcid & 0x3ff, lcid >> 10);
+        if sublanguage > 0x14 {
+            return None;
+        }
+        Some(match (language, sublanguage) {
+            // Chinese: Taiwan, Hong Kong and Macao use traditional characters
+            (0x04, 1) | (0x04, 3) | (0x04, 5) => encoding_rs::BIG5,
+            (0x04, 2) | (0x04, 4) => encoding_rs::GBK,
+            (0x11, _) => encoding_rs::SHIFT_JIS,
+            (0x12, _) => encoding_rs::EUC_KR,
+            (0x1e, _) => encoding_rs::WINDOWS_874,
+            // Croatian, Serbian and Bosnian, in latin or cyrillic script
+            (0x1a, 3) | (0x1a, 7) | (0x1a, 8) => encoding_rs::WINDOWS_1251,
+            (0x1a, _) => encoding_rs::WINDOWS_1250,
+            // Azeri and Uzbek, in latin or cyrillic script
+            (0x2c, 2) | (0x43, 2) => encoding_rs::WINDOWS_1251,
+            (0x2c, _) | (0x43, _) | (0x1f, _) => encoding_rs::WINDOWS_1254,
+            // Czech, Hungarian, Polish, Romanian, Slovak, Albanian, Slovenian
+            (0x05, _) | (0x0e, _) | (0x15, _) | (0x18, _) | (0x1b, _) | (0x1c, _) | (0x24, _) => {
+                encoding_rs::WINDOWS_1250
+            }
+            // Bulgarian, Russian, Ukrainian, Belarusian, Macedonian, Kazakh, Kyrgyz, Tatar,
+            // Mongolian
+            (0x02, _)
+            | (0x19, _)
+            | (0x22, _)
+            | (0x23, _)
+            | (0x2f, _)
+            | (0x3f, _)
+            | (0x40, _)
+            | (0x44, _)
+            | (0x50, _) => encoding_rs::WINDOWS_1251,
+            (0x08, _) => encoding_rs::WINDOWS_1253,
+            (0x0d, _) => encoding_rs::WINDOWS_1255,
+            // Arabic, Urdu, Farsi
+            (0x01, _) | (0x20, _) | (0x29, _) => encoding_rs::WINDOWS_1256,
+            // Estonian, Latvian, Lithuanian
+            (0x25, _) | (0x26, _) | (0x27, _) => encoding_rs::WINDOWS_1257,
+            (0x2a, _) => encoding_rs::WINDOWS_1258,
+            // Catalan, Danish, German, English, Spanish, Finnish, French, Icelandic, Italian,
+            // Dutch, Norwegian, Portuguese, Swedish, Indonesian, Basque, Afrikaans, Faroese,
+            // Malay, Swahili, Galician
+            (0x03, _)
+            | (0x06, _)
+            | (0x07, _)
+            | (0x09, _)
+            | (0x0a, _)
+            | (0x0b, _)
+            | (0x0c, _)
+            | (0x0f, _)
+            | (0x10, _)
+            | (0x13, _)
+            | (0x14, _)
+            | (0x16, _)
+            | (0x1d, _)
+            | (0x21, _)
+            | (0x2d, _)
+            | (0x36, _)
+            | (0x38, _)
+            | (0x3e, _)
+            | (0x41, _)
+            | (0x56, _) => encoding_rs::WINDOWS_1252,
+            _ => return None,
+        })
+    }
+
+    // Invalid sequences are replaced with U+FFFD instead of failing
+    pub fn decode(&self, data: &[u8]) -> String {
+        self.decode_lossy(data).0
+    }
+
+    // Like `decode`, but also returns whether invalid sequences were replaced
+    pub fn decode_lossy(&self, data: &[u8]) -> (String, bool) {
+        let (s, _, had_errors) = self.0.decode(data);
+        (s.into_owned(), had_errors)
+    }
 }
 
 impl SqlType {
-    pub fn from_col(col: &SysColPar, ty: &SysScalarType) -> Self {
-        match ty.name.as_str() {
+    pub fn from_col(col: &SysColPar, ty: &SysScalarType) -> Result<Self, MdfError> {
+        Ok(
+            Self::from_tsql_name(&ty.name, col.length, col.prec as u8, col.scale as u8)?
+                .with_code_page(CodePage::from_collation_id(col.collation_id)),
+        )
+    }
+
+    // Changes the code page of `char` and `varchar` types, all other types are unchanged
+    pub fn with_code_page(self, code_page: CodePage) -> Self {
+        match self {
+            Self::Char(size, _) => Self::Char(size, code_page),
+            Self::VarChar(size, _) => Self::VarChar(size, code_page),
+            ty => ty,
+        }
+    }
+
+    // `length` is the storage size in bytes (like `SysColPar::length`),
+    // so for `nchar(n)` it is 2 * n.
+    // `name` can also be a complete declaration like the ones returned by `to_tsql_name`
+    // (`nvarchar(n)`, `varchar(max)`, `decimal(p, s)`, ...), the arguments in it take the
+    // place of `length`, `prec` and `scale`
+    pub fn from_tsql_name(name: &str, length: i16, prec: u8, scale: u8) -> Result<Self, MdfError> {
+        if let Some((name, args)) = name.strip_suffix(')').and_then(|name| name.split_once('(')) {
+            let (length, prec, scale) = parse_tsql_args(name.trim(), args)
+                .ok_or_else(|| MdfError::UnknownType(format!("{}({})", name, args)))?;
+            return Self::from_tsql_name(name.trim(), length, prec, scale);
+        }
+
+        Ok(match name {
             "tinyint" => Self::TinyInt,
             "smallint" => Self::SmallInt,
             "int" => Self::Int,
             "bigint" => Self::BigInt,
-            "binary" => Self::Binary(col.length as usize),
-            "char" => Self::Char(col.length as usize),
-            "nchar" => Self::NChar(col.length as usize),
-            "varbinary" => Self::VarBinary(Some(col.length as usize)),
-            "varchar" => Self::VarChar(Some(col.length as usize)),
+            "binary" => Self::Binary(fixed_length(length)?),
+            "char" => Self::Char(fixed_length(length)?, CodePage::default()),
+            "nchar" => Self::NChar(fixed_length(length)?),
+            "varbinary" => Self::VarBinary(max_length(length)?),
+            "varchar" => Self::VarChar(max_length(length)?, CodePage::default()),
             "bit" => Self::Bit,
-            "nvarchar" => Self::NVarChar,
+            "nvarchar" => Self::NVarChar(max_length(length)?),
             "sysname" => Self::SysName,
             "uniqueidentifier" => Self::UniqueIdentifier,
             "datetime" => Self::DateTime,
             "sql_variant" => Self::SqlVariant,
             "image" => Self::Image,
             "ntext" => Self::NText,
+            // float(n) with n <= 24 is stored like a real
+            "float" if length == 4 => Self::Real,
             "float" => Self::Float,
+            "real" => Self::Real,
+            "date" => Self::Date,
+            "time" => Self::Time(scale),
+            "datetime2" => Self::DateTime2(scale),
+            "datetimeoffset" => Self::DateTimeOffset(scale),
             "smalldatetime" => Self::SmallDateTime,
-            _ => panic!("unknown column type\n{:?}\n{:?}", col, ty),
+            "decimal" | "numeric" => Self::Decimal {
+                precision: prec,
+                scale,
+            },
+            "money" => Self::Money,
+            "smallmoney" => Self::SmallMoney,
+            _ => return Err(MdfError::UnknownType(name.to_string())),
+        })
+    }
+
+    pub fn to_tsql_name(&self) -> String {
+        format!("{}{}", self.type_name(), self.length_suffix())
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::TinyInt => "tinyint",
+            Self::SmallInt => "smallint",
+            Self::Int => "int",
+            Self::BigInt => "bigint",
+            Self::Binary(_) => "binary",
+            Self::Char(..) => "char",
+            Self::NChar(_) => "nchar",
+            Self::VarBinary(_) => "varbinary",
+            Self::VarChar(..) => "varchar",
+            Self::Bit => "bit",
+            Self::NVarChar(_) => "nvarchar",
+            Self::SysName => "sysname",
+            Self::UniqueIdentifier => "uniqueidentifier",
+            Self::DateTime => "datetime",
+            Self::SqlVariant => "sql_variant",
+            Self::Image => "image",
+            Self::NText => "ntext",
+            Self::Float => "float",
+            Self::SmallDateTime => "smalldatetime",
+            Self::Decimal { .. } => "decimal",
+            Self::Money => "money",
+            Self::SmallMoney => "smallmoney",
+            Self::Real => "real",
+            Self::Date => "date",
+            Self::Time(_) => "time",
+            Self::DateTime2(_) => "datetime2",
+            Self::DateTimeOffset(_) => "datetimeoffset",
+        }
+    }
+
+    // The `(length)`, `(max)`, `(precision, scale)` or `(scale)` following the type name,
+    // empty for types without one
+    pub fn length_suffix(&self) -> String {
+        match self {
+            Self::Binary(size) | Self::Char(size, _) => format!("({})", size),
+            Self::NChar(size) | Self::NVarChar(Some(size)) => format!("({})", size / 2),
+            Self::VarBinary(Some(size)) | Self::VarChar(Some(size), _) => format!("({})", size),
+            Self::VarBinary(None) | Self::VarChar(None, _) | Self::NVarChar(None) => {
+                "(max)".to_string()
+            }
+            Self::Decimal { precision, scale } => format!("({}, {})", precision, scale),
+            Self::Time(scale) | Self::DateTime2(scale) | Self::DateTimeOffset(scale) => {
+                format!("({})", scale)
+            }
+            _ => String::new(),
         }
     }
 
+    // The declared length in bytes of binary and string types, `None` for `(max)` and all
+    // other types. Like `SysColPar::length`, `nchar(n)` and `nvarchar(n)` have a length of 2 * n
+    pub fn max_length(&self) -> Option<usize> {
+        match self {
+            Self::Binary(size) | Self::Char(size, _) | Self::NChar(size) => Some(*size),
+            Self::VarBinary(size) | Self::VarChar(size, _) | Self::NVarChar(size) => *size,
+            _ => None,
+        }
+    }
+
+    // Number of bytes a value of this type takes up in the fixed data of a record, `None` for
+    // variable length types and `bit`, as up to 8 `bit` columns share a byte
+    pub fn fixed_size(&self) -> Option<usize> {
+        let time_size = |scale: u8| match scale {
+            0..=2 => 3,
+            3..=4 => 4,
+            _ => 5,
+        };
+        Some(match self {
+            Self::TinyInt => 1,
+            Self::SmallInt => 2,
+            Self::Int | Self::Real | Self::SmallMoney | Self::SmallDateTime => 4,
+            Self::BigInt | Self::Float | Self::Money | Self::DateTime => 8,
+            Self::Binary(size) | Self::Char(size, _) | Self::NChar(size) => *size,
+            Self::UniqueIdentifier => 16,
+            // a sign byte followed by the mantissa
+            Self::Decimal { precision, .. } => match precision {
+                1..=9 => 5,
+                10..=19 => 9,
+                20..=28 => 13,
+                _ => 17,
+            },
+            Self::Date => 3,
+            Self::Time(scale) => time_size(*scale),
+            Self::DateTime2(scale) => time_size(*scale) + 3,
+            Self::DateTimeOffset(scale) => time_size(*scale) + 5,
+            _ => return None,
+        })
+    }
+
+    // (precision, scale) of `decimal` and `numeric` types
+    pub fn precision_scale(&self) -> Option<(u8, u8)> {
+        match self {
+            Self::Decimal { precision, scale } => Some((*precision, *scale)),
+            _ => None,
+        }
+    }
+
+    // The number of fractional second digits of `time`, `datetime2` and `datetimeoffset` types
+    pub fn fractional_seconds_scale(&self) -> Option<u8> {
+        match self {
+            Self::Time(scale) | Self::DateTime2(scale) | Self::DateTimeOffset(scale) => {
+                Some(*scale)
+            }
+            _ => None,
+        }
+    }
+
+    // `varbinary(max)`, `varchar(max)` and `nvarchar(max)`, their values are stored in the row
+    // if they fit and in LOB pages otherwise, see `ValueOrLob`
+    pub fn is_max(&self) -> bool {
+        matches!(
+            self,
+            Self::VarBinary(None) | Self::VarChar(None, _) | Self::NVarChar(None)
+        )
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Self::TinyInt
+                | Self::SmallInt
+                | Self::Int
+                | Self::BigInt
+                | Self::Float
+                | Self::Real
+                | Self::Decimal { .. }
+                | Self::Money
+                | Self::SmallMoney
+        )
+    }
+
     pub fn is_var_length(&self) -> bool {
         use SqlType::*;
         match self {
-            TinyInt | SmallInt | Int | BigInt | Binary(_) | Char(_) | NChar(_) | DateTime
-            | UniqueIdentifier | Bit | Float | SmallDateTime => false,
-            VarBinary(_) | VarChar(_) | SysName | NVarChar | SqlVariant | Image | NText => true,
+            TinyInt
+            | SmallInt
+            | Int
+            | BigInt
+            | Binary(_)
+            | Char(..)
+            | NChar(_)
+            | DateTime
+            | UniqueIdentifier
+            | Bit
+            | Float
+            | SmallDateTime
+            | Decimal { .. }
+            | Money
+            | SmallMoney
+            | Real
+            | Date
+            | Time(_)
+            | DateTime2(_)
+            | DateTimeOffset(_) => false,
+            VarBinary(_) | VarChar(..) | SysName | NVarChar(_) | SqlVariant | Image | NText => true,
+        }
+    }
+
+    // Whether values of both types are stored the same way. The maximum length of variable
+    // length types and the code page of `char` / `varchar` don't change how they are stored
+    pub fn has_same_storage(&self, other: &SqlType) -> bool {
+        if std::mem::discriminant(self) != std::mem::discriminant(other) {
+            false
+        } else {
+            self.is_var_length() || self.length_suffix() == other.length_suffix()
         }
     }
 
     // TODO(robin): think of way to consolidate these two
-    pub fn parse_var_length<'a>(&self, complex: bool, data: &'a [u8]) -> SqlValue<'a> {
-        match self {
-            Self::VarBinary(max_size) => {
-                SqlValue::VarBinary(if complex {
-                    ValueOrLob::Lob(LobPointer::parse(data))
-                } else {
-                    if let Some(max_size) = max_size {
-                        if data.len() > *max_size {
-                            println!(
-                                "got a value longer than we wanted {} > {}",
-                                data.len(),
-                                *max_size
-                            );
-                        }
-                        // Maybe this is actually allowed?
-                        // what is the meaning of it then?
-                        // assert!(data.len() <= *max_size);
-                    }
-                    ValueOrLob::Value(data)
-                })
-            }
-            Self::VarChar(max_size) => {
-                assert!(!complex);
+    pub fn parse_var_length<'a>(
+        &self,
+        complex: bool,
+        data: PageBytes<'a>,
+    ) -> Result<SqlValue<'a>, MdfError> {
+        self.parse_var_length_checked(complex, data, &mut false)
+    }
 
-                if let Some(max_size) = max_size {
-                    assert!(data.len() <= *max_size);
-                }
-                SqlValue::VarChar(data)
-            }
-            Self::Image => SqlValue::Image(if !data.is_empty() {
-                assert!(complex);
-                assert_eq!(data.len(), 16);
-                Some(LobPointer::parse(data))
-            } else {
-                None
-            }),
-            Self::NText => SqlValue::Image(if !data.is_empty() {
-                assert!(complex);
-                assert_eq!(data.len(), 16);
-                Some(LobPointer::parse(data))
+    // Like `parse_var_length`, but sets `lossy` if characters of a string value could not
+    // be decoded and were replaced with U+FFFD
+    fn parse_var_length_checked<'a>(
+        &self,
+        complex: bool,
+        data: PageBytes<'a>,
+        lossy: &mut bool,
+    ) -> Result<SqlValue<'a>, MdfError> {
+        let mut decode = |(s, had_errors): (String, bool)| {
+            *lossy |= had_errors;
+            s
+        };
+        Ok(match self {
+            Self::VarBinary(max_size) => SqlValue::VarBinary(if complex {
+                ValueOrLob::Lob(LobPointer::parse(&data)?)
             } else {
-                None
+                warn_if_too_long(self, &data, *max_size);
+                ValueOrLob::Value(data)
             }),
-            Self::SysName => {
-                assert!(!complex);
-                SqlValue::SysName(parse_utf16_string(data))
+            Self::VarChar(max_size, code_page) => {
+                // values that don't fit into the row are moved to row-overflow pages
+                SqlValue::VarChar(if complex {
+                    ValueOrLob::Lob(LobPointer::parse(&data)?)
+                } else {
+                    warn_if_too_long(self, &data, *max_size);
+                    ValueOrLob::Value(decode(code_page.decode_lossy(&data)))
+                })
             }
-            Self::NVarChar => SqlValue::NVarChar(if complex {
-                ValueOrLob::Lob(LobPointer::parse(data))
+            Self::Image => SqlValue::Image(parse_text_pointer(complex, &data)?),
+            Self::NText => SqlValue::NText(parse_text_pointer(complex, &data)?),
+            // a `sysname` is a `nvarchar(128)`, which always fits into the row. If it is
+            // marked as stored out of row anyway, it is returned as a `nvarchar` LOB
+            Self::SysName if complex => {
+                warn!("sysname value stored out of row, treating it as a LOB pointer");
+                SqlValue::NVarChar(ValueOrLob::Lob(LobPointer::parse(&data)?))
+            }
+            Self::SysName => SqlValue::SysName(decode(decode_utf16(&data))),
+            Self::NVarChar(max_size) => SqlValue::NVarChar(if complex {
+                ValueOrLob::Lob(LobPointer::parse(&data)?)
             } else {
-                ValueOrLob::Value(parse_utf16_string(data))
+                warn_if_too_long(self, &data, *max_size);
+                ValueOrLob::Value(decode(decode_utf16(&data)))
             }),
-            // TODO(robin): proper parsing
             Self::SqlVariant => {
-                assert!(!complex);
-                SqlValue::SqlVariant(data)
+                expect_in_row(self, complex)?;
+                parse_sql_variant(data, lossy)?
             }
             _ => panic!(
                 "cannot parse fixed length type using `parse_var_length`: {:?}",
                 self
             ),
-        }
+        })
     }
 
     pub fn parse<'a>(
         &self,
         bit_parser: &mut BitParser,
-        cursor: &mut Cursor<&'a [u8]>,
-    ) -> SqlValue<'a> {
-        match self {
-            Self::TinyInt => SqlValue::TinyInt(cursor.read_i8().unwrap()),
-            Self::SmallInt => SqlValue::SmallInt(cursor.read_i16::<LittleEndian>().unwrap()),
-            Self::Int => SqlValue::Int(cursor.read_i32::<LittleEndian>().unwrap()),
-            Self::BigInt => SqlValue::BigInt(cursor.read_i64::<LittleEndian>().unwrap()),
-            Self::Bit => SqlValue::Bit(bit_parser.read_bit(cursor)),
-            Self::Float => SqlValue::Float(cursor.read_f64::<LittleEndian>().unwrap()),
+        cursor: &mut Cursor<PageBytes<'a>>,
+    ) -> Result<SqlValue<'a>, MdfError> {
+        self.parse_checked(bit_parser, cursor, &mut false)
+    }
+
+    // Like `parse`, see `parse_var_length_checked` for `lossy`
+    fn parse_checked<'a>(
+        &self,
+        bit_parser: &mut BitParser,
+        cursor: &mut Cursor<PageBytes<'a>>,
+        lossy: &mut bool,
+    ) -> Result<SqlValue<'a>, MdfError> {
+        let mut decode = |(s, had_errors): (String, bool)| {
+            *lossy |= had_errors;
+            s
+        };
+        Ok(match self {
+            Self::TinyInt => SqlValue::TinyInt(cursor.read_i8()?),
+            Self::SmallInt => SqlValue::SmallInt(cursor.read_i16::<LittleEndian>()?),
+            Self::Int => SqlValue::Int(cursor.read_i32::<LittleEndian>()?),
+            Self::BigInt => SqlValue::BigInt(cursor.read_i64::<LittleEndian>()?),
+            Self::Bit => SqlValue::Bit(bit_parser.read_bit(cursor)?),
+            Self::Float => SqlValue::Float(cursor.read_f64::<LittleEndian>()?),
+            Self::Real => SqlValue::Real(cursor.read_f32::<LittleEndian>()?),
+            Self::Date => SqlValue::Date(read_date(cursor)?),
+            Self::Time(scale) => SqlValue::Time(read_time(cursor, *scale)?),
+            Self::DateTime2(scale) => {
+                let time = read_time(cursor, *scale)?;
+                SqlValue::DateTime2(read_date(cursor)?.and_time(time))
+            }
+            Self::DateTimeOffset(scale) => {
+                // time and date are stored in UTC
+                let time = read_time(cursor, *scale)?;
+                let utc = read_date(cursor)?.and_time(time);
+                let offset_minutes = cursor.read_i16::<LittleEndian>()?;
+                let offset =
+                    chrono::FixedOffset::east_opt(offset_minutes as i32 * 60).ok_or_else(|| {
+                        MdfError::Corrupt(format!("invalid time zone offset {}", offset_minutes))
+                    })?;
+                SqlValue::DateTimeOffset(chrono::DateTime::from_naive_utc_and_offset(utc, offset))
+            }
+            Self::Decimal { precision, scale } => {
+                if *precision > 38 || *scale > 38 {
+                    return Err(MdfError::Corrupt(format!(
+                        "invalid decimal precision {} and scale {}",
+                        precision, scale
+                    )));
+                }
+                // a sign byte (1 for positive) followed by the mantissa,
+                // whose size depends on the precision
+                let size = match precision {
+                    1..=9 => 4,
+                    10..=19 => 8,
+                    20..=28 => 12,
+                    _ => 16,
+                };
+                let positive = cursor.read_u8()? == 1;
+                let mut bytes = [0u8; 16];
+                cursor.read_exact(&mut bytes[..size])?;
+                let mantissa = i128::from_le_bytes(bytes);
+
+                let value = if positive {
+                    Some(mantissa)
+                } else {
+                    mantissa.checked_neg()
+                };
+                SqlValue::Decimal(
+                    value.ok_or_else(|| {
+                        MdfError::Corrupt(format!("decimal mantissa {} out of range", mantissa))
+                    })?,
+                    *scale,
+                )
+            }
+            Self::Money => SqlValue::Money(Money(cursor.read_i64::<LittleEndian>()?)),
+            Self::SmallMoney => SqlValue::Money(Money(cursor.read_i32::<LittleEndian>()? as i64)),
             Self::UniqueIdentifier => {
-                SqlValue::UniqueIdentifier(cursor.read_u128::<LittleEndian>().unwrap())
+                SqlValue::UniqueIdentifier(cursor.read_u128::<LittleEndian>()?)
             }
             Self::DateTime => {
-                let time = cursor.read_i32::<LittleEndian>().unwrap();
-                let date = cursor.read_i32::<LittleEndian>().unwrap();
+                // ticks of 1/300 seconds since midnight, followed by the days since 1900-01-01,
+                // which are negative for dates before 1900 (down to 1753-01-01)
+                let time = cursor.read_i32::<LittleEndian>()?;
+                let date = cursor.read_i32::<LittleEndian>()?;
                 let mut dt = chrono::NaiveDate::from_ymd(1900, 1, 1).and_hms(0, 0, 0);
-                // TODO(robin): wtf is happening here??
-                if date < 1_000_000 && date > 0 {
-                    dt += chrono::Duration::days(date as i64);
-                }
-                dt += chrono::Duration::milliseconds((time as i64) * 1000 / 300);
+                dt = add_duration(dt, chrono::Duration::days(date as i64))?;
+                // SQL Server rounds the ticks to the nearest millisecond (.000, .003, .007)
+                dt = add_duration(
+                    dt,
+                    chrono::Duration::milliseconds(((time as i64) * 1000 + 150) / 300),
+                )?;
 
                 SqlValue::DateTime(dt)
             }
             Self::SmallDateTime => {
-                let time = cursor.read_u16::<LittleEndian>().unwrap();
-                let date = cursor.read_u16::<LittleEndian>().unwrap();
+                let time = cursor.read_u16::<LittleEndian>()?;
+                let date = cursor.read_u16::<LittleEndian>()?;
                 let mut dt = chrono::NaiveDate::from_ymd(1900, 1, 1).and_hms(0, 0, 0);
                 dt += chrono::Duration::days(date as i64);
                 dt += chrono::Duration::minutes(time as i64);
 
                 SqlValue::DateTime(dt)
             }
-            Self::Binary(size) => {
-                let pos = cursor.position() as usize;
-                let ret = SqlValue::Binary(&cursor.get_ref()[pos..pos + size]);
-                cursor.set_position((pos + size) as u64);
-                ret
-            }
-            Self::Char(size) => {
-                let pos = cursor.position() as usize;
-                let ret = SqlValue::Char(
-                    std::str::from_utf8(&cursor.get_ref()[pos..pos + size]).unwrap(),
-                );
-                cursor.set_position((pos + size) as u64);
-                ret
-            }
-            Self::NChar(size) => {
-                let pos = cursor.position() as usize;
-                let ret = SqlValue::NChar(parse_utf16_string(&cursor.get_ref()[pos..pos + size]));
-                cursor.set_position((pos + size) as u64);
-                ret
+            Self::Binary(size) => SqlValue::Binary(read_bytes(cursor, *size)?),
+            Self::Char(size, code_page) => {
+                SqlValue::Char(decode(code_page.decode_lossy(&read_bytes(cursor, *size)?)))
             }
+            Self::NChar(size) => SqlValue::NChar(decode(decode_utf16(&read_bytes(cursor, *size)?))),
             _ => panic!("cannot parse var length type using `parse`"),
+        })
+    }
+}
+
+fn undecodable_warning(column: &str) -> String {
+    format!("{} contains characters that could not be decoded", column)
+}
+
+// A 1 byte base type (its `system_type_id`) and a 1 byte version, followed by the
+// properties of the base type and the value itself. Values of base types we don't know
+// are returned as raw `SqlValue::SqlVariant`
+fn parse_sql_variant<'a>(data: PageBytes<'a>, lossy: &mut bool) -> Result<SqlValue<'a>, MdfError> {
+    let base_type = *slice(&data, 0)?;
+    let props = data.slice(2..)?;
+    let props_size = match base_type {
+        // precision and scale
+        106 | 108 => 2,
+        // scale
+        41..=43 => 1,
+        // collation and maximum length
+        167 | 175 | 231 | 239 => 6,
+        // maximum length
+        165 | 173 => 2,
+        _ => 0,
+    };
+    let value = props.slice(props_size..)?;
+    // `props` is at least `props_size` long
+    let collation = || -> Result<CodePage, MdfError> {
+        let id = (&props[0..4]).read_i32::<LittleEndian>()?;
+        Ok(CodePage::from_collation_id(id))
+    };
+
+    let ty = match base_type {
+        48 => SqlType::TinyInt,
+        52 => SqlType::SmallInt,
+        56 => SqlType::Int,
+        127 => SqlType::BigInt,
+        104 => SqlType::Bit,
+        59 => SqlType::Real,
+        62 => SqlType::Float,
+        60 => SqlType::Money,
+        122 => SqlType::SmallMoney,
+        61 => SqlType::DateTime,
+        58 => SqlType::SmallDateTime,
+        40 => SqlType::Date,
+        41 => SqlType::Time(props[0]),
+        42 => SqlType::DateTime2(props[0]),
+        43 => SqlType::DateTimeOffset(props[0]),
+        36 => SqlType::UniqueIdentifier,
+        106 | 108 => SqlType::Decimal {
+            precision: props[0],
+            scale: props[1],
+        },
+        167 => SqlType::VarChar(None, collation()?),
+        175 => SqlType::Char(value.len(), collation()?),
+        231 => SqlType::NVarChar(None),
+        239 => SqlType::NChar(value.len()),
+        165 => SqlType::VarBinary(None),
+        173 => SqlType::Binary(value.len()),
+        _ => return Ok(SqlValue::SqlVariant(data)),
+    };
+
+    if ty.is_var_length() {
+        ty.parse_var_length_checked(false, value, lossy)
+    } else {
+        ty.parse_checked(&mut BitParser::new(), &mut Cursor::new(value), lossy)
+    }
+}
+
+// Values longer than their declared size happen after schema changes, they are
+// still returned as they are, recovering as much data as possible
+fn warn_if_too_long(ty: &SqlType, data: &[u8], max_size: Option<usize>) {
+    if let Some(max_size) = max_size {
+        if data.len() > max_size {
+            warn!(
+                "{} value longer than its maximum size {} > {}",
+                ty.to_tsql_name(),
+                data.len(),
+                max_size
+            );
         }
     }
 }
 
+fn expect_in_row(ty: &SqlType, complex: bool) -> Result<(), MdfError> {
+    if complex {
+        Err(MdfError::Corrupt(format!(
+            "{:?} value can not be stored out of row",
+            ty
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+// `image` and `ntext` columns only store a 16 byte pointer to the LOB data in the record
+fn parse_text_pointer(complex: bool, data: &[u8]) -> Result<Option<LobPointer>, MdfError> {
+    if data.is_empty() {
+        Ok(None)
+    } else if !complex || data.len() != 16 {
+        Err(MdfError::Corrupt(format!(
+            "invalid text pointer of length {}, complex = {}",
+            data.len(),
+            complex
+        )))
+    } else {
+        Ok(Some(LobPointer::parse(data)?))
+    }
+}
+
+fn add_duration(
+    dt: chrono::NaiveDateTime,
+    duration: chrono::Duration,
+) -> Result<chrono::NaiveDateTime, MdfError> {
+    dt.checked_add_signed(duration)
+        .ok_or_else(|| MdfError::Corrupt(format!("{} + {} is out of range", dt, duration)))
+}
+
+fn read_bytes<'a>(
+    cursor: &mut Cursor<PageBytes<'a>>,
+    size: usize,
+) -> Result<PageBytes<'a>, MdfError> {
+    let pos = cursor.position() as usize;
+    let bytes = cursor.get_ref().slice(pos..pos + size)?;
+    cursor.set_position((pos + size) as u64);
+    Ok(bytes)
+}
+
+// days since 0001-01-01, stored in 3 bytes
+fn read_date(cursor: &mut impl Read) -> Result<chrono::NaiveDate, MdfError> {
+    let days = cursor.read_u24::<LittleEndian>()?;
+    chrono::NaiveDate::from_ymd_opt(1, 1, 1)
+        .and_then(|date| date.checked_add_signed(chrono::Duration::days(days as i64)))
+        .ok_or_else(|| MdfError::Corrupt(format!("date with {} days is out of range", days)))
+}
+
+// ticks of 10^-scale seconds since midnight, the number of bytes depends on the scale
+fn read_time(cursor: &mut impl Read, scale: u8) -> Result<chrono::NaiveTime, MdfError> {
+    let size = match scale {
+        0..=2 => 3,
+        3..=4 => 4,
+        5..=7 => 5,
+        _ => return Err(MdfError::Corrupt(format!("invalid time scale {}", scale))),
+    };
+    let ticks = cursor.read_uint::<LittleEndian>(size)?;
+    let ticks_per_second = 10u64.pow(scale as u32);
+    let nanos = (ticks % ticks_per_second) * 10u64.pow(9 - scale as u32);
+    chrono::NaiveTime::from_num_seconds_from_midnight_opt(
+        (ticks / ticks_per_second) as u32,
+        nanos as u32,
+    )
+    .ok_or_else(|| MdfError::Corrupt(format!("time with {} ticks is out of range", ticks)))
+}
+
 pub trait ToSqlType {
     fn to_sql_type() -> SqlType;
+
+    fn nullable() -> bool {
+        false
+    }
 }
 
-pub trait FromSqlValue<'a> {
-    fn from_sql_value(sql_value: SqlValue<'a>) -> Self;
+pub trait FromSqlValue<'a>: Sized {
+    // Gives the value back if it can't be converted
+    fn from_sql_value(sql_value: SqlValue<'a>) -> Result<Self, SqlValue<'a>>;
+
+    // NULL values can only be read into `Option`s
+    fn from_nullable_sql_value(
+        sql_value: Option<SqlValue<'a>>,
+    ) -> Result<Self, Option<SqlValue<'a>>> {
+        match sql_value {
+            Some(v) => Self::from_sql_value(v).map_err(Some),
+            None => Err(None),
+        }
+    }
+}
+
+impl<T: ToSqlType> ToSqlType for Option<T> {
+    fn to_sql_type() -> SqlType {
+        T::to_sql_type()
+    }
+
+    fn nullable() -> bool {
+        true
+    }
+}
+
+impl<'a, T: FromSqlValue<'a>> FromSqlValue<'a> for Option<T> {
+    fn from_sql_value(sql_value: SqlValue<'a>) -> Result<Self, SqlValue<'a>> {
+        T::from_sql_value(sql_value).map(Some)
+    }
+
+    fn from_nullable_sql_value(
+        sql_value: Option<SqlValue<'a>>,
+    ) -> Result<Self, Option<SqlValue<'a>>> {
+        match sql_value {
+            Some(v) => T::from_sql_value(v).map(Some).map_err(Some),
+            None => Ok(None),
+        }
+    }
 }
 
 macro_rules! impl_to_from_sql_for_literal {
@@ -206,10 +825,10 @@ macro_rules! impl_to_from_sql_for_literal {
             }
 
             impl<'a> FromSqlValue<'a> for $literal {
-                fn from_sql_value(sql_value: SqlValue<'a>) -> Self {
+                fn from_sql_value(sql_value: SqlValue<'a>) -> Result<Self, SqlValue<'a>> {
                     match sql_value {
-                        SqlValue::$sql_type(v) => v,
-                        _ => unreachable!()
+                        SqlValue::$sql_type(v) => Ok(v),
+                        v => Err(v),
                     }
                 }
             }
@@ -217,24 +836,69 @@ macro_rules! impl_to_from_sql_for_literal {
     }
 }
 
-impl_to_from_sql_for_literal!(i8 = TinyInt, i16 = SmallInt, i32 = Int, i64 = BigInt);
+impl_to_from_sql_for_literal!(
+    i8 = TinyInt,
+    i16 = SmallInt,
+    i32 = Int,
+    i64 = BigInt,
+    Money = Money,
+    bool = Bit,
+    f64 = Float,
+    u128 = UniqueIdentifier,
+);
 
-impl ToSqlType for ValueOrLob<&[u8]> {
+// Strings are parsed as `nvarchar` columns, but can be read from every string type
+impl ToSqlType for String {
+    fn to_sql_type() -> SqlType {
+        SqlType::NVarChar(None)
+    }
+}
+
+impl<'a> FromSqlValue<'a> for String {
+    fn from_sql_value(sql_value: SqlValue<'a>) -> Result<Self, SqlValue<'a>> {
+        match sql_value {
+            SqlValue::NVarChar(ValueOrLob::Value(s))
+            | SqlValue::NChar(s)
+            | SqlValue::SysName(s) => Ok(s),
+            SqlValue::Char(s) | SqlValue::VarChar(ValueOrLob::Value(s)) => Ok(s),
+            v => Err(v),
+        }
+    }
+}
+
+// The T-SQL name of the type, like `nvarchar(50)` or `decimal(18, 2)`
+impl std::fmt::Display for SqlType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.to_tsql_name())
+    }
+}
+
+// `money` and `smallmoney` values, in units of 1/10000
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Money(pub i64);
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", format_decimal(self.0 as i128, 4))
+    }
+}
+
+impl ToSqlType for ValueOrLob<PageBytes<'_>> {
     fn to_sql_type() -> SqlType {
         SqlType::VarBinary(None)
     }
 }
 
-impl<'a> FromSqlValue<'a> for ValueOrLob<&'a [u8]> {
-    fn from_sql_value(sql_value: SqlValue<'a>) -> Self {
+impl<'a> FromSqlValue<'a> for ValueOrLob<PageBytes<'a>> {
+    fn from_sql_value(sql_value: SqlValue<'a>) -> Result<Self, SqlValue<'a>> {
         match sql_value {
-            SqlValue::VarBinary(v) => v,
-            _ => unreachable!(),
+            SqlValue::VarBinary(v) => Ok(v),
+            v => Err(v),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ValueOrLob<T> {
     Value(T),
     Lob(LobPointer),
@@ -249,100 +913,564 @@ impl<T> ValueOrLob<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SqlValue<'a> {
     TinyInt(i8),
     SmallInt(i16),
     Int(i32),
     BigInt(i64),
     Bit(bool),
-    Binary(&'a [u8]),
-    Char(&'a str),
+    Binary(PageBytes<'a>),
+    Char(String),
     NChar(String),
     // always in a seperate database page
-    NText(&'a [u8]),
-    VarBinary(ValueOrLob<&'a [u8]>),
-    VarChar(&'a [u8]),
+    NText(Option<LobPointer>),
+    VarBinary(ValueOrLob<PageBytes<'a>>),
+    // out of row values are not decoded, see `CodePage::decode`
+    VarChar(ValueOrLob<String>),
+    SysName(String),
+    NVarChar(ValueOrLob<String>),
+    // `sql_variant` values are returned as their base type, this is only used for
+    // base types that can't be decoded
+    SqlVariant(PageBytes<'a>),
+    UniqueIdentifier(u128),
+    DateTime(chrono::NaiveDateTime),
+    SmallDateTime(chrono::NaiveDateTime),
+    Image(Option<LobPointer>),
+    Float(f64),
+    // the unscaled value and the number of fractional digits
+    Decimal(i128, u8),
+    Money(Money),
+    Real(f32),
+    Date(chrono::NaiveDate),
+    Time(chrono::NaiveTime),
+    DateTime2(chrono::NaiveDateTime),
+    DateTimeOffset(chrono::DateTime<chrono::FixedOffset>),
+}
+
+// Like `SqlValue`, but owning all of its data, so it can outlive the page it was read from
+#[derive(Debug, Clone)]
+pub enum OwnedSqlValue {
+    TinyInt(i8),
+    SmallInt(i16),
+    Int(i32),
+    BigInt(i64),
+    Bit(bool),
+    Binary(Vec<u8>),
+    Char(String),
+    NChar(String),
+    NText(Option<LobPointer>),
+    VarBinary(ValueOrLob<Vec<u8>>),
+    VarChar(ValueOrLob<String>),
     SysName(String),
     NVarChar(ValueOrLob<String>),
-    SqlVariant(&'a [u8]),
+    SqlVariant(Vec<u8>),
     UniqueIdentifier(u128),
     DateTime(chrono::NaiveDateTime),
     SmallDateTime(chrono::NaiveDateTime),
     Image(Option<LobPointer>),
     Float(f64),
+    Decimal(i128, u8),
+    Money(Money),
+    Real(f32),
+    Date(chrono::NaiveDate),
+    Time(chrono::NaiveTime),
+    DateTime2(chrono::NaiveDateTime),
+    DateTimeOffset(chrono::DateTime<chrono::FixedOffset>),
 }
 
-impl<'a> SqlValue<'a> {
-    pub fn unwrap_unique_identifier(self) -> u128 {
+impl<'a> SqlValue<'a> {
+    pub fn to_owned(&self) -> OwnedSqlValue {
+        match self {
+            Self::TinyInt(v) => OwnedSqlValue::TinyInt(*v),
+            Self::SmallInt(v) => OwnedSqlValue::SmallInt(*v),
+            Self::Int(v) => OwnedSqlValue::Int(*v),
+            Self::BigInt(v) => OwnedSqlValue::BigInt(*v),
+            Self::Bit(v) => OwnedSqlValue::Bit(*v),
+            Self::Binary(v) => OwnedSqlValue::Binary(v.to_vec()),
+            Self::Char(v) => OwnedSqlValue::Char(v.clone()),
+            Self::NChar(v) => OwnedSqlValue::NChar(v.clone()),
+            Self::NText(v) => OwnedSqlValue::NText(v.clone()),
+            Self::VarBinary(v) => OwnedSqlValue::VarBinary(v.clone().map(|v| v.to_vec())),
+            Self::VarChar(v) => OwnedSqlValue::VarChar(v.clone()),
+            Self::SysName(v) => OwnedSqlValue::SysName(v.clone()),
+            Self::NVarChar(v) => OwnedSqlValue::NVarChar(v.clone()),
+            Self::SqlVariant(v) => OwnedSqlValue::SqlVariant(v.to_vec()),
+            Self::UniqueIdentifier(v) => OwnedSqlValue::UniqueIdentifier(*v),
+            Self::DateTime(v) => OwnedSqlValue::DateTime(*v),
+            Self::SmallDateTime(v) => OwnedSqlValue::SmallDateTime(*v),
+            Self::Image(v) => OwnedSqlValue::Image(v.clone()),
+            Self::Float(v) => OwnedSqlValue::Float(*v),
+            Self::Decimal(v, scale) => OwnedSqlValue::Decimal(*v, *scale),
+            Self::Money(v) => OwnedSqlValue::Money(*v),
+            Self::Real(v) => OwnedSqlValue::Real(*v),
+            Self::Date(v) => OwnedSqlValue::Date(*v),
+            Self::Time(v) => OwnedSqlValue::Time(*v),
+            Self::DateTime2(v) => OwnedSqlValue::DateTime2(*v),
+            Self::DateTimeOffset(v) => OwnedSqlValue::DateTimeOffset(*v),
+        }
+    }
+
+    // Removes the padding of fixed length values: trailing spaces of `char` and `nchar` values
+    // and, if `trim_binary` is set, trailing zero bytes of `binary` values.
+    // The value itself still holds the data exactly as it was stored
+    pub fn trimmed(&self, trim_binary: bool) -> SqlValue<'a> {
+        match self {
+            Self::Char(s) => Self::Char(s.trim_end_matches(' ').to_string()),
+            Self::NChar(s) => Self::NChar(s.trim_end_matches(' ').to_string()),
+            Self::Binary(bytes) if trim_binary => {
+                let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |pos| pos + 1);
+                Self::Binary(bytes.slice(..len).unwrap())
+            }
+            v => v.clone(),
+        }
+    }
+
+    // Orders two values of the same kind, `None` if they can't be compared.
+    // All numeric types can be compared with each other, as can `date` and the datetime types.
+    // Strings are compared ordinally, which does not match most collations
+    pub fn compare(&self, other: &SqlValue) -> Option<Ordering> {
+        if let (Some(a), Some(b)) = (self.as_i64(), other.as_i64()) {
+            return Some(a.cmp(&b));
+        }
+        if let (Some(a), Some(b)) = (self.exact_numeric(), other.exact_numeric()) {
+            // bring both values to the same scale
+            let (a, a_scale) = a;
+            let (b, b_scale) = b;
+            let a = a.checked_mul(10i128.checked_pow(b_scale as u32)?)?;
+            let b = b.checked_mul(10i128.checked_pow(a_scale as u32)?)?;
+            return Some(a.cmp(&b));
+        }
+        if let (Some(a), Some(b)) = (self.approximate_numeric(), other.approximate_numeric()) {
+            return a.partial_cmp(&b);
+        }
+        if let (Some(a), Some(b)) = (self.as_str(), other.as_str()) {
+            return Some(a.cmp(b));
+        }
+        if let (Some(a), Some(b)) = (self.as_bytes(), other.as_bytes()) {
+            return Some(a.cmp(b));
+        }
+        if let (Some(a), Some(b)) = (self.as_datetime(), other.as_datetime()) {
+            return Some(a.cmp(&b));
+        }
+
+        match (self, other) {
+            (SqlValue::Time(a), SqlValue::Time(b)) => Some(a.cmp(b)),
+            (SqlValue::DateTimeOffset(a), SqlValue::DateTimeOffset(b)) => Some(a.cmp(b)),
+            (SqlValue::UniqueIdentifier(a), SqlValue::UniqueIdentifier(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+
+    // integers, `decimal` and `money` as an unscaled value and scale
+    fn exact_numeric(&self) -> Option<(i128, u8)> {
+        self.as_decimal()
+            .or_else(|| self.as_i64().map(|v| (v as i128, 0)))
+    }
+
+    // every numeric value, as soon as a `float` or `real` is involved
+    fn approximate_numeric(&self) -> Option<f64> {
+        self.as_f64().or_else(|| self.as_i64().map(|v| v as f64))
+    }
+
+    // All integer types (and `bit`) widened to an i64
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::TinyInt(v) => Some(*v as i64),
+            Self::SmallInt(v) => Some(*v as i64),
+            Self::Int(v) => Some(*v as i64),
+            Self::BigInt(v) => Some(*v),
+            Self::Bit(v) => Some(*v as i64),
+            _ => None,
+        }
+    }
+
+    // Like `as_i64`, `None` if the value does not fit
+    pub fn as_i32(&self) -> Option<i32> {
+        self.as_i64().and_then(|v| i32::try_from(v).ok())
+    }
+
+    pub fn as_i16(&self) -> Option<i16> {
+        self.as_i64().and_then(|v| i16::try_from(v).ok())
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bit(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    // `float` and `real` values, as well as `decimal` and `money` (which may lose precision)
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Float(v) => Some(*v),
+            Self::Real(v) => Some(*v as f64),
+            Self::Decimal(..) | Self::Money(_) => {
+                let (value, scale) = self.as_decimal()?;
+                Some(value as f64 / 10f64.powi(scale as i32))
+            }
+            _ => None,
+        }
+    }
+
+    // The unscaled value and the number of fractional digits of `decimal` and `money` values
+    pub fn as_decimal(&self) -> Option<(i128, u8)> {
+        match self {
+            Self::Decimal(value, scale) => Some((*value, *scale)),
+            Self::Money(money) => Some((money.0 as i128, 4)),
+            _ => None,
+        }
+    }
+
+    pub fn as_money(&self) -> Option<Money> {
+        match self {
+            Self::Money(money) => Some(*money),
+            _ => None,
+        }
+    }
+
+    // The in row string of a `nchar`, `nvarchar`, `sysname`, `char` or `varchar` value,
+    // see `read_string` for values stored out of row
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::NChar(s)
+            | Self::SysName(s)
+            | Self::NVarChar(ValueOrLob::Value(s))
+            | Self::Char(s)
+            | Self::VarChar(ValueOrLob::Value(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    // The in row data of a `binary`, `varbinary` or undecodable `sql_variant` value,
+    // see `read_bytes` for values stored out of row
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Binary(b) | Self::VarBinary(ValueOrLob::Value(b)) | Self::SqlVariant(b) => {
+                Some(b)
+            }
+            _ => None,
+        }
+    }
+
+    // `datetime`, `smalldatetime` and `datetime2` values, `date` values at midnight
+    pub fn as_datetime(&self) -> Option<chrono::NaiveDateTime> {
+        match self {
+            Self::DateTime(v) | Self::SmallDateTime(v) | Self::DateTime2(v) => Some(*v),
+            Self::Date(v) => v.and_hms_opt(0, 0, 0),
+            _ => None,
+        }
+    }
+
+    // `date` values and the date part of `datetime`, `smalldatetime` and `datetime2` values
+    pub fn as_date(&self) -> Option<chrono::NaiveDate> {
+        match self {
+            Self::Date(v) => Some(*v),
+            _ => self.as_datetime().map(|v| v.date()),
+        }
+    }
+
+    pub fn as_time(&self) -> Option<chrono::NaiveTime> {
+        match self {
+            Self::Time(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_datetime_offset(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        match self {
+            Self::DateTimeOffset(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    // The raw value of an `uniqueidentifier`, see `as_uuid` for the properly ordered version
+    pub fn as_unique_identifier(&self) -> Option<u128> {
+        match self {
+            Self::UniqueIdentifier(raw) => Some(*raw),
+            _ => None,
+        }
+    }
+
+    pub fn as_uuid(&self) -> Option<uuid::Uuid> {
+        self.as_unique_identifier().map(guid_to_uuid)
+    }
+
+    pub fn unwrap_unique_identifier(self) -> u128 {
+        self.as_unique_identifier()
+            .unwrap_or_else(|| panic!("{:?} is not a unique identifier", self))
+    }
+
+    // Only for `int` values, use `as_i32` to also accept the smaller integer types
+    pub fn unwrap_int(self) -> i32 {
+        match self {
+            Self::Int(i) => i,
+            _ => panic!("{:?} is not a int", self),
+        }
+    }
+
+    // The pointer to the LOB data, if this value is stored out of row
+    pub fn lob_pointer(&self) -> Option<LobPointer> {
+        self.as_lob_pointer().cloned()
+    }
+
+    pub fn as_lob_pointer(&self) -> Option<&LobPointer> {
+        match self {
+            Self::VarBinary(ValueOrLob::Lob(ptr))
+            | Self::VarChar(ValueOrLob::Lob(ptr))
+            | Self::NVarChar(ValueOrLob::Lob(ptr))
+            | Self::Image(Some(ptr))
+            | Self::NText(Some(ptr)) => Some(ptr),
+            _ => None,
+        }
+    }
+
+    // The data of values stored out of row, `None` for all other values
+    pub fn read_lob<'p, T: PageProvider>(&self, page_provider: &'p T) -> Option<LobDataBlocks<'p>> {
+        self.as_lob_pointer()?.read(page_provider)
+    }
+
+    // The complete binary data of this value, reading it from the LOB / row-overflow pages
+    // if it is not stored in the row
+    pub fn read_bytes<T: PageProvider>(&self, page_provider: &T) -> Option<Vec<u8>> {
         match self {
-            Self::UniqueIdentifier(uuid) => uuid,
-            _ => panic!("{:?} is not a unique identifier", self),
+            Self::Binary(bytes)
+            | Self::VarBinary(ValueOrLob::Value(bytes))
+            | Self::SqlVariant(bytes) => Some(bytes.to_vec()),
+            _ => Some(self.read_lob(page_provider)?.to_vec()),
         }
     }
 
-    pub fn unwrap_int(self) -> i32 {
+    // The complete string of a `nchar`, `nvarchar`, `ntext`, `sysname`, `char` or in row
+    // `varchar` value, reading it from the LOB / row-overflow pages if it is not stored in the row.
+    // Out of row `varchar` values have to be decoded with the code page of their column
+    pub fn read_string<T: PageProvider>(&self, page_provider: &T) -> Option<String> {
         match self {
-            Self::Int(i) => i,
-            _ => panic!("{:?} is not a int", self),
+            Self::NVarChar(ValueOrLob::Value(s))
+            | Self::VarChar(ValueOrLob::Value(s))
+            | Self::NChar(s)
+            | Self::SysName(s)
+            | Self::Char(s) => Some(s.clone()),
+            Self::NVarChar(ValueOrLob::Lob(ptr)) | Self::NText(Some(ptr)) => {
+                Some(parse_utf16_string(&ptr.read(page_provider)?.to_vec()))
+            }
+            _ => None,
         }
     }
 
     pub fn unwrap_nvar_char_in_row(self) -> String {
-        match self {
-            Self::NVarChar(ValueOrLob::Value(s)) => s,
-            _ => panic!("{:?} is not a in row stored nvarchar", self),
+        match self.as_str() {
+            Some(s) => s.to_string(),
+            None => panic!("{:?} is not a in row stored string", self),
         }
     }
 }
 
+// Values are equal if `compare` considers them equal, so `Int(1) == BigInt(1)`.
+// Values that can't be compared (like out of row values) are never equal, not even to themselves
+impl<'a, 'b> PartialEq<SqlValue<'b>> for SqlValue<'a> {
+    fn eq(&self, other: &SqlValue<'b>) -> bool {
+        self.compare(other) == Some(Ordering::Equal)
+    }
+}
+
+impl<'a, 'b> PartialOrd<SqlValue<'b>> for SqlValue<'a> {
+    fn partial_cmp(&self, other: &SqlValue<'b>) -> Option<Ordering> {
+        self.compare(other)
+    }
+}
+
+// uniqueidentifiers are stored with the first three groups in little endian
+// and the last two in big endian byte order
+pub fn guid_to_uuid(raw: u128) -> uuid::Uuid {
+    let mut bytes = raw.to_le_bytes();
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    uuid::Uuid::from_bytes(bytes)
+}
+
+fn format_decimal(value: i128, scale: u8) -> String {
+    let scale = scale as u32;
+    let digits = value.unsigned_abs();
+    let sign = if value < 0 { "-" } else { "" };
+    if scale == 0 {
+        format!("{}{}", sign, digits)
+    } else {
+        // no mantissa reaches 10^39, so there are only fractional digits for larger scales
+        let (integer, fraction) = match 10u128.checked_pow(scale) {
+            Some(divisor) => (digits / divisor, digits % divisor),
+            None => (0, digits),
+        };
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            integer,
+            fraction,
+            width = scale as usize
+        )
+    }
+}
+
 pub fn value_for_display(this: &Option<SqlValue>) -> String {
     match this {
-        Some(v) => match v {
-            SqlValue::TinyInt(i) => format!("{}", i),
-            SqlValue::SmallInt(i) => format!("{}", i),
-            SqlValue::Int(i) => format!("{}", i),
-            SqlValue::BigInt(i) => format!("{}", i),
-            SqlValue::Bit(b) => format!("{}", b),
-            SqlValue::Binary(bytes) | SqlValue::VarChar(bytes) => {
-                format!("{:x?}", bytes)
-            }
-            SqlValue::VarBinary(b) => match b {
-                ValueOrLob::Value(s) => format!("{:x?}", s),
-                ValueOrLob::Lob(l) => format!("{:?}", l),
-            },
-            SqlValue::Char(s) => s.to_string(),
-            SqlValue::NChar(s) => s.to_string(),
-            SqlValue::SysName(s) => s.to_string(),
-            SqlValue::NVarChar(s) => match s {
-                ValueOrLob::Value(s) => s.to_string(),
-                ValueOrLob::Lob(l) => format!("{:?}", l),
-            },
-            SqlValue::DateTime(d) | SqlValue::SmallDateTime(d) => format!("{}", d),
-            SqlValue::SqlVariant(bytes) => format!("{:?}", bytes),
-            SqlValue::UniqueIdentifier(uuid) => format!("{}", uuid),
-            SqlValue::Image(bytes) => format!("{:?}", bytes),
-            SqlValue::NText(bytes) => format!("{:?}", bytes),
-            SqlValue::Float(f) => format!("{}", f),
-        },
+        Some(v) => format_value(v),
         None => "NULL".to_string(),
     }
 }
 
-#[derive(Debug)]
+fn format_value(v: &SqlValue) -> String {
+    match v {
+        SqlValue::TinyInt(i) => format!("{}", i),
+        SqlValue::SmallInt(i) => format!("{}", i),
+        SqlValue::Int(i) => format!("{}", i),
+        SqlValue::BigInt(i) => format!("{}", i),
+        SqlValue::Bit(b) => format!("{}", b),
+        SqlValue::Binary(bytes) => format!("{:x?}", bytes),
+        SqlValue::VarBinary(b) => match b {
+            ValueOrLob::Value(s) => format!("{:x?}", s),
+            ValueOrLob::Lob(l) => format!("{:?}", l),
+        },
+        SqlValue::Char(s) => s.to_string(),
+        SqlValue::NChar(s) => s.to_string(),
+        SqlValue::SysName(s) => s.to_string(),
+        SqlValue::NVarChar(s) | SqlValue::VarChar(s) => match s {
+            ValueOrLob::Value(s) => s.to_string(),
+            ValueOrLob::Lob(l) => format!("{:?}", l),
+        },
+        SqlValue::DateTime(d) | SqlValue::SmallDateTime(d) => format!("{}", d),
+        SqlValue::SqlVariant(bytes) => format!("{:?}", bytes),
+        SqlValue::UniqueIdentifier(uuid) => format!("{}", guid_to_uuid(*uuid)),
+        SqlValue::Image(bytes) => format!("{:?}", bytes),
+        SqlValue::NText(bytes) => format!("{:?}", bytes),
+        SqlValue::Float(f) => format!("{}", f),
+        SqlValue::Decimal(v, scale) => format_decimal(*v, *scale),
+        SqlValue::Money(m) => format!("{}", m),
+        SqlValue::Real(f) => format!("{}", f),
+        SqlValue::Date(d) => format!("{}", d),
+        SqlValue::Time(t) => format!("{}", t),
+        SqlValue::DateTime2(d) => format!("{}", d),
+        SqlValue::DateTimeOffset(d) => format!("{}", d),
+    }
+}
+
+const ISO_DATETIME: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+// Like `value_for_display`, but meant for exporting the data:
+// binary data is written as hex and dates and times in ISO 8601 format
+pub fn value_for_export(value: &SqlValue) -> String {
+    match value {
+        SqlValue::Binary(bytes)
+        | SqlValue::SqlVariant(bytes)
+        | SqlValue::VarBinary(ValueOrLob::Value(bytes)) => to_hex(bytes),
+        SqlValue::DateTime(d) | SqlValue::SmallDateTime(d) | SqlValue::DateTime2(d) => {
+            d.format(ISO_DATETIME).to_string()
+        }
+        SqlValue::DateTimeOffset(d) => d.to_rfc3339(),
+        v => format_value(v),
+    }
+}
+
+// Numbers are serialized as numbers, except for `decimal` and `money` which are serialized
+// as strings to not lose precision. Dates and times are serialized in ISO 8601 format,
+// binary data as hex strings and LOB pointers as `{"lob": pointer}`.
+impl<'a> Serialize for SqlValue<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        fn lob<S: Serializer>(serializer: S, ptr: &LobPointer) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(Some(1))?;
+            map.serialize_entry("lob", ptr)?;
+            map.end()
+        }
+
+        match self {
+            SqlValue::TinyInt(i) => serializer.serialize_i8(*i),
+            SqlValue::SmallInt(i) => serializer.serialize_i16(*i),
+            SqlValue::Int(i) => serializer.serialize_i32(*i),
+            SqlValue::BigInt(i) => serializer.serialize_i64(*i),
+            SqlValue::Bit(b) => serializer.serialize_bool(*b),
+            SqlValue::Binary(bytes)
+            | SqlValue::SqlVariant(bytes)
+            | SqlValue::VarBinary(ValueOrLob::Value(bytes)) => {
+                serializer.serialize_str(&to_hex(bytes))
+            }
+            SqlValue::VarBinary(ValueOrLob::Lob(ptr))
+            | SqlValue::VarChar(ValueOrLob::Lob(ptr))
+            | SqlValue::NVarChar(ValueOrLob::Lob(ptr))
+            | SqlValue::Image(Some(ptr))
+            | SqlValue::NText(Some(ptr)) => lob(serializer, ptr),
+            SqlValue::Image(None) | SqlValue::NText(None) => serializer.serialize_none(),
+            SqlValue::Char(s)
+            | SqlValue::VarChar(ValueOrLob::Value(s))
+            | SqlValue::NChar(s)
+            | SqlValue::SysName(s)
+            | SqlValue::NVarChar(ValueOrLob::Value(s)) => serializer.serialize_str(s),
+            SqlValue::UniqueIdentifier(uuid) => {
+                serializer.serialize_str(&guid_to_uuid(*uuid).to_string())
+            }
+            SqlValue::DateTime(d) | SqlValue::SmallDateTime(d) | SqlValue::DateTime2(d) => {
+                serializer.serialize_str(&d.format(ISO_DATETIME).to_string())
+            }
+            SqlValue::Date(d) => serializer.serialize_str(&d.to_string()),
+            SqlValue::Time(t) => serializer.serialize_str(&t.to_string()),
+            SqlValue::DateTimeOffset(d) => serializer.serialize_str(&d.to_rfc3339()),
+            SqlValue::Float(f) => serializer.serialize_f64(*f),
+            SqlValue::Real(f) => serializer.serialize_f32(*f),
+            SqlValue::Decimal(v, scale) => serializer.serialize_str(&format_decimal(*v, *scale)),
+            SqlValue::Money(m) => serializer.serialize_str(&m.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ColumnType {
     pub idx: i32,
     pub data_type: SqlType,
     pub name: String,
     pub nullable: bool,
     pub computed: bool,
+    // only meaningful for computed columns, persisted ones are stored like normal columns
+    pub persisted: bool,
+    // the name of the user defined alias type of the column, `data_type` is its base type
+    pub type_alias: Option<String>,
 }
 
-#[derive(Debug)]
+impl ColumnType {
+    pub fn is_var_length(&self) -> bool {
+        self.data_type.is_var_length()
+    }
+
+    pub fn is_fixed_length(&self) -> bool {
+        !self.is_var_length()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Schema {
     // Each column has a name and a type
     // the ordering of the columns is also significant, so we don't use a hashmap ore something like that
     pub columns: Vec<ColumnType>,
+    // shared with the rows parsed using this schema, for lookup of columns by name
+    column_names: Arc<[String]>,
+    // where each column is stored in the records, empty if unknown,
+    // then the columns are assumed to be stored in the order of `columns`
+    layout: Vec<Option<PhysicalColumn>>,
+}
+
+// Where a column is stored in the records, from `sysrscols`
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicalColumn {
+    // offset into the record for fixed length columns,
+    // the negated 1 based index into the variable length columns otherwise
+    pub leaf_offset: i16,
+    // 1 based index into the null bitmap
+    pub null_bit: u16,
+    // the bit of the byte at `leaf_offset` for `bit` columns
+    pub bit_pos: u8,
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("[{}]", name.replace(']', "]]"))
 }
 
 pub struct BitParser {
@@ -358,51 +1486,261 @@ impl BitParser {
         }
     }
 
-    fn read_bit(&mut self, cursor: &mut Cursor<&[u8]>) -> bool {
+    fn read_bit(&mut self, cursor: &mut impl Read) -> Result<bool, MdfError> {
         if self.read_bits == 8 {
-            self.current_byte = cursor.read_u8().unwrap();
+            self.current_byte = cursor.read_u8()?;
             self.read_bits = 0;
         }
 
         let ret = (self.current_byte & 1) == 1;
         self.current_byte >>= 1;
         self.read_bits += 1;
-        ret
+        Ok(ret)
     }
 }
 
 impl Schema {
+    pub fn new(columns: Vec<ColumnType>) -> Self {
+        let column_names = columns.iter().map(|col| col.name.clone()).collect();
+        Self {
+            columns,
+            column_names,
+            layout: vec![],
+        }
+    }
+
+    // The `p_min_len` of the data pages of a table with this schema: the 4 byte record header
+    // plus the fixed length columns. Only an estimate, as columns that were dropped still
+    // take up space in the records (with a layout, only the ones after the last column are missed)
+    pub fn expected_p_min_len(&self) -> Option<u16> {
+        let stored = |col: &&ColumnType| col.is_fixed_length() && (!col.computed || col.persisted);
+        let len = if self.layout.is_empty() {
+            let bits = self
+                .columns
+                .iter()
+                .filter(stored)
+                .filter(|col| matches!(col.data_type, SqlType::Bit))
+                .count();
+            let fixed: Option<usize> = self
+                .columns
+                .iter()
+                .filter(stored)
+                .filter(|col| !matches!(col.data_type, SqlType::Bit))
+                .map(|col| col.data_type.fixed_size())
+                .sum();
+            4 + fixed? + bits.div_ceil(8)
+        } else {
+            // the end of the column stored last
+            self.columns
+                .iter()
+                .zip(&self.layout)
+                .filter(|(col, _)| col.is_fixed_length())
+                .filter_map(|(col, layout)| Some((col, (*layout)?)))
+                .map(|(col, layout)| {
+                    let size = col.data_type.fixed_size().unwrap_or(1);
+                    Some(usize::try_from(layout.leaf_offset).ok()? + size)
+                })
+                .try_fold(4, |end: usize, col_end| Some(end.max(col_end?)))?
+        };
+        u16::try_from(len).ok()
+    }
+
+    // Checks that this (on disk) schema has the columns of `expected` in the same order and with
+    // types that are stored the same way. Names are compared ignoring case, like SQL Server does
+    pub fn validate_against(&self, expected: &Schema) -> Result<(), SchemaMismatch> {
+        if self.columns.len() != expected.columns.len() {
+            return Err(SchemaMismatch::ColumnCount {
+                expected: expected.columns.len(),
+                actual: self.columns.len(),
+            });
+        }
+
+        for (idx, (actual, expected)) in self.columns.iter().zip(&expected.columns).enumerate() {
+            if !actual.name.eq_ignore_ascii_case(&expected.name) {
+                return Err(SchemaMismatch::Name {
+                    idx,
+                    expected: expected.name.clone(),
+                    actual: actual.name.clone(),
+                });
+            }
+            if !actual.data_type.has_same_storage(&expected.data_type) {
+                return Err(SchemaMismatch::Type {
+                    column: actual.name.clone(),
+                    expected: expected.data_type.to_tsql_name(),
+                    actual: actual.data_type.to_tsql_name(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Uses the physical layout of the columns from `sysrscols` to parse the records, instead of
+    // assuming the columns are stored in order, which breaks once columns were dropped or added.
+    // `rs_cols` are matched to the columns by their `row_set_col_id`, columns without an entry
+    // (like non persisted computed ones) are always NULL
+    pub fn with_layout<'a>(mut self, rs_cols: impl IntoIterator<Item = &'a SysRsCol>) -> Self {
+        let rs_cols: Vec<_> = rs_cols.into_iter().collect();
+        self.layout = self
+            .columns
+            .iter()
+            .map(|col| {
+                let rs_col = rs_cols
+                    .iter()
+                    .find(|rs_col| rs_col.row_set_col_id == col.idx)?;
+                Some(PhysicalColumn {
+                    leaf_offset: rs_col.leaf_offset(),
+                    null_bit: rs_col.null_bit as u16,
+                    bit_pos: rs_col.bit_pos as u8,
+                })
+            })
+            .collect();
+        self
+    }
+
+    // Fails for columns using storage features that can't be parsed (sparse, `FILESTREAM`
+    // and `xml` columns bound to a schema collection)
     pub fn from_col_par<'a>(
         column_info: impl Iterator<Item = (&'a SysColPar, &'a SysScalarType)>,
-    ) -> Self {
+    ) -> Result<Self, MdfError> {
         let mut columns = column_info
             .map(|(col, ty)| {
-                assert!(!col.status.contains(ColParStatus::SPARSE));
-                assert!(!col.status.contains(ColParStatus::FILESTREAM));
-                assert!(!col.status.contains(ColParStatus::XML_DOCUMENT));
+                let name = col.name.clone().ok_or_else(|| {
+                    MdfError::Corrupt(format!("column {} without a name", col.col_id))
+                })?;
+                for (flag, what) in [
+                    (ColParStatus::SPARSE, "sparse"),
+                    (ColParStatus::FILESTREAM, "FILESTREAM"),
+                    (ColParStatus::XML_DOCUMENT, "xml document"),
+                ] {
+                    if col.status.contains(flag) {
+                        return Err(MdfError::Unsupported(format!("{} column {}", what, name)));
+                    }
+                }
 
-                ColumnType {
+                Ok(ColumnType {
                     idx: col.col_id,
-                    data_type: SqlType::from_col(col, ty),
-                    name: col.name.clone().unwrap(),
+                    data_type: SqlType::from_col(col, ty)?,
+                    name,
                     nullable: !col.status.contains(ColParStatus::NULLABLE),
                     computed: col.status.contains(ColParStatus::COMPUTED),
-                }
+                    persisted: col.status.contains(ColParStatus::PERSISTED),
+                    type_alias: None,
+                })
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, _>>()?;
+
+        columns.sort_by_key(|col| col.idx);
 
-        columns.sort_by(|a, b| a.idx.partial_cmp(&b.idx).unwrap());
+        Ok(Self::new(columns))
+    }
 
-        Self { columns }
+    // A `CREATE TABLE` statement for a table with this schema. The expressions of computed
+    // columns are not part of the schema, so they are only marked with a comment
+    pub fn to_create_table_sql(&self, table_name: &str) -> String {
+        self.create_table_sql(table_name, &[])
     }
 
-    // TODO(robin): we probably want to return something more like Option<Row>, because
-    //              of forwarded / forwarding records and the like
-    pub fn parse<'a>(&self, record: Record<'a>) -> Row<'a> {
-        let mut values: Vec<_> = std::iter::repeat_with(|| None)
-            .take(self.columns.len())
+    // `primary_key` are indices into `columns`
+    pub(crate) fn create_table_sql(&self, table_name: &str, primary_key: &[usize]) -> String {
+        let mut columns: Vec<_> = self
+            .columns
+            .iter()
+            .map(|col| {
+                let data_type = match &col.type_alias {
+                    Some(alias) => format!(
+                        "{} /* {} */",
+                        quote_identifier(alias),
+                        col.data_type.to_tsql_name()
+                    ),
+                    None => col.data_type.to_tsql_name(),
+                };
+                let mut definition = format!(
+                    "    {} {} {}",
+                    quote_identifier(&col.name),
+                    data_type,
+                    if col.nullable { "NULL" } else { "NOT NULL" }
+                );
+                if col.computed {
+                    definition += if col.persisted {
+                        " /* computed, persisted */"
+                    } else {
+                        " /* computed */"
+                    };
+                }
+                definition
+            })
             .collect();
-        let mut fixed_data_cursor = Cursor::new(record.fixed_data);
+        if !primary_key.is_empty() {
+            let key: Vec<_> = primary_key
+                .iter()
+                .map(|&idx| quote_identifier(&self.columns[idx].name))
+                .collect();
+            columns.push(format!("    PRIMARY KEY ({})", key.join(", ")));
+        }
+
+        format!(
+            "CREATE TABLE {} (\n{}\n);",
+            quote_identifier(table_name),
+            columns.join(",\n")
+        )
+    }
+
+    // `None` for records that don't store a row, see `Record::stores_row`
+    pub fn parse<'a>(&self, record: Record<'a>) -> Result<Option<Row<'a>>, MdfError> {
+        if !record.stores_row() {
+            return Ok(None);
+        }
+
+        let mut values = Vec::with_capacity(self.columns.len());
+        let mut parse_warnings = vec![];
+        self.parse_values(record, &mut values, &mut parse_warnings)?;
+        Ok(Some(Row {
+            values,
+            column_names: self.column_names.clone(),
+            parse_warnings,
+        }))
+    }
+
+    // Like `parse`, but reuses the allocation of `values`, which is cleared first.
+    // Useful when the parsed values are consumed before the next record is parsed.
+    pub fn parse_into<'a>(
+        &self,
+        record: Record<'a>,
+        values: &mut Vec<Option<SqlValue<'a>>>,
+    ) -> Result<(), MdfError> {
+        self.parse_values(record, values, &mut vec![])
+    }
+
+    // Problems that did not stop the record from being parsed, but might have caused wrong
+    // values, are added to `warnings`
+    fn parse_values<'a>(
+        &self,
+        record: Record<'a>,
+        values: &mut Vec<Option<SqlValue<'a>>>,
+        warnings: &mut Vec<String>,
+    ) -> Result<(), MdfError> {
+        values.clear();
+        values.extend(std::iter::repeat_with(|| None).take(self.columns.len()));
+
+        // records without a null bitmap don't store their column count
+        let stored_columns = self
+            .columns
+            .iter()
+            .filter(|col| !col.computed || col.persisted)
+            .count();
+        if record.column_count != u16::MAX && record.column_count as usize > stored_columns {
+            warnings.push(format!(
+                "record has {} columns, but the schema only {}",
+                record.column_count, stored_columns
+            ));
+        }
+
+        if !self.layout.is_empty() {
+            return self.parse_with_layout(record, values, warnings);
+        }
+
+        let mut fixed_data_cursor = Cursor::new(record.fixed_data.clone());
         let mut bit_parser = BitParser::new();
         let mut var_column_idx = 0;
         let mut null_bit_idx = 0;
@@ -415,6 +1753,7 @@ impl Schema {
                 data_type,
                 nullable,
                 computed,
+                persisted,
                 name,
                 ..
             },
@@ -427,9 +1766,10 @@ impl Schema {
                 nullable,
                 name
             );
+            let mut lossy = false;
 
-            if *computed {
-                trace!("column is computed, doing nothing for now");
+            if *computed && !*persisted {
+                trace!("column is computed and not persisted, so there is nothing stored");
                 continue;
             }
 
@@ -444,29 +1784,182 @@ impl Schema {
                     match record.var_length_columns {
                         Some(ref columns) => {
                             trace!("the record has var length columns, so we parse it, current idx: {}, total: {}", var_column_idx, columns.count);
-                            let (complex, data) = columns.get(var_column_idx);
-                            values[i] = Some(data_type.parse_var_length(complex, data));
+                            match columns.get(var_column_idx) {
+                                Some((complex, data)) => {
+                                    values[i] = Some(data_type.parse_var_length_checked(complex, data, &mut lossy)?);
+                                }
+                                None => warnings.push(format!(
+                                    "invalid offset of variable length column {}, treating {} as NULL",
+                                    var_column_idx, name
+                                )),
+                            }
                             var_column_idx += 1;
                         }
                         None => {
                             trace!("the record does not have var length columns, so we parse a zero byte value");
                             // We are guessing with false here, lets hope it won't break
-                            values[i] = Some(data_type.parse_var_length(false, &[]));
+                            warnings.push(format!(
+                                "record has no variable length columns, parsed {} as empty",
+                                name
+                            ));
+                            values[i] =
+                                Some(data_type.parse_var_length(false, PageBytes::empty())?);
                         }
                     }
                 } else {
                     trace!("the column is fixed length, we parse");
-                    values[i] = Some(data_type.parse(&mut bit_parser, &mut fixed_data_cursor));
+                    values[i] = Some(data_type.parse_checked(
+                        &mut bit_parser,
+                        &mut fixed_data_cursor,
+                        &mut lossy,
+                    )?);
                 }
             } else {
                 trace!("the column is null");
             }
+            if lossy {
+                warnings.push(undecodable_warning(name));
+            }
 
             null_bit_idx += 1;
             trace!("we got the value {:?}", values[i]);
         }
 
-        Row { values }
+        Ok(())
+    }
+
+    fn parse_with_layout<'a>(
+        &self,
+        record: Record<'a>,
+        values: &mut [Option<SqlValue<'a>>],
+        warnings: &mut Vec<String>,
+    ) -> Result<(), MdfError> {
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = self.parse_column_with_layout(&record, i, warnings)?;
+        }
+        Ok(())
+    }
+
+    fn parse_column_with_layout<'a>(
+        &self,
+        record: &Record<'a>,
+        idx: usize,
+        warnings: &mut Vec<String>,
+    ) -> Result<Option<SqlValue<'a>>, MdfError> {
+        let data_type = &self.columns[idx].data_type;
+        let layout = match self.layout[idx] {
+            Some(layout) => layout,
+            None => return Ok(None),
+        };
+
+        // nullable columns can be added after the fact, the records written before
+        // don't have a null bit for them
+        let null_bit_idx = layout.null_bit.saturating_sub(1);
+        if null_bit_idx >= record.column_count || record.is_column_null(null_bit_idx) {
+            return Ok(None);
+        }
+
+        let mut lossy = false;
+        let value = if layout.leaf_offset < 0 {
+            let var_column_idx = (-layout.leaf_offset - 1) as u16;
+            // trailing empty variable length columns are not stored
+            match record.var_length_columns {
+                Some(ref columns) if var_column_idx < columns.count => {
+                    match columns.get(var_column_idx) {
+                        Some((complex, data)) => {
+                            data_type.parse_var_length_checked(complex, data, &mut lossy)?
+                        }
+                        None => {
+                            warnings.push(format!(
+                                "invalid offset of variable length column {}, treating {} as NULL",
+                                var_column_idx, self.columns[idx].name
+                            ));
+                            return Ok(None);
+                        }
+                    }
+                }
+                _ => data_type.parse_var_length(false, PageBytes::empty())?,
+            }
+        } else {
+            // the offset includes the 4 byte record header
+            let offset = (layout.leaf_offset as usize).saturating_sub(4);
+            match data_type {
+                SqlType::Bit => {
+                    let byte = *slice(&record.fixed_data, offset)?;
+                    SqlValue::Bit((byte >> layout.bit_pos) & 1 == 1)
+                }
+                _ => {
+                    let mut cursor = Cursor::new(record.fixed_data.clone());
+                    cursor.set_position(offset as u64);
+                    data_type.parse_checked(&mut BitParser::new(), &mut cursor, &mut lossy)?
+                }
+            }
+        };
+        if lossy {
+            warnings.push(undecodable_warning(&self.columns[idx].name));
+        }
+        Ok(Some(value))
+    }
+
+    // Parses only the fixed length column `idx` of `record`, `None` if it is NULL.
+    // Cheap enough to be used for filtering records before parsing the whole row,
+    // only the fixed length columns in front of it are read.
+    // Fails with `MdfError::InvalidColumn` for variable length and non persisted computed columns
+    pub fn parse_fixed_column<'a>(
+        &self,
+        record: &Record<'a>,
+        idx: usize,
+    ) -> Result<Option<SqlValue<'a>>, MdfError> {
+        let column = self
+            .columns
+            .get(idx)
+            .ok_or_else(|| MdfError::InvalidColumn(format!("no column {}", idx)))?;
+        if column.data_type.is_var_length() {
+            return Err(MdfError::InvalidColumn(format!(
+                "{} is not a fixed length column",
+                column.name
+            )));
+        }
+        if column.computed && !column.persisted {
+            return Err(MdfError::InvalidColumn(format!(
+                "{} is computed and not persisted, so it is not stored",
+                column.name
+            )));
+        }
+        if !self.layout.is_empty() {
+            return self.parse_column_with_layout(record, idx, &mut vec![]);
+        }
+
+        let mut fixed_data_cursor = Cursor::new(record.fixed_data.clone());
+        let mut bit_parser = BitParser::new();
+        let mut null_bit_idx = 0;
+
+        // skip the stored columns in front of it
+        for col in self.columns[..idx]
+            .iter()
+            .filter(|col| !col.computed || col.persisted)
+        {
+            let is_null = null_bit_idx >= record.column_count as usize
+                || record.is_column_null(null_bit_idx as u16);
+            if !is_null && !col.data_type.is_var_length() {
+                col.data_type
+                    .parse(&mut bit_parser, &mut fixed_data_cursor)?;
+            }
+
+            null_bit_idx += 1;
+        }
+
+        let is_null = null_bit_idx >= record.column_count as usize
+            || record.is_column_null(null_bit_idx as u16);
+        if is_null {
+            Ok(None)
+        } else {
+            Ok(Some(
+                column
+                    .data_type
+                    .parse(&mut bit_parser, &mut fixed_data_cursor)?,
+            ))
+        }
     }
 }
 
@@ -475,9 +1968,80 @@ pub struct Row<'a> {
     // TODO(robin): Is there a better way to do nullability handling?
     //              maybe type level nullability?
     pub values: Vec<Option<SqlValue<'a>>>,
+    column_names: Arc<[String]>,
+    // problems noticed while parsing the record, the values might be wrong if not empty
+    pub parse_warnings: Vec<String>,
+}
+
+// A `Row` that owns all its values
+#[derive(Debug, Clone)]
+pub struct OwnedRow {
+    pub values: Vec<Option<OwnedSqlValue>>,
+    column_names: Arc<[String]>,
+    pub parse_warnings: Vec<String>,
+}
+
+// Serialized as a map from the column names to the values
+impl<'a> Serialize for Row<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (name, value) in self.column_names.iter().zip(&self.values) {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl OwnedRow {
+    pub fn get_idx(&self, name: &str) -> Option<usize> {
+        self.column_names.iter().position(|n| n == name)
+    }
+
+    // `None` if there is no such column or the value is NULL
+    pub fn get(&self, name: &str) -> Option<&OwnedSqlValue> {
+        self.values.get(self.get_idx(name)?)?.as_ref()
+    }
 }
 
 impl<'a> Row<'a> {
+    pub fn to_owned(&self) -> OwnedRow {
+        OwnedRow {
+            values: self
+                .values
+                .iter()
+                .map(|v| v.as_ref().map(SqlValue::to_owned))
+                .collect(),
+            column_names: self.column_names.clone(),
+            parse_warnings: self.parse_warnings.clone(),
+        }
+    }
+
+    pub fn column_names(&self) -> &[String] {
+        &self.column_names
+    }
+
+    pub fn get_idx(&self, name: &str) -> Option<usize> {
+        self.column_names.iter().position(|n| n == name)
+    }
+
+    // `None` if there is no such column or the value is NULL
+    pub fn get(&self, name: &str) -> Option<&SqlValue<'a>> {
+        self.values.get(self.get_idx(name)?)?.as_ref()
+    }
+
+    // Moves the value at `idx` out of the row and converts it,
+    // NULL values are only allowed if `V` is an `Option`
+    pub fn take_value<V: FromSqlValue<'a>>(&mut self, idx: usize) -> V {
+        V::from_nullable_sql_value(self.values[idx].take())
+            .unwrap_or_else(|v| panic!("can't convert {:?} of column {}", v, idx))
+    }
+
+    // Like `get`, but moves the value out of the row
+    pub fn take(&mut self, name: &str) -> Option<SqlValue<'a>> {
+        let idx = self.get_idx(name)?;
+        self.values.get_mut(idx)?.take()
+    }
+
     pub fn format_row(&self) -> String {
         let mut res = "".to_owned();
         for value in &self.values {
@@ -487,7 +2051,16 @@ impl<'a> Row<'a> {
     }
 }
 
-// TODO(robin): use real columns idx's instead of dummy ones
+// The error for a value of a system table row that does not fit its column,
+// `value` is `None` for NULL values of non nullable columns
+#[doc(hidden)]
+pub fn unexpected_value(table: &str, column: &str, value: Option<SqlValue>) -> MdfError {
+    MdfError::Corrupt(match value {
+        Some(value) => format!("unexpected value {:?} for {} of {}", value, column, table),
+        None => format!("NULL for the non nullable {} of {}", column, table),
+    })
+}
+
 #[macro_export]
 macro_rules! create_row_parser {
     (struct $name:ident $(<$l:lifetime>)? { $($field_name:ident : $struct_ty:ty $([$optional:tt])? $(= [$input_ty:expr] $input_pat:pat => $conv_expr:expr)?),* $(,)? }) => {
@@ -501,24 +2074,40 @@ macro_rules! create_row_parser {
                 #[allow(unused)]
                 use crate::SqlType::*;
 
-                crate::Schema {
-                    columns: vec![$(create_row_parser!(@column_type $field_name, $($optional,)? $struct_ty $(as $input_ty)?),)*]
+                let mut columns = vec![$(create_row_parser!(@column_type $field_name, $($optional,)? $struct_ty $(as $input_ty)?),)*];
+                // the system tables are never altered, so the column ids are sequential
+                for (i, column) in columns.iter_mut().enumerate() {
+                    column.idx = i as i32 + 1;
                 }
+                crate::Schema::new(columns)
+            }
+
+            // Checks that `schema` (usually read from the database) matches the one of this struct
+            pub fn validate(schema: &crate::Schema) -> Result<(), crate::SchemaMismatch> {
+                schema.validate_against(&$name::schema())
             }
 
-            #[allow(unused_assignments)]
             pub fn parse(record: crate::Record<$($l)?>) -> Self {
+                Self::try_parse(record).unwrap()
+            }
+
+            #[allow(unused_assignments)]
+            pub fn try_parse(record: crate::Record<$($l)?>) -> Result<Self, crate::MdfError> {
                 let schema = $name::schema();
-                let mut row = schema.parse(record);
+                let ty = record.record_type();
+                let mut row = schema.parse(record)?.ok_or_else(|| {
+                    crate::MdfError::UnsupportedRecord(format!("{:?} in {}", ty, stringify!($name)))
+                })?;
                 let mut idx = 0;
                 $(
-                    let $field_name = create_row_parser!(@unpack_column row.values[idx].take(), $($optional,)? $struct_ty $(= [$input_ty] $input_pat => $conv_expr)?);
+                    let $field_name = create_row_parser!(@unpack_column row.values[idx].take(), $($optional,)? $struct_ty $(= [$input_ty] $input_pat => $conv_expr)?)
+                        .map_err(|v| crate::types::unexpected_value(stringify!($name), stringify!($field_name), v))?;
                     idx += 1;
                 )*
 
-                Self {
+                Ok(Self {
                     $($field_name,)*
-                }
+                })
             }
         }
     };
@@ -530,54 +2119,58 @@ macro_rules! create_row_parser {
     };
     (@unpack_column $value:expr, ?, $struct_ty:ty = [$input_ty:expr] $input_pat:pat => $conv_expr:expr) => {
         {
+            #[allow(unused_imports)]
             use crate::SqlValue::*;
-            $value.map(|v| {
-                match v {
-                    $input_pat => $conv_expr,
-                    _ => unreachable!()
-                }
-            })
+            $value
+                .map(|v| {
+                    #[allow(unreachable_patterns)]
+                    match v {
+                        $input_pat => Ok($conv_expr),
+                        v => Err(Some(v)),
+                    }
+                })
+                .transpose()
         }
     };
     (@unpack_column $value:expr, ?, $struct_ty:ty) => {
-        $value.map(<$struct_ty as crate::FromSqlValue>::from_sql_value)
+        create_row_parser!(@unpack_column $value, Option<$struct_ty>)
     };
     (@unpack_column $value:expr, $struct_ty:ty = [$input_ty:expr] $input_pat:pat => $conv_expr:expr) => {
         {
+            #[allow(unused_imports)]
             use crate::SqlValue::*;
-            match $value.unwrap() {
-                $input_pat => $conv_expr,
-                _ => unreachable!()
+            #[allow(unreachable_patterns)]
+            match $value {
+                Some($input_pat) => Ok($conv_expr),
+                v => Err(v),
             }
         }
     };
     (@unpack_column $value:expr, $struct_ty:ty) => {
-        <$struct_ty as crate::FromSqlValue>::from_sql_value($value.unwrap())
+        <$struct_ty as crate::FromSqlValue>::from_nullable_sql_value($value)
     };
     (@column_type $name:ident, ?, $struct_ty:ty as $input_ty:expr) => {
         crate::ColumnType {
             idx: 0,
             computed: false,
+            persisted: false,
+            type_alias: None,
             data_type: $input_ty,
             nullable: true,
             name: stringify!($name).to_string()
         }
     };
     (@column_type $name:ident, ?, $struct_ty:ty) => {
-        crate::ColumnType {
-            idx: 0,
-            computed: false,
-            data_type: <$struct_ty as crate::ToSqlType>::to_sql_type(),
-            nullable: true,
-            name: stringify!($name).to_string()
-        }
+        create_row_parser!(@column_type $name, Option<$struct_ty>)
     };
     (@column_type $name:ident, $struct_ty:ty) => {
         crate::ColumnType {
             idx: 0,
             computed: false,
+            persisted: false,
+            type_alias: None,
             data_type: <$struct_ty as crate::ToSqlType>::to_sql_type(),
-            nullable: false,
+            nullable: <$struct_ty as crate::ToSqlType>::nullable(),
             name: stringify!($name).to_string()
         }
     };
@@ -585,9 +2178,458 @@ macro_rules! create_row_parser {
         crate:: ColumnType {
             idx: 0,
             computed: false,
+            persisted: false,
+            type_alias: None,
             data_type: $input_ty,
             nullable: false,
             name: stringify!($name).to_string()
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::fixtures::{page, record, PTR};
+
+    fn column(idx: i32, name: &str, data_type: SqlType) -> ColumnType {
+        ColumnType {
+            idx,
+            data_type,
+            name: name.to_string(),
+            nullable: true,
+            computed: false,
+            persisted: false,
+            type_alias: None,
+        }
+    }
+
+    fn rs_col(row_set_col_id: i32, collation_id: i32, offset: i32, null_bit: i32) -> Vec<u8> {
+        let mut fixed = vec![];
+        fixed.extend_from_slice(&72_057_594_043_760_640i64.to_le_bytes()); // row_set_id
+        fixed.extend_from_slice(&row_set_col_id.to_le_bytes());
+        fixed.extend_from_slice(&row_set_col_id.to_le_bytes()); // hobt_col_id
+        fixed.extend_from_slice(&0i64.to_le_bytes()); // rc_modified
+        fixed.extend_from_slice(&0i32.to_le_bytes()); // ti
+        fixed.extend_from_slice(&collation_id.to_le_bytes());
+        fixed.extend_from_slice(&0i16.to_le_bytes()); // ord_key
+        fixed.extend_from_slice(&0i16.to_le_bytes()); // max_in_row_len
+        fixed.extend_from_slice(&0i32.to_le_bytes()); // status
+        fixed.extend_from_slice(&offset.to_le_bytes());
+        fixed.extend_from_slice(&null_bit.to_le_bytes());
+        fixed.extend_from_slice(&0i16.to_le_bytes()); // bit_pos
+        fixed.extend_from_slice(&0i32.to_le_bytes()); // db_frag_id
+        record(&fixed, 14, &[&[0; 16]])
+    }
+
+    #[test]
+    fn layout_from_sysrscols() {
+        // like `AllDocs`: a dropped column left a byte in front of `id`, and the collation
+        // ids of the columns don't line up with their column ids
+        let pp = page(vec![
+            rs_col(1, 0, 5, 1),
+            rs_col(2, 0, 21, 2),
+            rs_col(3, 872_468_488, -1, 4),
+        ]);
+        let rs_cols: Vec<_> = pp
+            .get(PTR)
+            .unwrap()
+            .records()
+            .map(SysRsCol::parse)
+            .collect();
+        assert_eq!(rs_cols[2].row_set_col_id, 3);
+        assert_eq!(rs_cols[2].collation_id, 872_468_488);
+
+        let schema = Schema::new(vec![
+            column(1, "id", SqlType::UniqueIdentifier),
+            column(2, "site_id", SqlType::UniqueIdentifier),
+            column(3, "leaf_name", SqlType::NVarChar(None)),
+        ])
+        .with_layout(&rs_cols);
+
+        let id = 0x0011_2233_4455_6677_8899_aabb_ccdd_eeffu128;
+        let site_id = 0xffee_ddcc_bbaa_9988_7766_5544_3322_1100u128;
+        let mut fixed = vec![0x01];
+        fixed.extend_from_slice(&id.to_le_bytes());
+        fixed.extend_from_slice(&site_id.to_le_bytes());
+        let pp = page(vec![record(&fixed, 4, &[b"a\0b\0"])]);
+        let page = pp.get(PTR).unwrap();
+        let row = schema.parse(page.record(0).unwrap()).unwrap().unwrap();
+
+        let values: Vec<_> = row.values.iter().map(Option::as_ref).collect();
+        assert_eq!(values[0].and_then(SqlValue::as_unique_identifier), Some(id));
+        assert_eq!(
+            values[1].and_then(SqlValue::as_unique_identifier),
+            Some(site_id)
+        );
+        assert_eq!(values[2].and_then(SqlValue::as_str), Some("ab"));
+    }
+
+    create_row_parser!(
+        struct TestRow {
+            id: i32,
+            parent: i32[?],
+        }
+    );
+
+    #[test]
+    fn system_table_rows_with_null_values() {
+        // a null bitmap with the bits of the NULL columns set
+        let row = |null_bits: u8| {
+            let mut rec = vec![0x10, 0, 12, 0];
+            rec.extend_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0]);
+            rec.extend_from_slice(&[2, 0, null_bits]);
+            rec
+        };
+        let pp = page(vec![row(0), row(0b10), row(0b01)]);
+        let page = pp.get(PTR).unwrap();
+
+        TestRow::validate(&TestRow::schema()).unwrap();
+        let parsed = TestRow::parse(page.record(0).unwrap());
+        assert_eq!((parsed.id, parsed.parent), (1, Some(2)));
+        let parsed = TestRow::try_parse(page.record(1).unwrap()).unwrap();
+        assert_eq!((parsed.id, parsed.parent), (1, None));
+        assert!(matches!(
+            TestRow::try_parse(page.record(2).unwrap()),
+            Err(MdfError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn persisted_computed_column() {
+        let computed = |idx, name, persisted| ColumnType {
+            computed: true,
+            persisted,
+            ..column(idx, name, SqlType::Int)
+        };
+        // `total AS price * count PERSISTED` is stored after the other columns,
+        // `doubled AS price * 2` is not stored at all
+        let schema = Schema::new(vec![
+            column(1, "price", SqlType::Int),
+            column(2, "count", SqlType::Int),
+            computed(3, "doubled", false),
+            computed(4, "total", true),
+        ]);
+
+        let mut fixed = vec![];
+        for value in [7i32, 3, 21] {
+            fixed.extend_from_slice(&value.to_le_bytes());
+        }
+        let pp = page(vec![record(&fixed, 3, &[])]);
+        let page = pp.get(PTR).unwrap();
+        let row = schema.parse(page.record(0).unwrap()).unwrap().unwrap();
+
+        let values: Vec<_> = row
+            .values
+            .iter()
+            .map(|value| value.as_ref().and_then(SqlValue::as_i32))
+            .collect();
+        assert_eq!(values, vec![Some(7), Some(3), None, Some(21)]);
+        assert_eq!(schema.expected_p_min_len(), Some(16));
+    }
+
+    #[test]
+    fn undecodable_characters_are_warned_about() {
+        let schema = Schema::new(vec![
+            column(1, "code", SqlType::NChar(4)),
+            column(2, "name", SqlType::NVarChar(None)),
+            column(3, "note", SqlType::NVarChar(None)),
+        ]);
+
+        // an unpaired surrogate in `code` and `note`
+        let pp = page(vec![record(b"\x00\xd8a\0", 3, &[b"a\0b\0", b"\x00\xdc"])]);
+        let page = pp.get(PTR).unwrap();
+        let row = schema.parse(page.record(0).unwrap()).unwrap().unwrap();
+
+        assert_eq!(
+            row.values[1].as_ref().and_then(SqlValue::as_str),
+            Some("ab")
+        );
+        assert_eq!(
+            row.values[2].as_ref().and_then(SqlValue::as_str),
+            Some("\u{FFFD}")
+        );
+        assert_eq!(
+            row.parse_warnings,
+            vec![
+                "code contains characters that could not be decoded",
+                "note contains characters that could not be decoded",
+            ]
+        );
+    }
+
+    fn try_parse_fixed(data_type: SqlType, data: &[u8]) -> Result<SqlValue<'_>, MdfError> {
+        let mut cursor = Cursor::new(PageBytes::borrowed(data));
+        data_type.parse(&mut BitParser::new(), &mut cursor)
+    }
+
+    fn parse_fixed(data_type: SqlType, data: &[u8]) -> SqlValue<'_> {
+        try_parse_fixed(data_type, data).unwrap()
+    }
+
+    #[test]
+    fn invalid_time_scales_and_offsets() {
+        // 01:00:00 on 0001-01-02, followed by the offset in minutes
+        let value = |offset: i16| {
+            let mut data = 3_600u32.to_le_bytes()[..3].to_vec();
+            data.extend_from_slice(&[1, 0, 0]);
+            data.extend_from_slice(&offset.to_le_bytes());
+            data
+        };
+
+        let parsed = try_parse_fixed(SqlType::DateTimeOffset(0), &value(-120))
+            .unwrap()
+            .as_datetime_offset()
+            .unwrap();
+        assert_eq!(parsed.offset().local_minus_utc(), -7200);
+        assert_eq!(parsed.naive_utc().to_string(), "0001-01-02 01:00:00");
+
+        assert!(matches!(
+            try_parse_fixed(SqlType::DateTimeOffset(0), &value(25 * 60)),
+            Err(MdfError::Corrupt(_))
+        ));
+        assert!(matches!(
+            try_parse_fixed(SqlType::Time(10), &[0; 5]),
+            Err(MdfError::Corrupt(_))
+        ));
+        assert!(matches!(
+            try_parse_fixed(SqlType::DateTime2(200), &[0; 8]),
+            Err(MdfError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_decimals() {
+        let decimal = |precision, scale, positive: bool, mantissa: i128| {
+            let mut data = vec![positive as u8];
+            data.extend_from_slice(&mantissa.to_le_bytes());
+            try_parse_fixed(SqlType::Decimal { precision, scale }, &data).map(|v| format_value(&v))
+        };
+
+        assert_eq!(decimal(38, 2, false, 12345).unwrap(), "-123.45");
+        assert!(matches!(
+            decimal(38, 0, false, i128::MIN),
+            Err(MdfError::Corrupt(_))
+        ));
+        assert!(matches!(decimal(39, 2, true, 1), Err(MdfError::Corrupt(_))));
+        assert!(matches!(
+            decimal(38, 39, true, 1),
+            Err(MdfError::Corrupt(_))
+        ));
+
+        // decimal(10, 50) stored in a sql_variant
+        let mut variant = vec![106, 1, 10, 50, 1];
+        variant.extend_from_slice(&1i32.to_le_bytes());
+        assert!(matches!(
+            parse_sql_variant(PageBytes::borrowed(&variant), &mut false),
+            Err(MdfError::Corrupt(_))
+        ));
+
+        assert_eq!(format_decimal(-5, 40), format!("-0.{}5", "0".repeat(39)));
+    }
+
+    #[test]
+    fn datetime_byte_patterns() {
+        let datetime = |time: [u8; 4], date: [u8; 4]| {
+            parse_fixed(SqlType::DateTime, &[time, date].concat()).as_datetime()
+        };
+        let expected = |y, m, d, h, min, s, ms| {
+            chrono::NaiveDate::from_ymd_opt(y, m, d)
+                .and_then(|date| date.and_hms_milli_opt(h, min, s, ms))
+        };
+
+        // the epoch, 1900-01-01
+        assert_eq!(datetime([0; 4], [0; 4]), expected(1900, 1, 1, 0, 0, 0, 0));
+        // 1753-01-01, the first day before the epoch that can be stored (-53690 days)
+        assert_eq!(
+            datetime([0; 4], [0x46, 0x2e, 0xff, 0xff]),
+            expected(1753, 1, 1, 0, 0, 0, 0)
+        );
+        // 1899-12-31 12:00:00, 12960000 ticks
+        assert_eq!(
+            datetime([0x00, 0xc1, 0xc5, 0x00], [0xff; 4]),
+            expected(1899, 12, 31, 12, 0, 0, 0)
+        );
+        // 9999-12-31 23:59:59.997, 2958463 days and 25919999 ticks
+        assert_eq!(
+            datetime([0xff, 0x81, 0x8b, 0x01], [0x7f, 0x24, 0x2d, 0x00]),
+            expected(9999, 12, 31, 23, 59, 59, 997)
+        );
+        // ticks are rounded to .000, .003 and .007
+        assert_eq!(
+            datetime([1, 0, 0, 0], [0; 4]),
+            expected(1900, 1, 1, 0, 0, 0, 3)
+        );
+        assert_eq!(
+            datetime([2, 0, 0, 0], [0; 4]),
+            expected(1900, 1, 1, 0, 0, 0, 7)
+        );
+    }
+
+    fn col_par(col_id: i32, name: &str, status: ColParStatus) -> SysColPar {
+        SysColPar {
+            id: 1,
+            number: 0,
+            col_id,
+            name: Some(name.to_string()),
+            xtype: 56,
+            utype: 56,
+            length: 4,
+            prec: 10,
+            scale: 0,
+            collation_id: 0,
+            status,
+            max_in_row: 0,
+            xml_ns: 0,
+            dflt: 0,
+            chk: 0,
+            idt_val: None,
+        }
+    }
+
+    fn scalar_type(name: &str) -> SysScalarType {
+        SysScalarType {
+            id: 56,
+            sch_id: 4,
+            name: name.to_string(),
+            xtype: 56,
+            length: 4,
+            prec: 10,
+            scale: 0,
+            collation_id: 0,
+            status: 0,
+            created: chrono::NaiveDateTime::default(),
+            modified: chrono::NaiveDateTime::default(),
+            dflt: 0,
+            chk: 0,
+        }
+    }
+
+    #[test]
+    fn unsupported_columns_are_errors() {
+        let int = scalar_type("int");
+        let b = col_par(2, "b", ColParStatus::empty());
+        let a = col_par(1, "a", ColParStatus::empty());
+        let schema = Schema::from_col_par(vec![(&b, &int), (&a, &int)].into_iter()).unwrap();
+        let names: Vec<_> = schema.columns.iter().map(|col| col.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        let sparse = col_par(3, "c", ColParStatus::SPARSE);
+        assert!(matches!(
+            Schema::from_col_par(vec![(&a, &int), (&sparse, &int)].into_iter()),
+            Err(MdfError::Unsupported(_))
+        ));
+        assert!(matches!(
+            SqlType::from_col(&a, &scalar_type("geography")),
+            Err(MdfError::UnknownType(_))
+        ));
+    }
+
+    #[test]
+    fn code_page_from_collation_id() {
+        let encoding = |collation_id| CodePage::from_collation_id(collation_id).0;
+
+        // SQL_Latin1_General_CP1_CI_AS
+        assert_eq!(encoding(872_468_488), encoding_rs::WINDOWS_1252);
+        // SQL_Latin1_General_CP1251_CI_AS
+        assert_eq!(encoding(106 << 24), encoding_rs::WINDOWS_1251);
+
+        // Windows collations, with and without comparison flags above the LCID
+        assert_eq!(encoding(0x0411), encoding_rs::SHIFT_JIS);
+        assert_eq!(encoding(0x00d0_0804), encoding_rs::GBK);
+        assert_eq!(encoding(0x0412), encoding_rs::EUC_KR);
+        assert_eq!(encoding(0x0404), encoding_rs::BIG5);
+        assert_eq!(encoding(0x0c04), encoding_rs::BIG5);
+        assert_eq!(encoding(0x0419), encoding_rs::WINDOWS_1251);
+        assert_eq!(encoding(0x0c1a), encoding_rs::WINDOWS_1251);
+        assert_eq!(encoding(0x081a), encoding_rs::WINDOWS_1250);
+        assert_eq!(encoding(0x0408), encoding_rs::WINDOWS_1253);
+        assert_eq!(encoding(0x040d), encoding_rs::WINDOWS_1255);
+        assert_eq!(encoding(0x0409), encoding_rs::WINDOWS_1252);
+
+        // unknown LCIDs fall back to windows-1252
+        assert_eq!(encoding(0x0455), encoding_rs::WINDOWS_1252);
+        assert_eq!(encoding(0xd008), encoding_rs::WINDOWS_1252);
+    }
+
+    #[test]
+    fn tsql_name_round_trip() {
+        let types = vec![
+            SqlType::TinyInt,
+            SqlType::Bit,
+            SqlType::Binary(16),
+            SqlType::Char(10, CodePage::default()),
+            SqlType::NChar(20),
+            SqlType::VarBinary(Some(100)),
+            SqlType::VarBinary(None),
+            SqlType::VarChar(Some(50), CodePage::default()),
+            SqlType::VarChar(None, CodePage::default()),
+            SqlType::NVarChar(Some(8000)),
+            SqlType::NVarChar(None),
+            SqlType::SysName,
+            SqlType::Float,
+            SqlType::Real,
+            SqlType::Decimal {
+                precision: 18,
+                scale: 2,
+            },
+            SqlType::Time(7),
+            SqlType::DateTime2(3),
+            SqlType::DateTimeOffset(0),
+            SqlType::Money,
+        ];
+        for ty in types {
+            let name = ty.to_tsql_name();
+            let parsed = SqlType::from_tsql_name(&name, 0, 0, 0).unwrap();
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", ty), "{}", name);
+            assert_eq!(parsed.to_tsql_name(), name);
+        }
+
+        // the length of `nvarchar(n)` is 2 * n
+        assert_eq!(SqlType::NVarChar(Some(20)).to_tsql_name(), "nvarchar(10)");
+        assert_eq!(
+            SqlType::from_tsql_name("nvarchar", 20, 0, 0)
+                .unwrap()
+                .max_length(),
+            Some(20)
+        );
+        assert_eq!(
+            SqlType::from_tsql_name("numeric(10)", 0, 0, 0)
+                .unwrap()
+                .to_tsql_name(),
+            "decimal(10, 0)"
+        );
+        assert_eq!(
+            SqlType::from_tsql_name("float(24)", 0, 0, 0)
+                .unwrap()
+                .to_tsql_name(),
+            "real"
+        );
+        assert!(SqlType::from_tsql_name("int(4)", 0, 0, 0).is_err());
+        assert!(SqlType::from_tsql_name("nvarchar(20000)", 0, 0, 0).is_err());
+    }
+
+    #[test]
+    fn parse_fixed_column() {
+        let schema = Schema::new(vec![
+            column(1, "id", SqlType::Int),
+            ColumnType {
+                computed: true,
+                ..column(2, "doubled", SqlType::Int)
+            },
+            column(3, "name", SqlType::NVarChar(None)),
+            column(4, "count", SqlType::SmallInt),
+        ]);
+
+        let pp = page(vec![record(&[7, 0, 0, 0, 3, 0], 3, &[b"a\0"])]);
+        let page = pp.get(PTR).unwrap();
+        let record = page.record(0).unwrap();
+
+        let value = |idx| schema.parse_fixed_column(&record, idx);
+        assert_eq!(value(0).unwrap().and_then(|v| v.as_i32()), Some(7));
+        assert_eq!(value(3).unwrap().and_then(|v| v.as_i32()), Some(3));
+        assert!(matches!(value(1), Err(MdfError::InvalidColumn(_))));
+        assert!(matches!(value(2), Err(MdfError::InvalidColumn(_))));
+        assert!(matches!(value(4), Err(MdfError::InvalidColumn(_))));
+    }
+}
@@ -1,3 +1,6 @@
+use log::warn;
+use std::convert::TryInto;
+
 /*
 pub(crate) fn parse_utf16_string(data: &[u8]) -> String {
     String::from_utf16(
@@ -10,7 +13,65 @@ pub(crate) fn parse_utf16_string(data: &[u8]) -> String {
 }
  */
 
+// Invalid code units (e.g. unpaired surrogates) are replaced with U+FFFD,
+// a trailing odd byte is dropped
 pub(crate) fn parse_utf16_string(data: &[u8]) -> String {
-    let (s, _, _) = encoding_rs::UTF_16LE.decode(data);
-    s.into_owned()
+    decode_utf16(data).0
+}
+
+// Like `parse_utf16_string`, but also returns whether invalid code units were replaced
+pub(crate) fn decode_utf16(data: &[u8]) -> (String, bool) {
+    let data = if !data.len().is_multiple_of(2) {
+        warn!(
+            "utf16 string has odd length {}, dropping trailing byte",
+            data.len()
+        );
+        &data[..data.len() - 1]
+    } else {
+        data
+    };
+    // no BOM sniffing, a leading U+FEFF is part of the stored value
+    let (s, had_errors) = encoding_rs::UTF_16LE.decode_without_bom_handling(data);
+    (s.into_owned(), had_errors)
+}
+
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 32 bytes per line, with offsets and ASCII
+pub(crate) fn hex_dump(data: &[u8]) -> String {
+    let cfg = pretty_hex::HexConfig {
+        width: 32,
+        group: 0,
+        ..pretty_hex::HexConfig::default()
+    };
+    pretty_hex::config_hex(&data, cfg)
+}
+
+// bounds checked little endian reads of the integer starting at `offset`
+pub(crate) fn read_u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub(crate) fn read_u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+// bounds checked slicing
+pub(crate) fn slice<R: std::slice::SliceIndex<[u8]>>(
+    data: &[u8],
+    range: R,
+) -> Result<&R::Output, crate::MdfError> {
+    data.get(range).ok_or(crate::MdfError::UnexpectedEnd)
 }